@@ -0,0 +1,229 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// Rotate the log once it grows past this size
+const MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Rotated files kept next to the active one (audit.1.jsonl … audit.N.jsonl)
+const KEEP_ROTATED: usize = 5;
+
+/// Longest command text stored per entry
+const MAX_COMMAND_CHARS: usize = 500;
+
+/// Serializes appends and rotation within this process
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// What an audit entry records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A text command or message and the permission decision for it
+    Command,
+    /// An inline keyboard button press and the permission decision for it
+    Callback,
+    /// A finished `!` shell command
+    Shell,
+    /// A finished AI run
+    AiRun,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Local time, RFC 3339
+    pub time: String,
+    pub event: AuditEvent,
+    pub user_id: u64,
+    pub chat_id: i64,
+    pub command: String,
+    /// `CommandRisk` of the command, for Command/Callback events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risk: Option<String>,
+    /// Whether `can_execute` let it through
+    pub allowed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// How an AI run ended: "done", "error" or "stopped"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files_touched: Vec<String>,
+    /// Correlation id of an AI run, as shown in logs and error replies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+}
+
+impl AuditEntry {
+    /// Entry stamped with the current time; the optional fields start empty
+    pub fn new(
+        event: AuditEvent,
+        user_id: u64,
+        chat_id: i64,
+        command: &str,
+        allowed: bool,
+    ) -> Self {
+        let command = match command.char_indices().nth(MAX_COMMAND_CHARS) {
+            Some((end, _)) => format!("{}…", &command[..end]),
+            None => command.to_string(),
+        };
+        AuditEntry {
+            time: chrono::Local::now().to_rfc3339(),
+            event,
+            user_id,
+            chat_id,
+            command,
+            risk: None,
+            allowed,
+            working_dir: None,
+            exit_code: None,
+            outcome: None,
+            duration_ms: None,
+            files_touched: Vec::new(),
+            run_id: None,
+        }
+    }
+}
+
+/// Audit log directory: ~/<app_dir>/audit
+fn audit_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|h| h.join(crate::app::dir_name()).join("audit"))
+}
+
+fn rotated_path(dir: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        dir.join("audit.jsonl")
+    } else {
+        dir.join(format!("audit.{}.jsonl", n))
+    }
+}
+
+/// Shift audit.jsonl -> audit.1.jsonl -> … dropping the oldest
+fn rotate(dir: &Path) {
+    let _ = fs::remove_file(rotated_path(dir, KEEP_ROTATED));
+    for n in (0..KEEP_ROTATED).rev() {
+        let _ = fs::rename(rotated_path(dir, n), rotated_path(dir, n + 1));
+    }
+}
+
+/// Append `entry` to the log in `dir`, rotating first if it exceeds `max_bytes`
+fn append_in(dir: &Path, entry: &AuditEntry, max_bytes: u64) -> Result<(), String> {
+    let line = serde_json::to_string(entry).map_err(|e| format!("serialize: {}", e))?;
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let path = rotated_path(dir, 0);
+    if fs::metadata(&path).is_ok_and(|m| m.len() >= max_bytes) {
+        rotate(dir);
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    writeln!(file, "{}", line).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Append an entry to the audit log. Failures are logged as warnings via tracing, never fatal.
+pub fn record(entry: &AuditEntry) {
+    let result = match audit_dir() {
+        Some(dir) => append_in(&dir, entry, MAX_LOG_BYTES),
+        None => Err("no home directory".to_string()),
+    };
+    if let Err(e) = result {
+        tracing::warn!("audit log write failed: {e}");
+    }
+}
+
+/// The newest `count` entries in `dir`, oldest first, reading into rotated files as needed
+fn recent_in(dir: &Path, count: usize) -> Vec<AuditEntry> {
+    let mut newest_first: Vec<AuditEntry> = Vec::new();
+    for n in 0..=KEEP_ROTATED {
+        let Ok(content) = fs::read_to_string(rotated_path(dir, n)) else {
+            continue;
+        };
+        let remaining = count - newest_first.len();
+        newest_first.extend(
+            content
+                .lines()
+                .rev()
+                .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+                .take(remaining),
+        );
+        if newest_first.len() >= count {
+            break;
+        }
+    }
+    newest_first.reverse();
+    newest_first
+}
+
+/// The newest `count` audit entries, oldest first
+pub fn recent(count: usize) -> Vec<AuditEntry> {
+    audit_dir()
+        .map(|dir| recent_in(&dir, count))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("openclaude-audit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_append_and_read_recent() {
+        let dir = temp_dir("recent");
+        for i in 0..5 {
+            let mut entry =
+                AuditEntry::new(AuditEvent::Shell, 7, -100, &format!("!echo {i}"), true);
+            entry.exit_code = Some(i);
+            append_in(&dir, &entry, MAX_LOG_BYTES).expect("append");
+        }
+        let recent = recent_in(&dir, 3);
+        let _ = fs::remove_dir_all(&dir);
+
+        let commands: Vec<&str> = recent.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, vec!["!echo 2", "!echo 3", "!echo 4"]);
+        assert_eq!(recent[2].exit_code, Some(4));
+        assert_eq!(recent[2].event, AuditEvent::Shell);
+    }
+
+    #[test]
+    fn test_rotation_keeps_history_readable() {
+        let dir = temp_dir("rotate");
+        // Tiny limit: every append after the first rotates
+        for i in 0..(KEEP_ROTATED + 3) {
+            let entry = AuditEntry::new(AuditEvent::Command, 1, 1, &format!("/cmd{i}"), true);
+            append_in(&dir, &entry, 1).expect("append");
+        }
+        let files = fs::read_dir(&dir).expect("read dir").count();
+        let recent = recent_in(&dir, 100);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(files, KEEP_ROTATED + 1);
+        assert_eq!(recent.len(), KEEP_ROTATED + 1);
+        assert_eq!(
+            recent.last().map(|e| e.command.as_str()),
+            Some(format!("/cmd{}", KEEP_ROTATED + 2).as_str())
+        );
+    }
+
+    #[test]
+    fn test_long_command_truncated() {
+        let entry = AuditEntry::new(AuditEvent::Command, 1, 1, &"가".repeat(600), true);
+        assert_eq!(entry.command.chars().count(), MAX_COMMAND_CHARS + 1);
+    }
+}
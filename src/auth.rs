@@ -1,6 +1,5 @@
 /// Permission levels for users interacting with the bot.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
 pub enum PermissionLevel {
     Owner,
     Public,
@@ -41,8 +40,26 @@ pub fn classify_command(cmd: &str) -> CommandRisk {
         // /cd is elevated (sandbox enforcement happens at a higher level)
         "/cd" => CommandRisk::Elevated,
 
-        // /down: elevated for relative paths, dangerous for absolute paths
-        "/down" => {
+        // File browser: its buttons change directory and download files
+        "/ls" | "/browse" => CommandRisk::Elevated,
+
+        // Git: /diff shows file contents, /log authors and emails, /commit changes history
+        "/diff" | "/log" | "/commit" => CommandRisk::Elevated,
+
+        // Checkpoints: restoring rewrites files in the session directory
+        "/undo" | "/checkpoints" => CommandRisk::Elevated,
+
+        // Thinking display and budget: per-chat preferences
+        "/thinking" => CommandRisk::Elevated,
+
+        // Model, turn limit and effort: per-chat preferences that change what runs cost
+        "/model" | "/maxturns" | "/effort" => CommandRisk::Elevated,
+
+        // Chat instructions: change how the AI behaves for everyone in the chat
+        "/systemprompt" => CommandRisk::Elevated,
+
+        // /down, /cat: elevated for relative paths, dangerous for absolute paths
+        "/down" | "/cat" => {
             let arg = cmd.split_whitespace().nth(1).unwrap_or("");
             if arg.starts_with('/') || arg.starts_with("..") {
                 CommandRisk::Dangerous
@@ -51,8 +68,19 @@ pub fn classify_command(cmd: &str) -> CommandRisk {
             }
         }
 
+        // Audit log: shows every user's commands and working directories
+        "/audit" => CommandRisk::Dangerous,
+
+        // Run trace: raw tool output, including files outside the session directory
+        "/trace" => CommandRisk::Dangerous,
+
+        // MCP servers run arbitrary commands; extra directories widen the AI's reach
+        "/mcp" | "/adddir" => CommandRisk::Dangerous,
+
         // Dangerous: security / access control changes
-        "/allowed" | "/public" | "/uploadlimit" | "/mount" => CommandRisk::Dangerous,
+        "/allowed" | "/public" | "/publictools" | "/redact" | "/uploadlimit" | "/mount" => {
+            CommandRisk::Dangerous
+        }
 
         // Plain text messages (no leading slash or !) are safe
         _ if !cmd.starts_with('/') => CommandRisk::Safe,
@@ -62,6 +90,21 @@ pub fn classify_command(cmd: &str) -> CommandRisk {
     }
 }
 
+/// Determine a user's role in a chat.
+///
+/// - Owner: the registered owner, in any chat
+/// - Public: a non-owner in a group chat with public access enabled
+/// - Restricted: anyone else
+pub fn permission_level(user_is_owner: bool, is_public: bool) -> PermissionLevel {
+    if user_is_owner {
+        PermissionLevel::Owner
+    } else if is_public {
+        PermissionLevel::Public
+    } else {
+        PermissionLevel::Restricted
+    }
+}
+
 /// Determine whether a user is allowed to execute a command.
 ///
 /// Rules:
@@ -119,10 +162,29 @@ mod tests {
         );
         assert_eq!(classify_command("/allowedtools"), CommandRisk::Elevated);
         assert_eq!(classify_command("/availabletools"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/ls"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/browse"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/diff"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/log 5"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/commit fix typo"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/undo"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/checkpoints"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/thinking summary"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/model opus"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/maxturns 20"), CommandRisk::Elevated);
+        assert_eq!(classify_command("/effort high"), CommandRisk::Elevated);
+        assert_eq!(
+            classify_command("/systemprompt show"),
+            CommandRisk::Elevated
+        );
         assert_eq!(
             classify_command("/down relative/path"),
             CommandRisk::Elevated
         );
+        assert_eq!(
+            classify_command("/cat src/main.rs 1:20"),
+            CommandRisk::Elevated
+        );
     }
 
     #[test]
@@ -134,8 +196,24 @@ mod tests {
             CommandRisk::Dangerous
         );
         assert_eq!(classify_command("/down ../escape"), CommandRisk::Dangerous);
+        assert_eq!(classify_command("/cat /etc/passwd"), CommandRisk::Dangerous);
         assert_eq!(classify_command("/allowed +tool"), CommandRisk::Dangerous);
+        assert_eq!(classify_command("/mcp list"), CommandRisk::Dangerous);
+        assert_eq!(
+            classify_command("/adddir ../shared"),
+            CommandRisk::Dangerous
+        );
         assert_eq!(classify_command("/public"), CommandRisk::Dangerous);
+        assert_eq!(
+            classify_command("/publictools +Bash"),
+            CommandRisk::Dangerous
+        );
+        assert_eq!(
+            classify_command("/redact +corp-\\d+"),
+            CommandRisk::Dangerous
+        );
+        assert_eq!(classify_command("/audit 50"), CommandRisk::Dangerous);
+        assert_eq!(classify_command("/trace"), CommandRisk::Dangerous);
         assert_eq!(classify_command("/uploadlimit 100"), CommandRisk::Dangerous);
         assert_eq!(classify_command("/mount add /path"), CommandRisk::Dangerous);
     }
@@ -162,6 +240,14 @@ mod tests {
         assert!(!can_execute(false, false, CommandRisk::Dangerous));
     }
 
+    #[test]
+    fn test_permission_level() {
+        assert_eq!(permission_level(true, false), PermissionLevel::Owner);
+        assert_eq!(permission_level(true, true), PermissionLevel::Owner);
+        assert_eq!(permission_level(false, true), PermissionLevel::Public);
+        assert_eq!(permission_level(false, false), PermissionLevel::Restricted);
+    }
+
     #[test]
     fn test_path_within_sandbox() {
         let tmp = std::env::temp_dir();
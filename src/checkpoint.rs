@@ -0,0 +1,346 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::git;
+use crate::snapshot::SKIP_DIRS;
+
+/// Prefix of checkpoints taken before an AI turn (as opposed to before a restore)
+pub const TURN_PREFIX: &str = "Turn: ";
+
+/// Prefix of checkpoints taken before a restore, followed by the restored id
+pub const RESTORE_PREFIX: &str = "Before restore of ";
+
+/// Checkpoints kept per project; older ones are dropped and their objects pruned
+pub const KEEP_COUNT: usize = 100;
+
+/// Checkpoints allowed beyond KEEP_COUNT before pruning, so gc does not run every turn
+const PRUNE_SLACK: usize = 20;
+
+/// Trees larger than this are not checkpointed automatically (e.g. a home directory)
+pub const AUTO_MAX_BYTES: u64 = 200 * 1024 * 1024;
+
+/// One saved state of a project directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Short commit hash in the checkpoint store
+    pub id: String,
+    /// Relative time, e.g. "5 minutes ago"
+    pub when: String,
+    pub label: String,
+}
+
+/// Checkpoint store for a project: ~/<app_dir>/checkpoints/<hash of project path>.
+/// Each store is a separate git directory used with the project as its work tree,
+/// so the project's own .git (if any) is never touched.
+pub fn store_dir(project: &str) -> Option<PathBuf> {
+    let mut hasher = Sha256::new();
+    hasher.update(project.as_bytes());
+    let key = hex::encode(&hasher.finalize()[..8]);
+    dirs::home_dir().map(|h| h.join(crate::app::dir_name()).join("checkpoints").join(key))
+}
+
+/// Run git against `store` with `project` as the work tree
+fn run_store_git(store: &Path, project: &str, args: &[&str]) -> Result<String, String> {
+    let git_dir = format!("--git-dir={}", store.display());
+    let work_tree = format!("--work-tree={}", project);
+    let mut full_args = vec![
+        git_dir.as_str(),
+        work_tree.as_str(),
+        "-c",
+        "user.name=openclaude",
+        "-c",
+        "user.email=openclaude@localhost",
+        "-c",
+        "core.autocrlf=false",
+    ];
+    full_args.extend_from_slice(args);
+    git::run_git_writable(project, &[store.display().to_string()], &full_args)
+}
+
+/// Create the store on first use, excluding the same directories as change summaries
+fn init_store(store: &Path, project: &str) -> Result<(), String> {
+    if store.join("HEAD").exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(store).map_err(|e| format!("Failed to create checkpoint dir: {}", e))?;
+    run_store_git(store, project, &["init", "--quiet"])?;
+    let info = store.join("info");
+    fs::create_dir_all(&info).map_err(|e| format!("Failed to create checkpoint dir: {}", e))?;
+    let exclude: String = SKIP_DIRS.iter().map(|d| format!("{}/\n", d)).collect();
+    fs::write(info.join("exclude"), exclude)
+        .map_err(|e| format!("Failed to write checkpoint excludes: {}", e))
+}
+
+/// Record the current state of `project` in `store` under `label`
+pub fn create_in(store: &Path, project: &str, label: &str) -> Result<Checkpoint, String> {
+    init_store(store, project)?;
+    run_store_git(store, project, &["add", "-A", "--ignore-errors", "."])?;
+    run_store_git(
+        store,
+        project,
+        &[
+            "commit",
+            "--quiet",
+            "--allow-empty",
+            "--no-verify",
+            "-m",
+            label,
+        ],
+    )?;
+    let created = list_in(store, project, 1)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| "Checkpoint was not recorded".to_string())?;
+    if let Err(e) = prune_in(store, project, KEEP_COUNT, PRUNE_SLACK) {
+        tracing::warn!("Checkpoint pruning failed: {}", e);
+    }
+    Ok(created)
+}
+
+/// Drop all but the newest `keep` checkpoints once there are more than `keep + slack`.
+/// The oldest kept commit is marked as a shallow boundary, which makes everything
+/// behind it unreachable for `git gc --prune`.
+fn prune_in(store: &Path, project: &str, keep: usize, slack: usize) -> Result<(), String> {
+    let count: usize = run_store_git(store, project, &["rev-list", "--count", "HEAD"])?
+        .trim()
+        .parse()
+        .map_err(|e| format!("Unexpected rev-list output: {}", e))?;
+    if count <= keep + slack {
+        return Ok(());
+    }
+    let skip = format!("--skip={}", keep - 1);
+    let oldest_kept = run_store_git(store, project, &["rev-list", "-n1", &skip, "HEAD"])?;
+    fs::write(store.join("shallow"), oldest_kept)
+        .map_err(|e| format!("Failed to write shallow file: {}", e))?;
+    run_store_git(
+        store,
+        project,
+        &["reflog", "expire", "--expire=now", "--all"],
+    )?;
+    run_store_git(store, project, &["gc", "--quiet", "--prune=now"])?;
+    Ok(())
+}
+
+/// Checkpoint /undo restores: the newest turn checkpoint not restored since it was taken.
+/// `list` is newest first, so each restore marker hides the earlier turns it restored,
+/// and repeated /undo steps further back.
+pub fn undo_target(list: &[Checkpoint]) -> Option<&Checkpoint> {
+    let mut restored: Vec<&str> = Vec::new();
+    for c in list {
+        if let Some(id) = c.label.strip_prefix(RESTORE_PREFIX) {
+            restored.push(id);
+        } else if c.label.starts_with(TURN_PREFIX)
+            && !restored
+                .iter()
+                .any(|id| id.starts_with(&c.id) || c.id.starts_with(id))
+        {
+            return Some(c);
+        }
+    }
+    None
+}
+
+/// Parse `git log` output in CHECKPOINT_LOG_FORMAT
+fn parse_checkpoints(output: &str) -> Vec<Checkpoint> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            Some(Checkpoint {
+                id: parts.next()?.to_string(),
+                when: parts.next()?.to_string(),
+                label: parts.next().unwrap_or("").to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Tab-separated short hash, relative date, label
+const CHECKPOINT_LOG_FORMAT: &str = "--pretty=format:%h%x09%ar%x09%s";
+
+/// Most recent `count` checkpoints, newest first
+pub fn list_in(store: &Path, project: &str, count: usize) -> Result<Vec<Checkpoint>, String> {
+    if !store.join("HEAD").exists() {
+        return Ok(Vec::new());
+    }
+    if run_store_git(
+        store,
+        project,
+        &["rev-parse", "--verify", "--quiet", "HEAD"],
+    )
+    .is_err()
+    {
+        return Ok(Vec::new());
+    }
+    let count_arg = format!("-n{}", count);
+    let output = run_store_git(
+        store,
+        project,
+        &["log", "--no-color", &count_arg, CHECKPOINT_LOG_FORMAT],
+    )?;
+    Ok(parse_checkpoints(&output))
+}
+
+/// Restore `project` to checkpoint `id`. The current state is saved as a new
+/// checkpoint first, so a restore can itself be undone from the list.
+/// Files created after the checkpoint are removed; ignored files are left alone.
+pub fn restore_in(store: &Path, project: &str, id: &str) -> Result<(), String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid checkpoint id: {}", id));
+    }
+    let commit = format!("{}^{{commit}}", id);
+    run_store_git(
+        store,
+        project,
+        &["rev-parse", "--verify", "--quiet", &commit],
+    )
+    .map_err(|_| format!("Checkpoint not found: {}", id))?;
+
+    create_in(store, project, &format!("{}{}", RESTORE_PREFIX, id))?;
+    // The index now mirrors the working tree, so read-tree -u deletes files
+    // that do not exist in the checkpoint and rewrites the rest
+    run_store_git(store, project, &["read-tree", "--reset", "-u", id])?;
+    Ok(())
+}
+
+/// Record the current state of `project` in its default store
+pub fn create(project: &str, label: &str) -> Result<Checkpoint, String> {
+    let store = store_dir(project).ok_or("Cannot determine home directory")?;
+    create_in(&store, project, label)
+}
+
+/// Most recent `count` checkpoints of `project`, newest first
+pub fn list(project: &str, count: usize) -> Result<Vec<Checkpoint>, String> {
+    let store = store_dir(project).ok_or("Cannot determine home directory")?;
+    list_in(&store, project, count)
+}
+
+/// Restore `project` to checkpoint `id` from its default store
+pub fn restore(project: &str, id: &str) -> Result<(), String> {
+    let store = store_dir(project).ok_or("Cannot determine home directory")?;
+    restore_in(&store, project, id)
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn temp_dirs(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!(
+            "openclaude-checkpoint-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&base);
+        let project = base.join("project");
+        fs::create_dir_all(&project).expect("create temp dir");
+        (project, base.join("store"))
+    }
+
+    #[test]
+    fn test_parse_checkpoints() {
+        let out = "abc1234\t2 minutes ago\tTurn: fix the build\ndef5678\t1 hour ago\tBefore restore of abc1234";
+        let parsed = parse_checkpoints(out);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].id, "abc1234");
+        assert_eq!(parsed[0].label, "Turn: fix the build");
+        assert_eq!(parsed[1].when, "1 hour ago");
+    }
+
+    #[test]
+    fn test_restore_reverts_edits_and_new_files() {
+        let (project, store) = temp_dirs("restore");
+        let project_str = project.to_string_lossy().to_string();
+        fs::write(project.join("a.txt"), "original").expect("write");
+        fs::write(project.join("b.txt"), "keep me").expect("write");
+
+        let checkpoint = create_in(&store, &project_str, "Turn: edit").expect("create");
+
+        fs::write(project.join("a.txt"), "changed").expect("write");
+        fs::remove_file(project.join("b.txt")).expect("remove");
+        fs::write(project.join("c.txt"), "new").expect("write");
+
+        restore_in(&store, &project_str, &checkpoint.id).expect("restore");
+        assert_eq!(
+            fs::read_to_string(project.join("a.txt")).expect("read"),
+            "original"
+        );
+        assert_eq!(
+            fs::read_to_string(project.join("b.txt")).expect("read"),
+            "keep me"
+        );
+        assert!(!project.join("c.txt").exists());
+
+        let list = list_in(&store, &project_str, 10).expect("list");
+        assert_eq!(list.len(), 2);
+        assert!(list[0].label.starts_with("Before restore of"));
+
+        if let Some(base) = project.parent() {
+            let _ = fs::remove_dir_all(base);
+        }
+    }
+
+    #[test]
+    fn test_undo_target_steps_back() {
+        let checkpoint = |id: &str, label: &str| Checkpoint {
+            id: id.to_string(),
+            when: String::new(),
+            label: label.to_string(),
+        };
+        let t1 = checkpoint("aaa1111", "Turn: one");
+        let t2 = checkpoint("bbb2222", "Turn: two");
+        let mut list = vec![t2.clone(), t1.clone()];
+        assert_eq!(undo_target(&list), Some(&t2));
+
+        list.insert(0, checkpoint("ccc3333", "Before restore of bbb2222"));
+        assert_eq!(undo_target(&list), Some(&t1));
+
+        list.insert(0, checkpoint("ddd4444", "Before restore of aaa1111"));
+        assert_eq!(undo_target(&list), None);
+
+        let t3 = checkpoint("eee5555", "Turn: three");
+        list.insert(0, t3.clone());
+        assert_eq!(undo_target(&list), Some(&t3));
+    }
+
+    #[test]
+    fn test_prune_keeps_newest() {
+        let (project, store) = temp_dirs("prune");
+        let project_str = project.to_string_lossy().to_string();
+        for n in 0..6 {
+            fs::write(project.join("a.txt"), n.to_string()).expect("write");
+            create_in(&store, &project_str, &format!("Turn: {}", n)).expect("create");
+        }
+        let oldest = list_in(&store, &project_str, 10).expect("list")[5]
+            .id
+            .clone();
+        prune_in(&store, &project_str, 3, 1).expect("prune");
+        assert!(run_store_git(&store, &project_str, &["cat-file", "-e", &oldest]).is_err());
+
+        let list = list_in(&store, &project_str, 10).expect("list");
+        let labels: Vec<&str> = list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["Turn: 5", "Turn: 4", "Turn: 3"]);
+        restore_in(&store, &project_str, &list[2].id).expect("restore");
+        assert_eq!(
+            fs::read_to_string(project.join("a.txt")).expect("read"),
+            "3"
+        );
+
+        if let Some(base) = project.parent() {
+            let _ = fs::remove_dir_all(base);
+        }
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_id() {
+        let (project, store) = temp_dirs("badid");
+        let project_str = project.to_string_lossy().to_string();
+        assert!(restore_in(&store, &project_str, "HEAD; rm").is_err());
+        if let Some(base) = project.parent() {
+            let _ = fs::remove_dir_all(base);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Secret variables removed from every child environment.
+/// `*` matches any run of characters.
+pub const DEFAULT_DENY: &[&str] = &[
+    "OPENCLAUDE_TELEGRAM_TOKEN",
+    "TELEGRAM_BOT_TOKEN",
+    "*_BOT_TOKEN",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "GITHUB_TOKEN",
+    "GH_TOKEN",
+    "GITLAB_TOKEN",
+    "NPM_TOKEN",
+    "*_PASSWORD",
+    "*_SECRET",
+    "*_SECRET_KEY",
+    "*_PRIVATE_KEY",
+];
+
+/// Variables kept in allow-list mode so shells and the Claude CLI still work
+const ESSENTIAL_VARS: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "LANG", "LC_*", "TERM", "TMPDIR", "TZ",
+];
+
+/// Per-project extra variables, one KEY=VALUE per line
+pub const PROJECT_ENV_FILE: &str = ".openclaude.env";
+
+/// Names a PROJECT_ENV_FILE may not set, on top of DEFAULT_DENY: anything that
+/// changes which code a child loads or runs (a cloned repo ships its own file)
+const PROJECT_DENY: &[&str] = &[
+    "LD_*",
+    "DYLD_*",
+    "NODE_OPTIONS",
+    "BASH_ENV",
+    "ENV",
+    "GIT_*",
+    "PATH",
+    "HOME",
+    "SHELL",
+];
+
+/// Which of the bot's environment variables reach Claude and shell children
+#[derive(Debug, Clone, Default)]
+pub struct EnvPolicy {
+    /// Extra name patterns to remove, on top of DEFAULT_DENY
+    pub deny: Vec<String>,
+    /// If set, only these name patterns (plus ESSENTIAL_VARS) are passed through
+    pub allow: Option<Vec<String>>,
+}
+
+struct ChildEnvConfig {
+    policy: EnvPolicy,
+    /// Values that must never appear in a child variable (the bot token)
+    secrets: Vec<String>,
+}
+
+static CHILD_ENV: OnceLock<ChildEnvConfig> = OnceLock::new();
+
+/// Set the policy and the bot token once at startup
+pub fn configure(policy: EnvPolicy, bot_token: &str) {
+    let _ = CHILD_ENV.set(ChildEnvConfig {
+        policy,
+        secrets: vec![bot_token.to_string()],
+    });
+}
+
+/// Match a variable name against a pattern where `*` matches any run of characters
+fn name_matches(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || !name.ends_with(last) || name.len() < first.len() + last.len() {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+fn contains_secret(value: &str, secrets: &[String]) -> bool {
+    secrets
+        .iter()
+        .any(|s| !s.is_empty() && value.contains(s.as_str()))
+}
+
+/// Apply `policy` to `vars`, dropping denied names and any value containing one of `secrets`
+pub fn filter_vars(
+    policy: &EnvPolicy,
+    secrets: &[String],
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<(String, String)> {
+    vars.into_iter()
+        .filter(|(name, value)| {
+            let denied = DEFAULT_DENY
+                .iter()
+                .copied()
+                .chain(policy.deny.iter().map(String::as_str))
+                .any(|p| name_matches(p, name));
+            let allowed = match &policy.allow {
+                Some(allow) => ESSENTIAL_VARS
+                    .iter()
+                    .copied()
+                    .chain(allow.iter().map(String::as_str))
+                    .any(|p| name_matches(p, name)),
+                None => true,
+            };
+            !denied && allowed && !contains_secret(value, secrets)
+        })
+        .collect()
+}
+
+/// Parse a PROJECT_ENV_FILE: KEY=VALUE lines, `#` comments, optional `export ` and quotes
+pub fn parse_env_file(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim();
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return None;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(value);
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Keep the PROJECT_ENV_FILE entries a project may set: no PROJECT_DENY or
+/// DEFAULT_DENY names and no value containing one of `secrets`
+fn project_vars(parsed: Vec<(String, String)>, secrets: &[String]) -> Vec<(String, String)> {
+    parsed
+        .into_iter()
+        .filter(|(name, value)| {
+            let denied = PROJECT_DENY
+                .iter()
+                .chain(DEFAULT_DENY)
+                .any(|p| name_matches(p, name));
+            if denied {
+                tracing::warn!("{PROJECT_ENV_FILE}: ignoring {name}");
+            }
+            !denied && !contains_secret(value, secrets)
+        })
+        .collect()
+}
+
+fn config() -> &'static ChildEnvConfig {
+    CHILD_ENV.get_or_init(|| ChildEnvConfig {
+        policy: EnvPolicy::default(),
+        secrets: Vec::new(),
+    })
+}
+
+/// The bot's own environment filtered by the configured policy, without any
+/// project variables. Callers should `env_clear()` first. Variables with non-UTF-8
+/// names or values are dropped.
+pub fn inherited_vars() -> Vec<(String, String)> {
+    let config = config();
+    let inherited = std::env::vars_os()
+        .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)));
+    filter_vars(&config.policy, &config.secrets, inherited)
+}
+
+/// Environment for a child process started in `working_dir`: `inherited_vars()`
+/// plus the project's PROJECT_ENV_FILE if present, minus the names in PROJECT_DENY.
+/// Callers should `env_clear()` first.
+pub fn vars_for(working_dir: &Path) -> Vec<(String, String)> {
+    let mut vars = inherited_vars();
+    if let Ok(content) = fs::read_to_string(working_dir.join(PROJECT_ENV_FILE)) {
+        for (key, value) in project_vars(parse_env_file(&content), &config().secrets) {
+            vars.retain(|(k, _)| *k != key);
+            vars.push((key, value));
+        }
+    }
+    vars
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("PATH", "PATH"));
+        assert!(!name_matches("PATH", "MANPATH"));
+        assert!(name_matches("*_PASSWORD", "DB_PASSWORD"));
+        assert!(!name_matches("*_PASSWORD", "PASSWORD"));
+        assert!(name_matches("LC_*", "LC_ALL"));
+        assert!(name_matches("A*B*C", "AxxBxxC"));
+        assert!(!name_matches("A*B*C", "AxxC"));
+    }
+
+    #[test]
+    fn test_filter_default_deny() {
+        let out = filter_vars(
+            &EnvPolicy::default(),
+            &[],
+            vars(&[
+                ("PATH", "/usr/bin"),
+                ("TELEGRAM_BOT_TOKEN", "x"),
+                ("DB_PASSWORD", "y"),
+                ("EDITOR", "vi"),
+            ]),
+        );
+        assert_eq!(out, vars(&[("PATH", "/usr/bin"), ("EDITOR", "vi")]));
+    }
+
+    #[test]
+    fn test_filter_allow_list() {
+        let policy = EnvPolicy {
+            deny: Vec::new(),
+            allow: Some(vec!["ANTHROPIC_*".to_string()]),
+        };
+        let out = filter_vars(
+            &policy,
+            &[],
+            vars(&[
+                ("PATH", "/usr/bin"),
+                ("ANTHROPIC_API_KEY", "k"),
+                ("EDITOR", "vi"),
+            ]),
+        );
+        assert_eq!(
+            out,
+            vars(&[("PATH", "/usr/bin"), ("ANTHROPIC_API_KEY", "k")])
+        );
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let parsed = parse_env_file(
+            "# comment\nexport FOO=bar\nQUOTED=\"a b\"\nSINGLE='c'\nbad line\n1-X=no\n",
+        );
+        assert_eq!(
+            parsed,
+            vars(&[("FOO", "bar"), ("QUOTED", "a b"), ("SINGLE", "c")])
+        );
+    }
+
+    #[test]
+    fn test_project_vars_drop_loader_and_git_names() {
+        let parsed = parse_env_file(
+            "LD_PRELOAD=/tmp/evil.so\nGIT_EXTERNAL_DIFF=/tmp/evil.sh\nDB_PASSWORD=x\nAPI_URL=http://localhost\n",
+        );
+        assert_eq!(
+            project_vars(parsed, &[]),
+            vars(&[("API_URL", "http://localhost")])
+        );
+    }
+
+    #[test]
+    fn test_token_never_reaches_child() {
+        let token = "123456:AAFakeBotTokenForTests".to_string();
+        let env = vars(&[
+            ("PATH", "/usr/bin:/bin"),
+            ("OPENCLAUDE_TELEGRAM_TOKEN", &token),
+            ("TELEGRAM_BOT_TOKEN", &token),
+            ("RENAMED_COPY", &token),
+            (
+                "EMBEDDED",
+                &format!("https://api.telegram.org/bot{}/", token),
+            ),
+            ("HARMLESS", "ok"),
+        ]);
+        let filtered = filter_vars(&EnvPolicy::default(), std::slice::from_ref(&token), env);
+
+        let output = std::process::Command::new("sh")
+            .args(["-c", "env"])
+            .env_clear()
+            .envs(filtered)
+            .output()
+            .expect("run sh");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("HARMLESS=ok"));
+        assert!(!stdout.contains(&token));
+        assert!(!stdout.contains("TELEGRAM_BOT_TOKEN"));
+    }
+}
@@ -1,12 +1,17 @@
-use std::fs::OpenOptions;
 use std::io::{BufRead, BufReader, Read, Write};
-use std::process::{Command, Stdio};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
 use std::sync::mpsc::{self, Sender};
 use std::sync::OnceLock;
 
 use regex::Regex;
 use serde_json::Value;
 
+use crate::child_env;
+use crate::confine;
+use crate::limits;
+use crate::trace::RunTrace;
+
 /// Cached path to selected AI binary.
 static AI_BINARY_PATH: OnceLock<Option<String>> = OnceLock::new();
 
@@ -25,7 +30,7 @@ fn execution_options() -> &'static ExecutionOptions {
     EXECUTION_OPTIONS.get_or_init(ExecutionOptions::default)
 }
 
-fn ai_binary_name() -> &'static str {
+pub(crate) fn ai_binary_name() -> &'static str {
     "claude"
 }
 
@@ -59,41 +64,12 @@ fn resolve_ai_binary_path() -> Option<String> {
     None
 }
 
-fn get_ai_binary_path() -> Option<&'static str> {
+pub(crate) fn get_ai_binary_path() -> Option<&'static str> {
     AI_BINARY_PATH
         .get_or_init(resolve_ai_binary_path)
         .as_deref()
 }
 
-/// Debug logging helper (active only when COKACDIR_DEBUG=1)
-fn debug_log(msg: &str) {
-    static ENABLED: OnceLock<bool> = OnceLock::new();
-    let enabled = ENABLED.get_or_init(|| {
-        std::env::var("COKACDIR_DEBUG")
-            .map(|v| v == "1")
-            .unwrap_or(false)
-    });
-    if !*enabled {
-        return;
-    }
-
-    let Some(home) = dirs::home_dir() else {
-        return;
-    };
-
-    let log_path = home
-        .join(crate::app::dir_name())
-        .join("debug")
-        .join("claude.log");
-    if let Some(parent) = log_path.parent() {
-        let _ = std::fs::create_dir_all(parent);
-    }
-    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&log_path) {
-        let timestamp = chrono::Local::now().format("%H:%M:%S%.3f");
-        let _ = writeln!(file, "[{}] {}", timestamp, msg);
-    }
-}
-
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct CodexResponse {
@@ -103,19 +79,93 @@ pub struct CodexResponse {
     pub error: Option<String>,
 }
 
+/// Progress of one entry in the AI's todo list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TodoStatus {
+    Pending,
+    InProgress,
+    Completed,
+}
+
+/// One entry of the AI's todo list (Claude's TodoWrite or TaskCreate/TaskUpdate tools,
+/// Codex's todo_list item)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoItem {
+    pub content: String,
+    pub status: TodoStatus,
+    /// Present-tense wording shown while the item is in progress
+    pub active_form: Option<String>,
+}
+
+/// Tools that stop to ask the user; their permission requests are forwarded to the chat
+pub const USER_INPUT_TOOLS: &[&str] = &["AskUserQuestion", "ExitPlanMode"];
+
+/// Answer to a tool permission request from the CLI
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolDecision {
+    /// Run the tool with this input (the original one, or with the user's answers added)
+    Allow(Value),
+    /// Refuse the tool call; the message is passed to the model as the reason
+    Deny(String),
+}
+
+/// An MCP server as reported by Claude's init event
+#[derive(Debug, Clone, PartialEq)]
+pub struct McpServerStatus {
+    pub name: String,
+    /// "connected", "failed", "pending", …
+    pub status: String,
+}
+
 /// Streaming message types for real-time Claude Code responses
 #[derive(Debug, Clone)]
 pub enum StreamMessage {
-    /// Initialization - contains thread/session ID
-    Init { session_id: String },
-    /// Text response chunk
-    Text { content: String },
-    /// Tool use started
-    ToolUse { name: String, input: String },
-    /// Tool execution result
-    ToolResult { content: String, is_error: bool },
-    /// Background task notification
-    #[allow(dead_code)]
+    /// Initialization - contains thread/session ID, and the model Claude runs
+    /// with the MCP servers it started
+    Init {
+        session_id: String,
+        model: Option<String>,
+        mcp_servers: Vec<McpServerStatus>,
+    },
+    /// Text response chunk. `parent` (here and on tool events) is the id of the
+    /// Task tool use whose sub-agent produced it; None for the main agent
+    Text {
+        content: String,
+        parent: Option<String>,
+    },
+    /// The model's extended thinking, streamed before the text it leads to
+    Thinking {
+        content: String,
+        parent: Option<String>,
+    },
+    /// Tool use started; `id` pairs it with its ToolResult
+    ToolUse {
+        id: Option<String>,
+        name: String,
+        input: String,
+        parent: Option<String>,
+    },
+    /// Tool execution result for the ToolUse with the same id
+    ToolResult {
+        tool_use_id: Option<String>,
+        content: String,
+        is_error: bool,
+        parent: Option<String>,
+    },
+    /// The complete, current todo list; replaces any earlier one.
+    /// `id` is the TodoWrite/TaskCreate/TaskUpdate tool use, whose result carries nothing to show
+    Todos {
+        id: Option<String>,
+        items: Vec<TodoItem>,
+    },
+    /// A USER_INPUT_TOOLS call waiting for the user. The run is paused until
+    /// a decision is sent through `reply` (or the run is cancelled).
+    AwaitingUser {
+        tool_name: String,
+        input: Value,
+        reply: Sender<ToolDecision>,
+    },
+    /// A background task (e.g. a backgrounded shell command) finished
     TaskNotification {
         task_id: String,
         status: String,
@@ -178,9 +228,126 @@ pub const DEFAULT_ALLOWED_TOOLS: &[&str] = &[
     "TaskGet",
     "TaskUpdate",
     "TaskList",
+    "AskUserQuestion",
+    "EnterPlanMode",
+    "ExitPlanMode",
 ];
 
-fn default_system_prompt() -> &'static str {
+/// Default tools for prompts from non-owner members of public group chats (read-only).
+pub const DEFAULT_PUBLIC_TOOLS: &[&str] = &["Read", "Glob", "Grep"];
+
+/// CLI tools that are not offered in the Telegram tool list. Enforced runs
+/// disallow these along with every DEFAULT_ALLOWED_TOOLS entry left out.
+const UNLISTED_CLI_TOOLS: &[&str] = &[
+    "MultiEdit",
+    "NotebookRead",
+    "LS",
+    "TodoWrite",
+    "BashOutput",
+    "KillShell",
+    "SlashCommand",
+    "ListMcpResourcesTool",
+    "ReadMcpResourceTool",
+];
+
+/// Tools that can change files in the working directory
+pub const WRITE_TOOLS: &[&str] = &["Bash", "Edit", "MultiEdit", "Write", "NotebookEdit"];
+
+/// Tool restrictions for a single request
+#[derive(Debug, Clone, Default)]
+pub struct ToolPolicy {
+    /// Tools listed in the prompt's TOOL CONSTRAINT section (advisory)
+    pub allowed: Option<Vec<String>>,
+    /// The only tools the CLI may use (non-owner runs). Such runs never skip
+    /// permissions: these go to --allowedTools and every other known tool to
+    /// --disallowedTools, since tools like Read or Glob run in the default mode
+    /// without a permission request. Anything left is denied by
+    /// `answer_control_request`.
+    pub enforced: Option<Vec<String>>,
+}
+
+impl ToolPolicy {
+    /// Whether the run may change files: always, unless an enforced list leaves out
+    /// every WRITE_TOOLS entry
+    pub fn can_write(&self) -> bool {
+        self.enforced
+            .as_ref()
+            .is_none_or(|tools| tools.iter().any(|t| WRITE_TOOLS.contains(&t.as_str())))
+    }
+}
+
+/// Model aliases accepted by /model and the `;alias:` prompt prefix. Full
+/// model ids (`claude-…`) are accepted as well.
+pub const KNOWN_MODELS: &[&str] = &["opus", "sonnet", "haiku", "opusplan"];
+
+/// Most turns /maxturns allows
+pub const MAX_TURNS_LIMIT: u32 = 200;
+
+/// Effort levels accepted by /effort and the thinking budget (MAX_THINKING_TOKENS)
+/// each one sets. A one-shot /thinking budget takes precedence.
+pub const EFFORT_LEVELS: &[(&str, u32)] = &[("low", 4_000), ("medium", 16_000), ("high", 32_000)];
+
+/// Thinking budget of an EFFORT_LEVELS entry
+pub fn effort_budget(level: &str) -> Option<u32> {
+    EFFORT_LEVELS
+        .iter()
+        .find(|(name, _)| *name == level)
+        .map(|(_, tokens)| *tokens)
+}
+
+/// Whether `name` is a model alias from KNOWN_MODELS or a full Claude model id
+pub fn is_known_model(name: &str) -> bool {
+    KNOWN_MODELS.contains(&name)
+        || name.strip_prefix("claude-").is_some_and(|rest| {
+            !rest.is_empty()
+                && rest
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+        })
+}
+
+/// Split a one-shot model override off a prompt: `opus: refactor this`
+/// gives ("opus", "refactor this"). None unless the prefix is a known model.
+pub fn split_model_override(prompt: &str) -> Option<(&str, &str)> {
+    let (name, rest) = prompt.split_once(':')?;
+    let name = name.trim();
+    let rest = rest.trim();
+    (is_known_model(name) && !rest.is_empty()).then_some((name, rest))
+}
+
+/// Model choice of a run; None fields leave the CLI default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelOptions {
+    pub model: Option<String>,
+    /// Used when the main model is overloaded (--fallback-model)
+    pub fallback_model: Option<String>,
+    /// Agentic turns before the CLI stops (--max-turns)
+    pub max_turns: Option<u32>,
+}
+
+/// Extra access configured for the project (/mcp, /adddir)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProjectOptions {
+    /// MCP server config files (--mcp-config)
+    pub mcp_configs: Vec<String>,
+    /// Directories the CLI may use besides the working directory (--add-dir)
+    pub add_dirs: Vec<String>,
+}
+
+/// Per-request options of an AI run
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub tools: ToolPolicy,
+    pub model: ModelOptions,
+    pub project: ProjectOptions,
+    /// Extended thinking budget in tokens (MAX_THINKING_TOKENS); None leaves
+    /// the CLI default
+    pub thinking_budget: Option<u32>,
+    /// Image and document content blocks sent along with the prompt
+    pub attachments: Vec<Value>,
+}
+
+pub(crate) fn default_system_prompt() -> &'static str {
     r#"You are a terminal coding assistant running through Claude Code CLI.
 Be concise. Focus on practical, safe, non-interactive execution.
 Respond in the same language as the user.
@@ -201,11 +368,13 @@ BASH EXECUTION RULES (MUST FOLLOW):
 - NEVER use interactive flags like -i"#
 }
 
-fn build_full_prompt(
-    prompt: &str,
+/// Text for --append-system-prompt: `system_prompt` (the default one when
+/// None, nothing when empty) followed by the allowed tools. The user's prompt
+/// is sent on its own, so the CLI keeps its own system prompt first.
+pub(crate) fn build_system_prompt(
     system_prompt: Option<&str>,
     allowed_tools: Option<&[String]>,
-) -> String {
+) -> Option<String> {
     let mut sections: Vec<String> = Vec::new();
 
     let effective_system_prompt = match system_prompt {
@@ -215,7 +384,7 @@ fn build_full_prompt(
     };
 
     if let Some(sp) = effective_system_prompt {
-        sections.push(format!("SYSTEM:\n{}", sp));
+        sections.push(sp.to_string());
     }
 
     if let Some(tools) = allowed_tools {
@@ -227,25 +396,93 @@ fn build_full_prompt(
         }
     }
 
-    sections.push(prompt.to_string());
-    sections.join("\n\n")
+    (!sections.is_empty()).then(|| sections.join("\n\n"))
 }
 
-fn ai_args(session_id: Option<&str>) -> Result<Vec<String>, String> {
+pub(crate) fn ai_args(
+    session_id: Option<&str>,
+    enforced_tools: Option<&[String]>,
+    model: &ModelOptions,
+    project: &ProjectOptions,
+    system_prompt: Option<&str>,
+) -> Result<Vec<String>, String> {
     let mut args = vec![
         "-p".to_string(),
         "--output-format".to_string(),
         "stream-json".to_string(),
         "--verbose".to_string(),
+        // The prompt goes in as a stream-json message and stdin stays open, so
+        // permission requests can be answered with control responses
+        "--input-format".to_string(),
+        "stream-json".to_string(),
+        "--permission-prompt-tool".to_string(),
+        "stdio".to_string(),
     ];
 
-    if execution_options().madmax {
+    if execution_options().madmax && enforced_tools.is_none() {
         args.push("--dangerously-skip-permissions".to_string());
     } else {
         args.push("--permission-mode".to_string());
         args.push("default".to_string());
     }
 
+    // Tools that ask the user keep going through the permission prompt
+    let pre_approved: Vec<&str> = enforced_tools
+        .unwrap_or_default()
+        .iter()
+        .map(String::as_str)
+        .filter(|t| *t != "EnterPlanMode" && !USER_INPUT_TOOLS.contains(t))
+        .collect();
+    if !pre_approved.is_empty() {
+        args.push("--allowedTools".to_string());
+        args.push(pre_approved.join(","));
+    }
+    if let Some(enforced) = enforced_tools {
+        let disallowed: Vec<&str> = DEFAULT_ALLOWED_TOOLS
+            .iter()
+            .chain(UNLISTED_CLI_TOOLS)
+            .copied()
+            .filter(|t| !enforced.iter().any(|e| e == t))
+            .collect();
+        if !disallowed.is_empty() {
+            args.push("--disallowedTools".to_string());
+            args.push(disallowed.join(","));
+        }
+    }
+
+    if let Some(name) = &model.model {
+        args.push("--model".to_string());
+        args.push(name.clone());
+    }
+    // The CLI refuses a fallback equal to the main model
+    if let Some(fallback) = model
+        .fallback_model
+        .as_ref()
+        .filter(|f| model.model.as_ref() != Some(*f))
+    {
+        args.push("--fallback-model".to_string());
+        args.push(fallback.clone());
+    }
+    if let Some(turns) = model.max_turns {
+        args.push("--max-turns".to_string());
+        args.push(turns.to_string());
+    }
+
+    // Both flags take several values, ended by the next flag
+    if !project.mcp_configs.is_empty() {
+        args.push("--mcp-config".to_string());
+        args.extend(project.mcp_configs.iter().cloned());
+    }
+    if !project.add_dirs.is_empty() {
+        args.push("--add-dir".to_string());
+        args.extend(project.add_dirs.iter().cloned());
+    }
+
+    if let Some(text) = system_prompt {
+        args.push("--append-system-prompt".to_string());
+        args.push(text.to_string());
+    }
+
     if let Some(sid) = session_id {
         if !is_valid_session_id(sid) {
             return Err("Invalid session ID format".to_string());
@@ -267,13 +504,24 @@ pub fn execute_command(
 ) -> CodexResponse {
     let (tx, rx) = mpsc::channel();
 
+    let options = RunOptions {
+        tools: ToolPolicy {
+            allowed: allowed_tools.map(|t| t.to_vec()),
+            enforced: None,
+        },
+        model: ModelOptions::default(),
+        project: ProjectOptions::default(),
+        thinking_budget: None,
+        attachments: Vec::new(),
+    };
     let run_result = execute_command_streaming(
         prompt,
         session_id,
         working_dir,
         tx,
         None,
-        allowed_tools,
+        &options,
+        None,
         None,
     );
 
@@ -292,10 +540,13 @@ pub fn execute_command(
 
     for msg in rx {
         match msg {
-            StreamMessage::Init { session_id } => {
+            StreamMessage::Init { session_id, .. } => {
                 final_session_id = Some(session_id);
             }
-            StreamMessage::Text { content } => {
+            StreamMessage::Text {
+                content,
+                parent: None,
+            } => {
                 if !response.is_empty() {
                     response.push('\n');
                 }
@@ -312,8 +563,12 @@ pub fn execute_command(
             StreamMessage::Error { message } => {
                 saw_error = Some(message);
             }
-            StreamMessage::ToolUse { .. }
+            StreamMessage::Text { .. }
+            | StreamMessage::Thinking { .. }
+            | StreamMessage::ToolUse { .. }
             | StreamMessage::ToolResult { .. }
+            | StreamMessage::Todos { .. }
+            | StreamMessage::AwaitingUser { .. }
             | StreamMessage::TaskNotification { .. } => {}
         }
     }
@@ -359,21 +614,69 @@ pub fn is_ai_supported() -> bool {
     cfg!(unix)
 }
 
+/// Start the CLI with `args` in `working_dir`, with piped stdio, the filtered
+/// environment, resource limits and confinement applied; `add_dirs` are writable
+/// under confinement too. The returned cgroup scope must be kept until the
+/// process has exited.
+pub(crate) fn spawn_ai_process(
+    ai_bin: &str,
+    args: &[String],
+    working_dir: &str,
+    add_dirs: &[String],
+    thinking_budget: Option<u32>,
+) -> Result<(Child, Option<limits::CgroupScope>), String> {
+    let mut command = Command::new(ai_bin);
+    command
+        .args(args)
+        .current_dir(working_dir)
+        .env_clear()
+        .envs(child_env::vars_for(Path::new(working_dir)))
+        .env_remove("CLAUDECODE")
+        .envs(thinking_budget.map(|tokens| ("MAX_THINKING_TOKENS", tokens.to_string())))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let cgroup_scope = limits::apply(&mut command);
+    confine::apply(&mut command, Path::new(working_dir), add_dirs)?;
+    let child = command
+        .spawn()
+        .map_err(|e| format!("Failed to start {}: {}", ai_binary_name(), e))?;
+    Ok((child, cgroup_scope))
+}
+
+/// One stream-json user message line for the CLI's stdin. With attachments
+/// the content is a block list, the text coming last.
+pub(crate) fn user_message_line(text: &str, attachments: &[Value]) -> String {
+    let content = if attachments.is_empty() {
+        Value::from(text)
+    } else {
+        let mut blocks = attachments.to_vec();
+        blocks.push(serde_json::json!({ "type": "text", "text": text }));
+        Value::Array(blocks)
+    };
+    serde_json::json!({
+        "type": "user",
+        "message": { "role": "user", "content": content },
+    })
+    .to_string()
+}
+
 /// Execute a command using Claude Code CLI with streaming JSON output.
 /// If `system_prompt` is None, uses the default system prompt.
-/// If `system_prompt` is Some(""), no system prompt is prepended.
+/// If `system_prompt` is Some(""), no system prompt is appended.
+/// When `trace` is given, the raw stream, stderr and argv are captured into it.
+#[allow(clippy::too_many_arguments)]
 pub fn execute_command_streaming(
     prompt: &str,
     session_id: Option<&str>,
     working_dir: &str,
     sender: Sender<StreamMessage>,
     system_prompt: Option<&str>,
-    allowed_tools: Option<&[String]>,
+    options: &RunOptions,
     cancel_token: Option<std::sync::Arc<CancelToken>>,
+    mut trace: Option<&mut RunTrace>,
 ) -> Result<(), String> {
-    debug_log("========================================");
-    debug_log("=== execute_command_streaming START ===");
-    debug_log("========================================");
+    tracing::debug!("execute_command_streaming start");
 
     let binary_name = ai_binary_name();
     let ai_bin = get_ai_binary_path().ok_or_else(|| {
@@ -383,26 +686,38 @@ pub fn execute_command_streaming(
         )
     })?;
 
-    let full_prompt = build_full_prompt(prompt, system_prompt, allowed_tools);
+    let tools = &options.tools;
+    let system_prompt = build_system_prompt(system_prompt, tools.allowed.as_deref());
     let mut effective_session_id = session_id.map(String::from);
     let mut retried = false;
 
     loop {
-        let args = ai_args(effective_session_id.as_deref())?;
-
-        debug_log(&format!("Command: {}", ai_bin));
-        debug_log(&format!("Args: {:?}", args));
-        debug_log(&format!("Prompt length: {}", full_prompt.len()));
-
-        let mut child = Command::new(ai_bin)
-            .args(&args)
-            .current_dir(working_dir)
-            .env_remove("CLAUDECODE")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| format!("Failed to start {}: {}", binary_name, e))?;
+        let args = ai_args(
+            effective_session_id.as_deref(),
+            tools.enforced.as_deref(),
+            &options.model,
+            &options.project,
+            system_prompt.as_deref(),
+        )?;
+
+        tracing::debug!(
+            command = ai_bin,
+            ?args,
+            prompt_len = prompt.len(),
+            "Starting AI process"
+        );
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.argv(ai_bin, &args, working_dir);
+        }
+
+        // Kept alive until the process has exited so its OOM counter can be read
+        let (mut child, cgroup_scope) = spawn_ai_process(
+            ai_bin,
+            &args,
+            working_dir,
+            &options.project.add_dirs,
+            options.thinking_budget,
+        )?;
 
         if let Some(ref token) = cancel_token {
             if let Ok(mut guard) = token.child_pid.lock() {
@@ -410,9 +725,10 @@ pub fn execute_command_streaming(
             }
         }
 
-        if let Some(mut stdin) = child.stdin.take() {
-            stdin
-                .write_all(full_prompt.as_bytes())
+        // Closed once the result arrives, which lets the CLI exit
+        let mut stdin = child.stdin.take();
+        if let Some(stdin) = stdin.as_mut() {
+            writeln!(stdin, "{}", user_message_line(prompt, &options.attachments))
                 .map_err(|e| format!("Failed to write prompt to Claude stdin: {}", e))?;
         }
 
@@ -435,15 +751,17 @@ pub fn execute_command_streaming(
 
         let mut reader = BufReader::new(stdout);
         let mut line_buf = String::new();
-        let mut last_session_id: Option<String> = None;
-        let mut done_sent = false;
+        let mut decoder = StreamDecoder::default();
 
         loop {
             if let Some(ref token) = cancel_token {
                 if token.cancelled.load(std::sync::atomic::Ordering::Relaxed) {
-                    debug_log("Cancel detected — killing AI process");
+                    tracing::debug!("Cancel detected — killing AI process");
                     let _ = child.kill();
                     let _ = child.wait();
+                    if let Some(trace) = trace.as_deref_mut() {
+                        trace.cancelled();
+                    }
                     return Ok(());
                 }
             }
@@ -462,36 +780,35 @@ pub fn execute_command_streaming(
                 continue;
             }
 
-            debug_log(&format!("line: {}", line));
+            tracing::trace!(line, "stream line");
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.stdout_line(line);
+            }
 
             let Ok(json) = serde_json::from_str::<Value>(line) else {
                 continue;
             };
 
-            let parsed = parse_claude_stream_line(&json);
-            for mut msg in parsed {
-                match &mut msg {
-                    StreamMessage::Init { session_id } => {
-                        last_session_id = Some(session_id.clone());
+            match json.get("type").and_then(|v| v.as_str()) {
+                Some("control_request") => {
+                    let response = answer_control_request(&json, &sender, cancel_token.as_deref());
+                    if let Some(trace) = trace.as_deref_mut() {
+                        trace.control_response(&response);
                     }
-                    StreamMessage::Done {
-                        session_id,
-                        result: _,
-                    } => {
-                        if session_id.is_none() {
-                            *session_id = last_session_id.clone();
+                    if let Some(stdin) = stdin.as_mut() {
+                        if let Err(e) = writeln!(stdin, "{}", response) {
+                            tracing::warn!("Failed to answer permission request: {e}");
                         }
-                        done_sent = true;
                     }
-                    StreamMessage::Text { .. }
-                    | StreamMessage::ToolUse { .. }
-                    | StreamMessage::ToolResult { .. }
-                    | StreamMessage::TaskNotification { .. }
-                    | StreamMessage::Error { .. } => {}
+                    continue;
                 }
+                Some("result") => stdin = None,
+                _ => {}
+            }
 
+            for msg in decoder.decode(&json) {
                 if sender.send(msg).is_err() {
-                    debug_log("Receiver dropped while streaming; stopping send loop");
+                    tracing::debug!("Receiver dropped while streaming; stopping send loop");
                     break;
                 }
             }
@@ -499,9 +816,12 @@ pub fn execute_command_streaming(
 
         if let Some(ref token) = cancel_token {
             if token.cancelled.load(std::sync::atomic::Ordering::Relaxed) {
-                debug_log("Cancel detected after stdout loop — killing AI process");
+                tracing::debug!("Cancel detected after stdout loop — killing AI process");
                 let _ = child.kill();
                 let _ = child.wait();
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.cancelled();
+                }
                 return Ok(());
             }
         }
@@ -511,44 +831,378 @@ pub fn execute_command_streaming(
             .map_err(|e| format!("Claude process wait failed: {}", e))?;
 
         let stderr_output = stderr_handle.join().unwrap_or_else(|_| "".to_string());
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.exit(&status, &stderr_output);
+        }
 
         if !status.success() {
             // Auto-retry once without --resume on stale session error
             if !retried && effective_session_id.is_some() {
                 let stderr_lower = stderr_output.to_lowercase();
                 if stderr_lower.contains("no conversation found") {
-                    debug_log("Stale session detected — retrying without --resume");
+                    tracing::debug!("Stale session detected — retrying without --resume");
                     effective_session_id = None;
                     retried = true;
                     continue;
                 }
             }
 
-            let message = if !stderr_output.trim().is_empty() {
-                stderr_output.trim().to_string()
-            } else {
-                format!("Claude exited with code {:?}", status.code())
-            };
+            let mut message = exit_error_message(status.code(), &stderr_output);
+            if let Some(reason) =
+                limits::describe_exit(&status, &stderr_output, cgroup_scope.as_ref())
+            {
+                message = format!("{}\n{}", reason, message);
+            }
             let _ = sender.send(StreamMessage::Error { message });
         }
 
-        if !done_sent {
-            let _ = sender.send(StreamMessage::Done {
-                result: String::new(),
-                session_id: last_session_id,
-            });
+        if let Some(done) = decoder.finish() {
+            let _ = sender.send(done);
         }
 
         break;
     }
 
-    debug_log("======================================");
-    debug_log("=== execute_command_streaming END ===");
-    debug_log("======================================");
+    tracing::debug!("execute_command_streaming end");
 
     Ok(())
 }
 
+/// Decide a `control_request` from the CLI and build the `control_response` line.
+/// Questions and plan approvals are forwarded to the chat, blocking until the
+/// user answers; other permission requests are refused, as in plain `-p` mode.
+pub(crate) fn answer_control_request(
+    request: &Value,
+    sender: &Sender<StreamMessage>,
+    cancel_token: Option<&CancelToken>,
+) -> Value {
+    let request_id = request.get("request_id").cloned().unwrap_or(Value::Null);
+    let body = request.get("request").unwrap_or(&Value::Null);
+    if body.get("subtype").and_then(|v| v.as_str()) != Some("can_use_tool") {
+        return serde_json::json!({
+            "type": "control_response",
+            "response": {
+                "subtype": "error",
+                "request_id": request_id,
+                "error": "Unsupported control request",
+            },
+        });
+    }
+
+    let tool_name = body
+        .get("tool_name")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_string();
+    let input = body.get("input").cloned().unwrap_or(Value::Null);
+    let decision = if tool_name == "EnterPlanMode" {
+        ToolDecision::Allow(input)
+    } else if USER_INPUT_TOOLS.contains(&tool_name.as_str()) {
+        tracing::debug!(tool = %tool_name, "Waiting for the user's answer");
+        let (reply, answer) = mpsc::channel();
+        let forwarded = sender.send(StreamMessage::AwaitingUser {
+            tool_name,
+            input,
+            reply,
+        });
+        match forwarded {
+            Ok(()) => wait_for_decision(&answer, cancel_token),
+            Err(_) => ToolDecision::Deny("The user is not available to answer.".to_string()),
+        }
+    } else {
+        ToolDecision::Deny(format!(
+            "Permission to use {} cannot be granted from Telegram.",
+            tool_name
+        ))
+    };
+
+    let response = match decision {
+        ToolDecision::Allow(updated_input) => {
+            serde_json::json!({ "behavior": "allow", "updatedInput": updated_input })
+        }
+        ToolDecision::Deny(message) => {
+            serde_json::json!({ "behavior": "deny", "message": message })
+        }
+    };
+    serde_json::json!({
+        "type": "control_response",
+        "response": {
+            "subtype": "success",
+            "request_id": request_id,
+            "response": response,
+        },
+    })
+}
+
+/// Block until the chat sends a decision, giving up if the run is cancelled
+/// or the chat side goes away
+fn wait_for_decision(
+    answer: &mpsc::Receiver<ToolDecision>,
+    cancel_token: Option<&CancelToken>,
+) -> ToolDecision {
+    loop {
+        match answer.recv_timeout(std::time::Duration::from_millis(500)) {
+            Ok(decision) => return decision,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let cancelled = cancel_token
+                    .is_some_and(|t| t.cancelled.load(std::sync::atomic::Ordering::Relaxed));
+                if cancelled {
+                    return ToolDecision::Deny("The run was stopped.".to_string());
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return ToolDecision::Deny("The user did not answer.".to_string());
+            }
+        }
+    }
+}
+
+/// Error text for a CLI process that exited unsuccessfully
+pub fn exit_error_message(code: Option<i32>, stderr: &str) -> String {
+    if !stderr.trim().is_empty() {
+        stderr.trim().to_string()
+    } else {
+        format!("Claude exited with code {:?}", code)
+    }
+}
+
+/// Turns parsed stream-json lines into StreamMessages for one CLI invocation,
+/// filling in the session id on Done and remembering whether Done was seen.
+/// It also builds the todo list from the main agent's TaskCreate/TaskUpdate calls.
+#[derive(Default)]
+pub struct StreamDecoder {
+    last_session_id: Option<String>,
+    done_sent: bool,
+    /// Tasks in creation order, keyed by task id; a TaskCreate waiting for its
+    /// result is keyed by its tool use id until the result names the task
+    tasks: Vec<(String, TodoItem)>,
+}
+
+impl StreamDecoder {
+    pub fn decode(&mut self, json: &Value) -> Vec<StreamMessage> {
+        let mut parsed = parse_claude_stream_line(json);
+        for msg in &mut parsed {
+            match msg {
+                StreamMessage::Init { session_id, .. } => {
+                    self.last_session_id = Some(session_id.clone());
+                }
+                StreamMessage::Done {
+                    session_id,
+                    result: _,
+                } => {
+                    if session_id.is_none() {
+                        *session_id = self.last_session_id.clone();
+                    }
+                    self.done_sent = true;
+                }
+                StreamMessage::Text { .. }
+                | StreamMessage::Thinking { .. }
+                | StreamMessage::ToolUse { .. }
+                | StreamMessage::ToolResult { .. }
+                | StreamMessage::Todos { .. }
+                | StreamMessage::AwaitingUser { .. }
+                | StreamMessage::TaskNotification { .. }
+                | StreamMessage::Error { .. } => {}
+            }
+        }
+        let mut out = Vec::with_capacity(parsed.len());
+        for msg in parsed {
+            out.extend(self.track_tasks(msg));
+        }
+        out
+    }
+
+    /// Replace the main agent's TaskCreate/TaskUpdate calls with the updated task list
+    fn track_tasks(&mut self, msg: StreamMessage) -> Vec<StreamMessage> {
+        match msg {
+            StreamMessage::ToolUse {
+                id: Some(id),
+                name,
+                input,
+                parent: None,
+            } if name == "TaskCreate" || name == "TaskUpdate" => {
+                let fields: Value = serde_json::from_str(&input).unwrap_or(Value::Null);
+                let text = |key: &str| fields.get(key).and_then(|v| v.as_str()).map(String::from);
+                if name == "TaskCreate" {
+                    let item = TodoItem {
+                        content: text("subject").unwrap_or_default(),
+                        status: TodoStatus::Pending,
+                        active_form: text("activeForm"),
+                    };
+                    self.tasks.push((id.clone(), item));
+                } else {
+                    let task_id = text("taskId").unwrap_or_default();
+                    let Some(pos) = self.tasks.iter().position(|(k, _)| *k == task_id) else {
+                        // A task from an earlier run; shown as a plain tool call
+                        return vec![StreamMessage::ToolUse {
+                            id: Some(id),
+                            name,
+                            input,
+                            parent: None,
+                        }];
+                    };
+                    let item = &mut self.tasks[pos].1;
+                    match text("status").as_deref() {
+                        Some("deleted") => {
+                            self.tasks.remove(pos);
+                        }
+                        status => {
+                            match status {
+                                Some("pending") => item.status = TodoStatus::Pending,
+                                Some("in_progress") => item.status = TodoStatus::InProgress,
+                                Some("completed") => item.status = TodoStatus::Completed,
+                                _ => {}
+                            }
+                            if let Some(subject) = text("subject") {
+                                item.content = subject;
+                            }
+                            if let Some(active_form) = text("activeForm") {
+                                item.active_form = Some(active_form);
+                            }
+                        }
+                    }
+                }
+                vec![StreamMessage::Todos {
+                    id: Some(id),
+                    items: self.task_items(),
+                }]
+            }
+            StreamMessage::ToolResult {
+                tool_use_id: Some(ref tool_use_id),
+                ref content,
+                is_error,
+                parent: None,
+            } if self.tasks.iter().any(|(k, _)| k == tool_use_id) => {
+                // "Task #3 created successfully: …"
+                let task_id = content
+                    .split_once('#')
+                    .map(|(_, rest)| rest.chars().take_while(char::is_ascii_digit).collect())
+                    .filter(|n: &String| !n.is_empty());
+                let pos = self.tasks.iter().position(|(k, _)| k == tool_use_id);
+                match (pos, task_id) {
+                    (Some(pos), _) if is_error => {
+                        self.tasks.remove(pos);
+                        let items = self.task_items();
+                        vec![msg, StreamMessage::Todos { id: None, items }]
+                    }
+                    (Some(pos), Some(task_id)) => {
+                        self.tasks[pos].0 = task_id;
+                        vec![msg]
+                    }
+                    _ => vec![msg],
+                }
+            }
+            other => vec![other],
+        }
+    }
+
+    fn task_items(&self) -> Vec<TodoItem> {
+        self.tasks.iter().map(|(_, item)| item.clone()).collect()
+    }
+
+    /// The closing Done message, if the stream ended without one
+    pub fn finish(self) -> Option<StreamMessage> {
+        (!self.done_sent).then_some(StreamMessage::Done {
+            result: String::new(),
+            session_id: self.last_session_id,
+        })
+    }
+}
+
+/// File path written by a file-modifying tool use (Write, Edit, MultiEdit, NotebookEdit),
+/// taken from its JSON input. Returns None for other tools.
+pub fn tool_file_path(name: &str, input: &str) -> Option<String> {
+    let key = match name {
+        "Write" | "Edit" | "MultiEdit" => "file_path",
+        "NotebookEdit" => "notebook_path",
+        _ => return None,
+    };
+    let v = serde_json::from_str::<Value>(input).ok()?;
+    v.get(key)
+        .and_then(|p| p.as_str())
+        .filter(|p| !p.is_empty())
+        .map(String::from)
+}
+
+/// Text of a tool_result `content`: either a plain string or a list of content
+/// blocks, whose text blocks are joined and other blocks (images) noted by type
+fn tool_result_text(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.trim_end().to_string(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|b| match b.get("type").and_then(|v| v.as_str()) {
+                Some("text") => b.get("text").and_then(|v| v.as_str()).map(String::from),
+                Some(other) => Some(format!("[{}]", other)),
+                None => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_end()
+            .to_string(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// The Task tool use a Claude event belongs to, when a sub-agent produced it
+fn parent_tool_use_id(json: &Value) -> Option<String> {
+    json.get("parent_tool_use_id")
+        .and_then(|v| v.as_str())
+        .map(String::from)
+}
+
+/// Items of a TodoWrite tool call, or None for any other tool
+fn claude_todos(tool_name: &str, input: Option<&Value>) -> Option<Vec<TodoItem>> {
+    if tool_name != "TodoWrite" {
+        return None;
+    }
+    let todos = input?.get("todos")?.as_array()?;
+    Some(
+        todos
+            .iter()
+            .map(|t| {
+                let text = |key: &str| t.get(key).and_then(|v| v.as_str()).map(String::from);
+                TodoItem {
+                    content: text("content").unwrap_or_default(),
+                    status: match t.get("status").and_then(|v| v.as_str()) {
+                        Some("in_progress") => TodoStatus::InProgress,
+                        Some("completed") => TodoStatus::Completed,
+                        _ => TodoStatus::Pending,
+                    },
+                    active_form: text("activeForm"),
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Todos of a Codex todo_list item, or None for any other item
+fn codex_todos(item: &Value) -> Option<StreamMessage> {
+    if item.get("type").and_then(|v| v.as_str()) != Some("todo_list") {
+        return None;
+    }
+    let items = item
+        .get("items")?
+        .as_array()?
+        .iter()
+        .map(|t| TodoItem {
+            content: t
+                .get("text")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string(),
+            status: if t.get("completed").and_then(|v| v.as_bool()) == Some(true) {
+                TodoStatus::Completed
+            } else {
+                TodoStatus::Pending
+            },
+            active_form: None,
+        })
+        .collect();
+    Some(StreamMessage::Todos { id: None, items })
+}
+
 /// Parse one Claude/Codex JSONL event line into zero or more StreamMessage values.
 fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
     let mut messages = Vec::new();
@@ -564,12 +1218,46 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
                 if let Some(session_id) = json.get("session_id").and_then(|v| v.as_str()) {
                     messages.push(StreamMessage::Init {
                         session_id: session_id.to_string(),
+                        model: json.get("model").and_then(|v| v.as_str()).map(String::from),
+                        mcp_servers: json
+                            .get("mcp_servers")
+                            .and_then(|v| v.as_array())
+                            .map(|servers| {
+                                servers
+                                    .iter()
+                                    .filter_map(|server| {
+                                        Some(McpServerStatus {
+                                            name: server.get("name")?.as_str()?.to_string(),
+                                            status: server
+                                                .get("status")
+                                                .and_then(|v| v.as_str())
+                                                .unwrap_or("unknown")
+                                                .to_string(),
+                                        })
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
                     });
                 }
+            } else if json.get("subtype").and_then(|v| v.as_str()) == Some("task_notification") {
+                // Background task completion
+                let field = |key: &str| {
+                    json.get(key)
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("")
+                        .to_string()
+                };
+                messages.push(StreamMessage::TaskNotification {
+                    task_id: field("task_id"),
+                    status: field("status"),
+                    summary: field("summary"),
+                });
             }
         }
         // Claude stream-json assistant event
         "assistant" => {
+            let parent = parent_tool_use_id(json);
             if let Some(content) = json
                 .get("message")
                 .and_then(|m| m.get("content"))
@@ -584,7 +1272,24 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
                                 .unwrap_or("")
                                 .to_string();
                             if !text.is_empty() {
-                                messages.push(StreamMessage::Text { content: text });
+                                messages.push(StreamMessage::Text {
+                                    content: text,
+                                    parent: parent.clone(),
+                                });
+                            }
+                        }
+                        Some("thinking") => {
+                            let thinking = block
+                                .get("thinking")
+                                .and_then(|v| v.as_str())
+                                .unwrap_or("")
+                                .trim()
+                                .to_string();
+                            if !thinking.is_empty() {
+                                messages.push(StreamMessage::Thinking {
+                                    content: thinking,
+                                    parent: parent.clone(),
+                                });
                             }
                         }
                         Some("tool_use") => {
@@ -603,13 +1308,66 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
                                     }
                                 })
                                 .unwrap_or_default();
-                            messages.push(StreamMessage::ToolUse { name, input });
+                            let id = block.get("id").and_then(|v| v.as_str()).map(String::from);
+                            // The pinned checklist follows the main agent's todo list only
+                            let todos = parent
+                                .is_none()
+                                .then(|| claude_todos(&name, block.get("input")))
+                                .flatten();
+                            match todos {
+                                Some(items) => messages.push(StreamMessage::Todos { id, items }),
+                                None => messages.push(StreamMessage::ToolUse {
+                                    id,
+                                    name,
+                                    input,
+                                    parent: parent.clone(),
+                                }),
+                            }
                         }
                         _ => {}
                     }
                 }
             }
         }
+        // Claude stream-json tool results, sent back to the model as a user turn
+        "user" => {
+            let parent = parent_tool_use_id(json);
+            if let Some(content) = json
+                .get("message")
+                .and_then(|m| m.get("content"))
+                .and_then(|v| v.as_array())
+            {
+                for block in content {
+                    if block.get("type").and_then(|v| v.as_str()) != Some("tool_result") {
+                        continue;
+                    }
+                    let tool_use_id = block
+                        .get("tool_use_id")
+                        .and_then(|v| v.as_str())
+                        .map(String::from);
+                    let is_error = block
+                        .get("is_error")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false);
+                    let content = block
+                        .get("content")
+                        .map(tool_result_text)
+                        .unwrap_or_default();
+                    // Tool failures arrive wrapped as <tool_use_error>…</tool_use_error>
+                    let content = content
+                        .strip_prefix("<tool_use_error>")
+                        .and_then(|c| c.strip_suffix("</tool_use_error>"))
+                        .map(String::from)
+                        .unwrap_or(content);
+                    messages.push(StreamMessage::ToolResult {
+                        tool_use_id,
+                        content,
+                        is_error,
+                        parent: parent.clone(),
+                    });
+                }
+            }
+        }
         // Claude stream-json final result event
         "result" => {
             let result_text = json
@@ -626,7 +1384,17 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
                 .and_then(|v| v.as_bool())
                 .unwrap_or(false);
 
-            if is_error {
+            if json.get("subtype").and_then(|v| v.as_str()) == Some("error_max_turns") {
+                // Keep the partial work; a follow-up prompt continues the session
+                let turns = json.get("num_turns").and_then(|v| v.as_u64()).unwrap_or(0);
+                messages.push(StreamMessage::Text {
+                    content: format!(
+                        "⚠️ Stopped at the turn limit ({} turns). Send a message to continue, or raise it with /maxturns.",
+                        turns
+                    ),
+                    parent: None,
+                });
+            } else if is_error {
                 let errors = json
                     .get("errors")
                     .and_then(|v| v.as_array())
@@ -659,13 +1427,23 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
             if let Some(thread_id) = json.get("thread_id").and_then(|v| v.as_str()) {
                 messages.push(StreamMessage::Init {
                     session_id: thread_id.to_string(),
+                    model: None,
+                    mcp_servers: Vec::new(),
                 });
             }
         }
+        // Codex stream-json todo list changes
+        "item.updated" => {
+            if let Some(todos) = json.get("item").and_then(codex_todos) {
+                messages.push(todos);
+            }
+        }
         // Codex stream-json tool start event
         "item.started" => {
             if let Some(item) = json.get("item") {
-                if item.get("type").and_then(|v| v.as_str()) == Some("command_execution") {
+                if let Some(todos) = codex_todos(item) {
+                    messages.push(todos);
+                } else if item.get("type").and_then(|v| v.as_str()) == Some("command_execution") {
                     let command = item
                         .get("command")
                         .and_then(|v| v.as_str())
@@ -673,8 +1451,10 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
                         .to_string();
                     if !command.is_empty() {
                         messages.push(StreamMessage::ToolUse {
+                            id: item.get("id").and_then(|v| v.as_str()).map(String::from),
                             name: "Bash".to_string(),
                             input: command,
+                            parent: None,
                         });
                     }
                 }
@@ -684,6 +1464,21 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
         "item.completed" => {
             if let Some(item) = json.get("item") {
                 match item.get("type").and_then(|v| v.as_str()) {
+                    Some("todo_list") => messages.extend(codex_todos(item)),
+                    Some("reasoning") => {
+                        let text = item
+                            .get("text")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("")
+                            .trim()
+                            .to_string();
+                        if !text.is_empty() {
+                            messages.push(StreamMessage::Thinking {
+                                content: text,
+                                parent: None,
+                            });
+                        }
+                    }
                     Some("agent_message") => {
                         let text = item
                             .get("text")
@@ -691,7 +1486,10 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
                             .unwrap_or("")
                             .to_string();
                         if !text.is_empty() {
-                            messages.push(StreamMessage::Text { content: text });
+                            messages.push(StreamMessage::Text {
+                                content: text,
+                                parent: None,
+                            });
                         }
                     }
                     Some("command_execution") => {
@@ -710,7 +1508,15 @@ fn parse_claude_stream_line(json: &Value) -> Vec<StreamMessage> {
                             } else {
                                 format!("Command exited with code {}", exit_code.unwrap_or(-1))
                             };
-                            messages.push(StreamMessage::ToolResult { content, is_error });
+                            messages.push(StreamMessage::ToolResult {
+                                tool_use_id: item
+                                    .get("id")
+                                    .and_then(|v| v.as_str())
+                                    .map(String::from),
+                                content,
+                                is_error,
+                                parent: None,
+                            });
                         }
                     }
                     Some("error") => {
@@ -786,7 +1592,12 @@ mod tests {
         let msgs = parse_claude_stream_line(&json);
         assert_eq!(msgs.len(), 1);
         match &msgs[0] {
-            StreamMessage::Init { session_id } => assert_eq!(session_id, "thread-123"),
+            StreamMessage::Init {
+                session_id, model, ..
+            } => {
+                assert_eq!(session_id, "thread-123");
+                assert_eq!(model, &None);
+            }
             _ => panic!("expected init message"),
         }
     }
@@ -794,13 +1605,31 @@ mod tests {
     #[test]
     fn test_parse_claude_init() {
         let json = parse_json(
-            r#"{"type":"system","subtype":"init","session_id":"54c57e53-7575-4fd6-820a-8432dc14ccb6"}"#,
+            r#"{"type":"system","subtype":"init","session_id":"54c57e53-7575-4fd6-820a-8432dc14ccb6","model":"claude-sonnet-4-5","mcp_servers":[{"name":"github","status":"connected"},{"name":"db","status":"failed"}]}"#,
         );
         let msgs = parse_claude_stream_line(&json);
         assert_eq!(msgs.len(), 1);
         match &msgs[0] {
-            StreamMessage::Init { session_id } => {
-                assert_eq!(session_id, "54c57e53-7575-4fd6-820a-8432dc14ccb6")
+            StreamMessage::Init {
+                session_id,
+                model,
+                mcp_servers,
+            } => {
+                assert_eq!(session_id, "54c57e53-7575-4fd6-820a-8432dc14ccb6");
+                assert_eq!(model.as_deref(), Some("claude-sonnet-4-5"));
+                assert_eq!(
+                    mcp_servers,
+                    &vec![
+                        McpServerStatus {
+                            name: "github".to_string(),
+                            status: "connected".to_string(),
+                        },
+                        McpServerStatus {
+                            name: "db".to_string(),
+                            status: "failed".to_string(),
+                        },
+                    ]
+                );
             }
             _ => panic!("expected init message"),
         }
@@ -814,7 +1643,7 @@ mod tests {
         let msgs = parse_claude_stream_line(&json);
         assert_eq!(msgs.len(), 1);
         match &msgs[0] {
-            StreamMessage::Text { content } => assert_eq!(content, "Hello from Claude"),
+            StreamMessage::Text { content, .. } => assert_eq!(content, "Hello from Claude"),
             _ => panic!("expected text message"),
         }
     }
@@ -863,11 +1692,129 @@ mod tests {
         let msgs = parse_claude_stream_line(&json);
         assert_eq!(msgs.len(), 1);
         match &msgs[0] {
-            StreamMessage::Text { content } => assert_eq!(content, "hello"),
+            StreamMessage::Text { content, .. } => assert_eq!(content, "hello"),
             _ => panic!("expected text message"),
         }
     }
 
+    #[test]
+    fn test_parse_claude_user_tool_results() {
+        let json = parse_json(
+            r#"{"type":"user","message":{"role":"user","content":[
+                {"type":"tool_result","tool_use_id":"toolu_1","content":"ok\n"},
+                {"type":"tool_result","tool_use_id":"toolu_2","content":[{"type":"text","text":"a"},{"type":"image","source":{}}]},
+                {"type":"tool_result","tool_use_id":"toolu_3","is_error":true,"content":"<tool_use_error>File does not exist.</tool_use_error>"}
+            ]}}"#,
+        );
+        let msgs = parse_claude_stream_line(&json);
+        let results: Vec<(Option<&str>, &str, bool)> = msgs
+            .iter()
+            .map(|m| match m {
+                StreamMessage::ToolResult {
+                    tool_use_id,
+                    content,
+                    is_error,
+                    ..
+                } => (tool_use_id.as_deref(), content.as_str(), *is_error),
+                _ => panic!("expected tool result message"),
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (Some("toolu_1"), "ok", false),
+                (Some("toolu_2"), "a\n[image]", false),
+                (Some("toolu_3"), "File does not exist.", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_thinking_blocks() {
+        let json = parse_json(
+            r#"{"type":"assistant","message":{"content":[
+                {"type":"thinking","thinking":"The user wants a fix.\nCheck main.rs first.","signature":"sig"},
+                {"type":"thinking","thinking":"  "},
+                {"type":"text","text":"Looking at main.rs"}
+            ]}}"#,
+        );
+        let msgs = parse_claude_stream_line(&json);
+        assert_eq!(msgs.len(), 2);
+        match &msgs[0] {
+            StreamMessage::Thinking { content, parent } => {
+                assert_eq!(content, "The user wants a fix.\nCheck main.rs first.");
+                assert!(parent.is_none());
+            }
+            _ => panic!("expected thinking message"),
+        }
+        assert!(matches!(&msgs[1], StreamMessage::Text { .. }));
+
+        let json = parse_json(
+            r#"{"type":"item.completed","item":{"id":"item_0","type":"reasoning","text":"**Planning the change**"}}"#,
+        );
+        let msgs = parse_claude_stream_line(&json);
+        assert!(matches!(
+            msgs.as_slice(),
+            [StreamMessage::Thinking { content, .. }] if content == "**Planning the change**"
+        ));
+    }
+
+    #[test]
+    fn test_parse_todo_write_and_task_notification() {
+        let json = parse_json(
+            r#"{"type":"assistant","message":{"content":[{"type":"tool_use","id":"toolu_t","name":"TodoWrite","input":{"todos":[
+                {"content":"Write parser","status":"completed","activeForm":"Writing parser"},
+                {"content":"Add tests","status":"in_progress","activeForm":"Adding tests"},
+                {"content":"Update README","status":"pending"}
+            ]}}]}}"#,
+        );
+        let msgs = parse_claude_stream_line(&json);
+        assert_eq!(msgs.len(), 1);
+        match &msgs[0] {
+            StreamMessage::Todos { id, items } => {
+                assert_eq!(id.as_deref(), Some("toolu_t"));
+                let statuses: Vec<TodoStatus> = items.iter().map(|t| t.status).collect();
+                assert_eq!(
+                    statuses,
+                    vec![
+                        TodoStatus::Completed,
+                        TodoStatus::InProgress,
+                        TodoStatus::Pending
+                    ]
+                );
+                assert_eq!(items[1].active_form.as_deref(), Some("Adding tests"));
+                assert_eq!(items[2].active_form, None);
+            }
+            _ => panic!("expected todos message"),
+        }
+
+        let json = parse_json(
+            r#"{"type":"system","subtype":"task_notification","task_id":"bash_1","status":"failed","summary":"npm test"}"#,
+        );
+        let msgs = parse_claude_stream_line(&json);
+        assert!(matches!(
+            &msgs[..],
+            [StreamMessage::TaskNotification { task_id, status, .. }]
+                if task_id == "bash_1" && status == "failed"
+        ));
+    }
+
+    #[test]
+    fn test_parse_codex_todo_list() {
+        let json = parse_json(
+            r#"{"type":"item.updated","item":{"id":"item_3","type":"todo_list","items":[{"text":"a","completed":true},{"text":"b","completed":false}]}}"#,
+        );
+        let msgs = parse_claude_stream_line(&json);
+        match &msgs[..] {
+            [StreamMessage::Todos { items, .. }] => {
+                assert_eq!(items[0].status, TodoStatus::Completed);
+                assert_eq!(items[1].status, TodoStatus::Pending);
+                assert_eq!(items[1].content, "b");
+            }
+            _ => panic!("expected todos message"),
+        }
+    }
+
     #[test]
     fn test_parse_command_started() {
         let json = parse_json(
@@ -876,7 +1823,7 @@ mod tests {
         let msgs = parse_claude_stream_line(&json);
         assert_eq!(msgs.len(), 1);
         match &msgs[0] {
-            StreamMessage::ToolUse { name, input } => {
+            StreamMessage::ToolUse { name, input, .. } => {
                 assert_eq!(name, "Bash");
                 assert!(input.contains("pwd"));
             }
@@ -892,7 +1839,9 @@ mod tests {
         let msgs = parse_claude_stream_line(&json);
         assert_eq!(msgs.len(), 1);
         match &msgs[0] {
-            StreamMessage::ToolResult { content, is_error } => {
+            StreamMessage::ToolResult {
+                content, is_error, ..
+            } => {
                 assert_eq!(content, "/tmp");
                 assert!(!is_error);
             }
@@ -908,7 +1857,9 @@ mod tests {
         let msgs = parse_claude_stream_line(&json);
         assert_eq!(msgs.len(), 1);
         match &msgs[0] {
-            StreamMessage::ToolResult { content, is_error } => {
+            StreamMessage::ToolResult {
+                content, is_error, ..
+            } => {
                 assert_eq!(content, "boom");
                 assert!(*is_error);
             }
@@ -949,6 +1900,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tool_file_path() {
+        assert_eq!(
+            tool_file_path("Write", r#"{"file_path":"/tmp/a.rs","content":"x"}"#).as_deref(),
+            Some("/tmp/a.rs")
+        );
+        assert_eq!(
+            tool_file_path("Edit", r#"{"file_path":"src/b.rs","old_string":"a"}"#).as_deref(),
+            Some("src/b.rs")
+        );
+        assert_eq!(
+            tool_file_path("NotebookEdit", r#"{"notebook_path":"/n.ipynb"}"#).as_deref(),
+            Some("/n.ipynb")
+        );
+        assert_eq!(tool_file_path("Read", r#"{"file_path":"/tmp/a.rs"}"#), None);
+        assert_eq!(tool_file_path("Write", "not json"), None);
+    }
+
     #[test]
     fn test_is_ai_supported() {
         #[cfg(unix)]
@@ -989,9 +1958,33 @@ mod tests {
         assert_eq!(ai_binary_name(), "claude");
     }
 
+    #[test]
+    fn test_user_message_line_with_attachments() {
+        let line: Value = serde_json::from_str(&user_message_line("hi", &[])).expect("json");
+        assert_eq!(line["message"]["content"], "hi");
+
+        let image = serde_json::json!({ "type": "image", "source": {} });
+        let line: Value = serde_json::from_str(&user_message_line(
+            "what is this?",
+            std::slice::from_ref(&image),
+        ))
+        .expect("json");
+        let content = line["message"]["content"].as_array().expect("blocks");
+        assert_eq!(content.len(), 2);
+        assert_eq!(content[0], image);
+        assert_eq!(content[1]["text"], "what is this?");
+    }
+
     #[test]
     fn test_ai_args_default_session() {
-        let args = ai_args(None).expect("args should build");
+        let args = ai_args(
+            None,
+            None,
+            &ModelOptions::default(),
+            &ProjectOptions::default(),
+            None,
+        )
+        .expect("args should build");
         assert_eq!(
             args,
             vec![
@@ -999,6 +1992,10 @@ mod tests {
                 "--output-format",
                 "stream-json",
                 "--verbose",
+                "--input-format",
+                "stream-json",
+                "--permission-prompt-tool",
+                "stdio",
                 "--permission-mode",
                 "default",
             ]
@@ -1007,7 +2004,14 @@ mod tests {
 
     #[test]
     fn test_ai_args_resume_session() {
-        let args = ai_args(Some("session-1")).expect("args should build");
+        let args = ai_args(
+            Some("session-1"),
+            None,
+            &ModelOptions::default(),
+            &ProjectOptions::default(),
+            None,
+        )
+        .expect("args should build");
         assert_eq!(
             args,
             vec![
@@ -1015,6 +2019,10 @@ mod tests {
                 "--output-format",
                 "stream-json",
                 "--verbose",
+                "--input-format",
+                "stream-json",
+                "--permission-prompt-tool",
+                "stdio",
                 "--permission-mode",
                 "default",
                 "--resume",
@@ -1023,6 +2031,189 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tool_policy_can_write() {
+        let policy = |enforced: Option<&[&str]>| ToolPolicy {
+            allowed: None,
+            enforced: enforced.map(|t| t.iter().map(|s| s.to_string()).collect()),
+        };
+        assert!(policy(None).can_write());
+        assert!(policy(Some(&["Read", "Edit"])).can_write());
+        assert!(!policy(Some(&["Read", "Glob", "Grep"])).can_write());
+        assert!(!policy(Some(&[])).can_write());
+    }
+
+    #[test]
+    fn test_ai_args_enforced_tools() {
+        let enforced = vec![
+            "Read".to_string(),
+            "Grep".to_string(),
+            "AskUserQuestion".to_string(),
+        ];
+        let args = ai_args(
+            None,
+            Some(&enforced),
+            &ModelOptions::default(),
+            &ProjectOptions::default(),
+            None,
+        )
+        .expect("args should build");
+        assert!(!args.iter().any(|a| a == "--dangerously-skip-permissions"));
+        let pos = args
+            .iter()
+            .position(|a| a == "--permission-mode")
+            .expect("flag present");
+        assert_eq!(args[pos + 1], "default");
+        let pos = args
+            .iter()
+            .position(|a| a == "--allowedTools")
+            .expect("flag present");
+        assert_eq!(args[pos + 1], "Read,Grep");
+
+        let args = ai_args(
+            None,
+            Some(&[]),
+            &ModelOptions::default(),
+            &ProjectOptions::default(),
+            None,
+        )
+        .expect("args should build");
+        assert!(!args.iter().any(|a| a == "--allowedTools"));
+
+        let args = ai_args(
+            None,
+            Some(&["Bash".to_string()]),
+            &ModelOptions::default(),
+            &ProjectOptions::default(),
+            None,
+        )
+        .expect("args should build");
+        let pos = args
+            .iter()
+            .position(|a| a == "--disallowedTools")
+            .expect("flag present");
+        let disallowed: Vec<&str> = args[pos + 1].split(',').collect();
+        assert!(disallowed.contains(&"Read"));
+        assert!(disallowed.contains(&"TodoWrite"));
+        assert!(!disallowed.contains(&"Bash"));
+
+        let args = ai_args(
+            None,
+            None,
+            &ModelOptions::default(),
+            &ProjectOptions::default(),
+            None,
+        )
+        .expect("args should build");
+        assert!(!args.iter().any(|a| a == "--disallowedTools"));
+    }
+
+    #[test]
+    fn test_ai_args_model_options() {
+        let model = ModelOptions {
+            model: Some("opus".to_string()),
+            fallback_model: Some("sonnet".to_string()),
+            max_turns: Some(30),
+        };
+        let args = ai_args(None, None, &model, &ProjectOptions::default(), None)
+            .expect("args should build");
+        assert!(args.ends_with(&[
+            "--model".to_string(),
+            "opus".to_string(),
+            "--fallback-model".to_string(),
+            "sonnet".to_string(),
+            "--max-turns".to_string(),
+            "30".to_string(),
+        ]));
+
+        let same = ModelOptions {
+            fallback_model: Some("opus".to_string()),
+            ..model
+        };
+        let args = ai_args(None, None, &same, &ProjectOptions::default(), None)
+            .expect("args should build");
+        assert!(!args.iter().any(|a| a == "--fallback-model"));
+    }
+
+    #[test]
+    fn test_ai_args_project_options() {
+        let project = ProjectOptions {
+            mcp_configs: vec!["/home/u/mcp.json".to_string()],
+            add_dirs: vec!["/home/u/lib".to_string(), "/home/u/docs".to_string()],
+        };
+        let args = ai_args(
+            Some("session-1"),
+            None,
+            &ModelOptions::default(),
+            &project,
+            None,
+        )
+        .expect("args should build");
+        let pos = args
+            .iter()
+            .position(|a| a == "--mcp-config")
+            .expect("flag present");
+        assert_eq!(
+            args[pos..pos + 6],
+            [
+                "--mcp-config",
+                "/home/u/mcp.json",
+                "--add-dir",
+                "/home/u/lib",
+                "/home/u/docs",
+                "--resume",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_system_prompt_goes_to_cli_flag() {
+        let tools = vec!["Read".to_string(), "Grep".to_string()];
+        let text = build_system_prompt(Some("Be brief."), Some(&tools)).expect("prompt");
+        assert_eq!(
+            text,
+            "Be brief.\n\nTOOL CONSTRAINT:\nOnly use the following tools when needed: Read, Grep"
+        );
+        assert_eq!(build_system_prompt(Some(""), None), None);
+
+        let args = ai_args(
+            None,
+            None,
+            &ModelOptions::default(),
+            &ProjectOptions::default(),
+            Some(&text),
+        )
+        .expect("args should build");
+        let pos = args
+            .iter()
+            .position(|a| a == "--append-system-prompt")
+            .expect("flag present");
+        assert_eq!(args[pos + 1], text);
+    }
+
+    #[test]
+    fn test_effort_budget() {
+        assert_eq!(effort_budget("low"), Some(4_000));
+        assert_eq!(effort_budget("high"), Some(32_000));
+        assert_eq!(effort_budget("extreme"), None);
+    }
+
+    #[test]
+    fn test_split_model_override() {
+        assert_eq!(
+            split_model_override("opus: refactor this"),
+            Some(("opus", "refactor this"))
+        );
+        assert_eq!(
+            split_model_override("claude-sonnet-4-5:fix it"),
+            Some(("claude-sonnet-4-5", "fix it"))
+        );
+        assert_eq!(split_model_override("note: keep it short"), None);
+        assert_eq!(split_model_override("opus:"), None);
+        assert!(!is_known_model("claude-"));
+        assert!(!is_known_model("claude-Opus 4"));
+    }
+
     #[test]
     fn test_resolve_ai_binary_path_uses_claude() {
         let has_claude = std::process::Command::new("which")
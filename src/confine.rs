@@ -0,0 +1,320 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Landlock filesystem access rights (linux/landlock.h)
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+/// ABI 1: everything from EXECUTE up to MAKE_SYM
+const ACCESS_ABI_1: u64 = (1 << 13) - 1;
+/// ABI 2: REFER (rename/link across directories)
+const ACCESS_REFER: u64 = 1 << 13;
+/// ABI 3: TRUNCATE
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+
+const READ_ONLY: u64 = ACCESS_EXECUTE | ACCESS_READ_FILE | ACCESS_READ_DIR;
+/// Rights that may be granted on a regular file rather than a directory
+const FILE_RIGHTS: u64 = ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE;
+
+/// System directories children may read and execute from
+const SYSTEM_READ_ONLY: &[&str] = &[
+    "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/opt", "/proc", "/sys", "/run",
+    "/nix", "/snap",
+];
+
+/// Writable outside the project: temp files and device nodes such as /dev/null
+const SYSTEM_WRITABLE: &[&str] = &["/tmp", "/var/tmp", "/dev"];
+
+/// Home-relative toolchain directories (node, npm, cargo...) children may read
+const HOME_READ_ONLY: &[&str] = &[
+    ".local",
+    ".nvm",
+    ".npm-global",
+    ".bun",
+    ".volta",
+    ".cargo",
+    ".rustup",
+    ".gitconfig",
+];
+
+/// Home-relative state the Claude CLI writes to
+const HOME_WRITABLE: &[&str] = &[".claude", ".claude.json", ".cache", ".npm"];
+
+struct ConfineConfig {
+    abi: i32,
+    /// Extra writable paths given on the command line
+    extra_writable: Vec<PathBuf>,
+}
+
+static CONFINE: OnceLock<Option<ConfineConfig>> = OnceLock::new();
+
+/// Landlock ABI version supported by the running kernel, if any
+pub fn abi_version() -> Option<i32> {
+    #[cfg(target_os = "linux")]
+    {
+        const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1 << 0;
+        // SAFETY: a version query passes no attribute pointer and creates no fd
+        #[allow(unsafe_code)]
+        let version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<u8>(),
+                0usize,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if version >= 1 {
+            return i32::try_from(version).ok();
+        }
+    }
+    None
+}
+
+/// Enable confinement once at startup. Returns an error (and stays disabled)
+/// when the kernel has no Landlock support.
+pub fn configure(extra_writable: Vec<PathBuf>) -> Result<i32, String> {
+    let Some(abi) = abi_version() else {
+        let _ = CONFINE.set(None);
+        return Err("Landlock is not supported by this kernel".to_string());
+    };
+    let _ = CONFINE.set(Some(ConfineConfig {
+        abi,
+        extra_writable,
+    }));
+    Ok(abi)
+}
+
+/// Whether children are confined. They cannot read the bot settings then, so
+/// `--sendfile` from inside a run does not work.
+pub fn is_enabled() -> bool {
+    matches!(CONFINE.get(), Some(Some(_)))
+}
+
+/// Access rights the kernel knows about for `abi`
+fn handled_access(abi: i32) -> u64 {
+    let mut access = ACCESS_ABI_1;
+    if abi >= 2 {
+        access |= ACCESS_REFER;
+    }
+    if abi >= 3 {
+        access |= ACCESS_TRUNCATE;
+    }
+    access
+}
+
+/// Paths a child started in `working_dir` may use: (writable, read-only)
+fn allowed_paths(working_dir: &Path, extra_writable: &[PathBuf]) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut writable: Vec<PathBuf> = vec![working_dir.to_path_buf()];
+    writable.extend(SYSTEM_WRITABLE.iter().map(PathBuf::from));
+    writable.extend(extra_writable.iter().cloned());
+
+    let mut read_only: Vec<PathBuf> = SYSTEM_READ_ONLY.iter().map(PathBuf::from).collect();
+    if let Some(home) = dirs::home_dir() {
+        writable.extend(HOME_WRITABLE.iter().map(|p| home.join(p)));
+        read_only.extend(HOME_READ_ONLY.iter().map(|p| home.join(p)));
+    }
+    // Binaries found through PATH (e.g. a node install elsewhere)
+    if let Some(path) = std::env::var_os("PATH") {
+        read_only.extend(std::env::split_paths(&path));
+    }
+    (writable, read_only)
+}
+
+/// Confine `cmd` to `working_dir` plus system directories, if confinement is enabled.
+/// `add_dirs` (the project's /adddir directories) are writable as well.
+/// Fails closed: an error means the command must not be started.
+pub fn apply(cmd: &mut Command, working_dir: &Path, add_dirs: &[String]) -> Result<(), String> {
+    let Some(Some(config)) = CONFINE.get() else {
+        return Ok(());
+    };
+    let extra_writable: Vec<PathBuf> = config
+        .extra_writable
+        .iter()
+        .cloned()
+        .chain(add_dirs.iter().map(PathBuf::from))
+        .collect();
+    let (writable, read_only) = allowed_paths(working_dir, &extra_writable);
+    #[cfg(target_os = "linux")]
+    {
+        let ruleset = landlock::build_ruleset(config.abi, &writable, &read_only)?;
+        landlock::restrict_on_exec(cmd, ruleset);
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (cmd, writable, read_only);
+        Err("Confinement is only available on Linux".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::ffi::CString;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use super::{handled_access, FILE_RIGHTS, READ_ONLY};
+
+    const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Create a ruleset fd granting all handled rights below `writable` and
+    /// read/execute below `read_only`. Paths that do not exist are skipped.
+    pub(super) fn build_ruleset(
+        abi: i32,
+        writable: &[PathBuf],
+        read_only: &[PathBuf],
+    ) -> Result<OwnedFd, String> {
+        let handled = handled_access(abi);
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // SAFETY: attr is a valid landlock_ruleset_attr prefix of the given size
+        #[allow(unsafe_code)]
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if fd < 0 {
+            return Err(format!(
+                "Failed to create Landlock ruleset: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: the syscall returned a fresh file descriptor that nothing else owns
+        #[allow(unsafe_code)]
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        for (paths, access) in [(writable, handled), (read_only, READ_ONLY & handled)] {
+            for path in paths {
+                add_path_rule(&ruleset, path, access)?;
+            }
+        }
+        Ok(ruleset)
+    }
+
+    fn add_path_rule(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<(), String> {
+        let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+            return Ok(());
+        };
+        // SAFETY: c_path is a valid NUL-terminated string
+        #[allow(unsafe_code)]
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            // Missing optional directory (e.g. /lib32, ~/.nvm)
+            return Ok(());
+        }
+        // SAFETY: open returned a fresh file descriptor that nothing else owns
+        #[allow(unsafe_code)]
+        let parent = unsafe { OwnedFd::from_raw_fd(fd) };
+        let access = if path.is_dir() {
+            access
+        } else {
+            access & FILE_RIGHTS
+        };
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: parent.as_raw_fd(),
+        };
+        // SAFETY: attr is a valid landlock_path_beneath_attr and both fds are open
+        #[allow(unsafe_code)]
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0u32,
+            )
+        };
+        if ret < 0 {
+            return Err(format!(
+                "Failed to add Landlock rule for {}: {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Enforce `ruleset` in the child right before exec
+    pub(super) fn restrict_on_exec(cmd: &mut Command, ruleset: OwnedFd) {
+        // SAFETY: the closure runs in the forked child before exec and only makes
+        // async-signal-safe calls (prctl, syscall) on an fd opened before the fork.
+        #[allow(unsafe_code)]
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0u32) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handled_access_by_abi() {
+        assert_eq!(handled_access(1), ACCESS_ABI_1);
+        assert_eq!(handled_access(2), ACCESS_ABI_1 | ACCESS_REFER);
+        assert_eq!(
+            handled_access(5),
+            ACCESS_ABI_1 | ACCESS_REFER | ACCESS_TRUNCATE
+        );
+    }
+
+    #[test]
+    fn test_child_cannot_write_outside_project() {
+        let Some(abi) = abi_version() else {
+            // Kernel without Landlock: nothing to enforce
+            return;
+        };
+        let root = std::env::temp_dir().join(format!("openclaude-confine-{}", std::process::id()));
+        let project = root.join("project");
+        std::fs::create_dir_all(&project).expect("create project dir");
+
+        let read_only: Vec<PathBuf> = SYSTEM_READ_ONLY.iter().map(PathBuf::from).collect();
+        let ruleset = landlock::build_ruleset(abi, std::slice::from_ref(&project), &read_only)
+            .expect("build ruleset");
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", "echo in > inside.txt; echo out > ../outside.txt"])
+            .current_dir(&project);
+        landlock::restrict_on_exec(&mut cmd, ruleset);
+        let output = cmd.output().expect("run sh");
+
+        let inside = project.join("inside.txt").exists();
+        let outside = root.join("outside.txt").exists();
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert!(inside);
+        assert!(!outside);
+        assert!(!output.status.success());
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::{child_env, confine, limits};
+
+/// One changed path in the working tree, combining `git status` and `git diff --numstat`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: String,
+    /// Single-letter status: M (modified), A (added), D (deleted), R (renamed), ? (untracked)
+    pub status: char,
+    /// Added/removed line counts; None for binary files or untracked files
+    pub added: Option<u64>,
+    pub removed: Option<u64>,
+}
+
+/// Every `git diff` passes these, so diff drivers configured in the repository never run
+const NO_EXT_DIFF: &str = "--no-ext-diff";
+const NO_TEXTCONV: &str = "--no-textconv";
+
+/// One commit from `git log`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub hash: String,
+    pub author: String,
+    pub when: String,
+    pub subject: String,
+}
+
+/// Leading arguments that select the repository (`--git-dir=…`, `--work-tree=…`),
+/// which the filter driver lookup needs as well
+fn repo_selection<'a>(args: &'a [&'a str]) -> &'a [&'a str] {
+    let n = args
+        .iter()
+        .take_while(|a| a.starts_with("--git-dir=") || a.starts_with("--work-tree="))
+        .count();
+    &args[..n]
+}
+
+/// Spawn git in `repo_dir` and return stdout. Exit codes in `ok_codes` besides 0
+/// count as success. The repository is as untrusted as the AI that edits it, so
+/// git gets the same filtered environment, resource limits and confinement as AI
+/// runs (`writable` besides `repo_dir`), and everything in the repository's config
+/// that starts a program when git reads or writes files is switched off: hooks,
+/// fsmonitor and commit signing here, filter drivers in `run_git_with_codes` and
+/// external diff drivers and textconv with `--no-ext-diff --no-textconv`.
+fn exec_git(
+    repo_dir: &str,
+    writable: &[String],
+    config: &[String],
+    args: &[&str],
+    ok_codes: &[i32],
+) -> Result<String, String> {
+    let mut command = Command::new("git");
+    command
+        .args(["--no-pager", "-c", "core.hooksPath=/dev/null"])
+        .args(["-c", "core.fsmonitor=false"])
+        .args([
+            "-c",
+            "commit.gpgSign=false",
+            "-c",
+            "log.showSignature=false",
+        ])
+        .args(config.iter().flat_map(|c| ["-c", c.as_str()]))
+        .args(args)
+        .current_dir(repo_dir)
+        .env_clear()
+        .envs(child_env::inherited_vars())
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null());
+    let _cgroup_scope = limits::apply(&mut command);
+    confine::apply(&mut command, Path::new(repo_dir), writable)?;
+    let output = command
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+
+    let code = output.status.code().unwrap_or(-1);
+    if output.status.success() || ok_codes.contains(&code) {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if stderr.is_empty() {
+            Err(format!("git exited with code {}", code))
+        } else {
+            Err(stderr)
+        }
+    }
+}
+
+/// `-c` values that blank every filter driver configured for the repository.
+/// `.gitattributes` in any directory can name a driver, but only configured
+/// drivers run, so all of them are switched off.
+fn filter_overrides(
+    repo_dir: &str,
+    writable: &[String],
+    selection: &[&str],
+) -> Result<Vec<String>, String> {
+    let lookup = [
+        selection,
+        &["config", "--null", "--get-regexp", r"^filter\."],
+    ]
+    .concat();
+    // Exit code 1: no filter configured
+    let output = exec_git(repo_dir, writable, &[], &lookup, &[1])?;
+    let mut names: Vec<&str> = Vec::new();
+    for entry in output.split('\0') {
+        let key = entry.split_once('\n').map_or(entry, |(key, _)| key);
+        let Some((name, _)) = key
+            .strip_prefix("filter.")
+            .and_then(|rest| rest.rsplit_once('.'))
+        else {
+            continue;
+        };
+        if name.contains('=') {
+            return Err(format!("Unsupported filter driver name: {}", name));
+        }
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names
+        .iter()
+        .flat_map(|name| {
+            ["clean=", "smudge=", "process=", "required=false"]
+                .map(|setting| format!("filter.{}.{}", name, setting))
+        })
+        .collect())
+}
+
+/// Run git non-interactively in `repo_dir` and return stdout.
+/// Exit codes listed in `ok_codes` besides 0 are treated as success
+/// (e.g. `git diff --no-index` exits 1 when files differ).
+fn run_git_with_codes(
+    repo_dir: &str,
+    writable: &[String],
+    args: &[&str],
+    ok_codes: &[i32],
+) -> Result<String, String> {
+    let config = filter_overrides(repo_dir, writable, repo_selection(args))?;
+    exec_git(repo_dir, writable, &config, args, ok_codes)
+}
+
+/// Run git non-interactively in `repo_dir` and return stdout
+pub fn run_git(repo_dir: &str, args: &[&str]) -> Result<String, String> {
+    run_git_with_codes(repo_dir, &[], args, &[])
+}
+
+/// Like `run_git`, with `writable` directories (e.g. a checkpoint store outside
+/// the project) writable under confinement as well
+pub fn run_git_writable(
+    repo_dir: &str,
+    writable: &[String],
+    args: &[&str],
+) -> Result<String, String> {
+    run_git_with_codes(repo_dir, writable, args, &[])
+}
+
+/// Top-level directory of the work tree containing `dir`, or None if not in a repository.
+/// Status and numstat paths are relative to this root, so commands run from it.
+pub fn repo_root(dir: &str) -> Option<String> {
+    run_git(dir, &["rev-parse", "--show-toplevel"])
+        .ok()
+        .map(|out| out.trim().to_string())
+        .filter(|root| !root.is_empty())
+}
+
+/// Whether the repository has at least one commit
+fn has_head(dir: &str) -> bool {
+    run_git(dir, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok()
+}
+
+/// Parse `git status --porcelain` into (status letter, path) pairs.
+/// Renames report the new path.
+pub fn parse_porcelain(output: &str) -> Vec<(char, String)> {
+    output
+        .lines()
+        .filter(|line| line.len() > 3)
+        .map(|line| {
+            let (xy, rest) = line.split_at(2);
+            let path = rest.trim_start();
+            let path = path.split_once(" -> ").map(|(_, new)| new).unwrap_or(path);
+            let path = path.trim_matches('"').to_string();
+            let mut codes = xy.chars().filter(|c| *c != ' ');
+            let status = match codes.next() {
+                Some('?') => '?',
+                Some(c) if "MADR".contains(c) => c,
+                Some('C') => 'A',
+                _ => 'M',
+            };
+            (status, path)
+        })
+        .collect()
+}
+
+/// Parse `git diff --numstat` into path -> (added, removed).
+/// Binary files report `-` counts and map to None.
+pub fn parse_numstat(output: &str) -> HashMap<String, (Option<u64>, Option<u64>)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            let added = parts.next()?.parse::<u64>().ok();
+            let removed = parts.next()?.parse::<u64>().ok();
+            let path = parts.next()?;
+            // Renames are reported as "old => new" or "dir/{old => new}"
+            let path = match path.split_once(" => ") {
+                Some((before, after)) => match (before.split_once('{'), after.split_once('}')) {
+                    (Some((prefix, _)), Some((new, suffix))) => {
+                        format!("{}{}{}", prefix, new, suffix)
+                    }
+                    _ => after.to_string(),
+                },
+                None => path.to_string(),
+            };
+            Some((path, (added, removed)))
+        })
+        .collect()
+}
+
+/// List changed files (staged, unstaged and untracked) relative to HEAD
+pub fn changed_files(dir: &str) -> Result<Vec<FileChange>, String> {
+    let status = run_git(dir, &["status", "--porcelain", "--untracked-files=all"])?;
+    let numstat = if has_head(dir) {
+        run_git(
+            dir,
+            &["diff", NO_EXT_DIFF, NO_TEXTCONV, "HEAD", "--numstat", "-M"],
+        )?
+    } else {
+        run_git(
+            dir,
+            &[
+                "diff",
+                NO_EXT_DIFF,
+                NO_TEXTCONV,
+                "--cached",
+                "--numstat",
+                "-M",
+            ],
+        )?
+    };
+    let counts = parse_numstat(&numstat);
+
+    Ok(parse_porcelain(&status)
+        .into_iter()
+        .map(|(status, path)| {
+            let (added, removed) = counts.get(&path).copied().unwrap_or((None, None));
+            FileChange {
+                path,
+                status,
+                added,
+                removed,
+            }
+        })
+        .collect())
+}
+
+/// Unified diff for a single changed file, including untracked files
+pub fn file_diff(dir: &str, change: &FileChange) -> Result<String, String> {
+    if change.status == '?' {
+        return run_git_with_codes(
+            dir,
+            &[],
+            &[
+                "diff",
+                NO_EXT_DIFF,
+                NO_TEXTCONV,
+                "--no-color",
+                "--no-index",
+                "--",
+                "/dev/null",
+                &change.path,
+            ],
+            &[1],
+        );
+    }
+    if has_head(dir) {
+        run_git(
+            dir,
+            &[
+                "diff",
+                NO_EXT_DIFF,
+                NO_TEXTCONV,
+                "--no-color",
+                "-M",
+                "HEAD",
+                "--",
+                &change.path,
+            ],
+        )
+    } else {
+        run_git(
+            dir,
+            &[
+                "diff",
+                NO_EXT_DIFF,
+                NO_TEXTCONV,
+                "--no-color",
+                "--cached",
+                "--",
+                &change.path,
+            ],
+        )
+    }
+}
+
+/// Parse the output of `git log` with LOG_FORMAT
+pub fn parse_log(output: &str) -> Vec<LogEntry> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(4, '\t');
+            Some(LogEntry {
+                hash: parts.next()?.to_string(),
+                author: parts.next()?.to_string(),
+                when: parts.next()?.to_string(),
+                subject: parts.next().unwrap_or("").to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Tab-separated short hash, author, relative date, subject
+const LOG_FORMAT: &str = "--pretty=format:%h%x09%an%x09%ar%x09%s";
+
+/// Most recent `count` commits on the current branch
+pub fn recent_log(dir: &str, count: usize) -> Result<Vec<LogEntry>, String> {
+    if !has_head(dir) {
+        return Ok(Vec::new());
+    }
+    let count_arg = format!("-n{}", count);
+    let output = run_git(dir, &["log", "--no-color", &count_arg, LOG_FORMAT])?;
+    Ok(parse_log(&output))
+}
+
+/// Stage everything and commit with `message`. Returns git's summary output.
+pub fn commit_all(dir: &str, message: &str) -> Result<String, String> {
+    run_git(dir, &["add", "-A"])?;
+    run_git(dir, &["commit", "-m", message])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_porcelain() {
+        let out = " M src/main.rs\nA  new.rs\n D gone.rs\nR  old.rs -> renamed.rs\n?? notes.txt\nMM both.rs\n";
+        let parsed = parse_porcelain(out);
+        assert_eq!(
+            parsed,
+            vec![
+                ('M', "src/main.rs".to_string()),
+                ('A', "new.rs".to_string()),
+                ('D', "gone.rs".to_string()),
+                ('R', "renamed.rs".to_string()),
+                ('?', "notes.txt".to_string()),
+                ('M', "both.rs".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_numstat() {
+        let out = "10\t2\tsrc/main.rs\n-\t-\tlogo.png\n3\t3\tsrc/{old => new}/lib.rs\n1\t0\ta.rs => b.rs\n";
+        let parsed = parse_numstat(out);
+        assert_eq!(parsed.get("src/main.rs"), Some(&(Some(10), Some(2))));
+        assert_eq!(parsed.get("logo.png"), Some(&(None, None)));
+        assert_eq!(parsed.get("src/new/lib.rs"), Some(&(Some(3), Some(3))));
+        assert_eq!(parsed.get("b.rs"), Some(&(Some(1), Some(0))));
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let out =
+            "abc1234\tAlice\t2 hours ago\tFix bug\ndef5678\tBob\t3 days ago\tAdd feature: x\ty";
+        let parsed = parse_log(out);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].hash, "abc1234");
+        assert_eq!(parsed[0].subject, "Fix bug");
+        assert_eq!(parsed[1].author, "Bob");
+        assert_eq!(parsed[1].subject, "Add feature: x\ty");
+    }
+
+    #[test]
+    fn test_repo_root_outside_repo() {
+        assert!(repo_root("/").is_none());
+    }
+
+    /// A fresh repository in the temp dir, with a commit identity configured
+    #[allow(clippy::expect_used)]
+    fn temp_repo(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("openclaude-git-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("create dir");
+        let repo = dir.to_str().expect("utf-8 path");
+        run_git(repo, &["init", "--quiet"]).expect("init");
+        run_git(repo, &["config", "user.name", "t"]).expect("config");
+        run_git(repo, &["config", "user.email", "t@localhost"]).expect("config");
+        dir
+    }
+
+    /// An executable script that creates `marker` when run
+    #[cfg(unix)]
+    #[allow(clippy::expect_used)]
+    fn marker_script(path: &std::path::Path, marker: &std::path::Path) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = format!("#!/bin/sh\ntouch '{}'\ncat\n", marker.display());
+        std::fs::write(path, script).expect("write script");
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).expect("chmod");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[allow(clippy::expect_used)]
+    fn test_repo_hooks_do_not_run() {
+        let dir = temp_repo("hooks");
+        let repo = dir.to_str().expect("utf-8 path");
+        marker_script(&dir.join(".git/hooks/pre-commit"), &dir.join("hook-ran"));
+
+        run_git(repo, &["commit", "--allow-empty", "-qm", "x"]).expect("commit");
+        assert!(!dir.join("hook-ran").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[allow(clippy::expect_used)]
+    fn test_diff_drivers_and_filters_do_not_run() {
+        let dir = temp_repo("drivers");
+        let repo = dir.to_str().expect("utf-8 path");
+        let marker = dir.join(".git/driver-ran");
+        let script = dir.join(".git/driver.sh");
+        marker_script(&script, &marker);
+        let script = script.to_str().expect("utf-8 path");
+        std::fs::write(dir.join("a.txt"), "one\n").expect("write");
+        commit_all(repo, "first").expect("commit");
+
+        run_git(repo, &["config", "diff.external", script]).expect("config");
+        run_git(repo, &["config", "diff.conv.textconv", script]).expect("config");
+        run_git(repo, &["config", "filter.evil.clean", script]).expect("config");
+        run_git(repo, &["config", "filter.evil.smudge", script]).expect("config");
+        std::fs::write(dir.join(".gitattributes"), "*.txt diff=conv filter=evil\n").expect("write");
+        std::fs::write(dir.join("a.txt"), "two\n").expect("write");
+
+        let changes = changed_files(repo).expect("changes");
+        let change = changes
+            .iter()
+            .find(|c| c.path == "a.txt")
+            .expect("a.txt changed");
+        let diff = file_diff(repo, change).expect("diff");
+        assert!(diff.contains("+two"));
+        commit_all(repo, "second").expect("commit");
+        assert!(!marker.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,319 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Resource limits applied to Claude, shell and git child processes alike.
+/// All off by default.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLimits {
+    /// memory.max of the cgroup scope in MiB; needs `cgroup_parent`
+    pub memory_mb: Option<u64>,
+    /// Address space (RLIMIT_AS) per process in MiB. Node reserves several GiB of
+    /// virtual memory at startup, so this is meant for setups that run no Claude
+    pub address_space_mb: Option<u64>,
+    /// CPU time (RLIMIT_CPU) in seconds, per process
+    pub cpu_seconds: Option<u64>,
+    /// Largest file a process may write (RLIMIT_FSIZE) in MiB
+    pub file_size_mb: Option<u64>,
+    /// Processes for the bot's user (RLIMIT_NPROC); also pids.max of the cgroup scope
+    pub max_processes: Option<u64>,
+    /// Delegated cgroup v2 directory; each run gets its own child group under it
+    pub cgroup_parent: Option<PathBuf>,
+}
+
+static LIMITS: OnceLock<ResourceLimits> = OnceLock::new();
+
+/// Counter for unique cgroup scope names within this bot process
+static SCOPE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Set the limits once at startup
+pub fn configure(limits: ResourceLimits) {
+    let _ = LIMITS.set(limits);
+}
+
+fn current() -> &'static ResourceLimits {
+    LIMITS.get_or_init(ResourceLimits::default)
+}
+
+/// A per-run cgroup v2 group, removed again when dropped (if empty)
+pub struct CgroupScope {
+    path: PathBuf,
+}
+
+impl CgroupScope {
+    fn create(parent: &Path, limits: &ResourceLimits) -> Result<Self, String> {
+        let name = format!(
+            "run-{}-{}",
+            std::process::id(),
+            SCOPE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = parent.join(name);
+        fs::create_dir(&path)
+            .map_err(|e| format!("Failed to create cgroup {}: {}", path.display(), e))?;
+        let scope = CgroupScope { path };
+        if let Some(mb) = limits.memory_mb {
+            scope.write("memory.max", &(mb * 1024 * 1024).to_string())?;
+        }
+        if let Some(n) = limits.max_processes {
+            scope.write("pids.max", &n.to_string())?;
+        }
+        Ok(scope)
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<(), String> {
+        fs::write(self.path.join(file), value)
+            .map_err(|e| format!("Failed to set {} in {}: {}", file, self.path.display(), e))
+    }
+
+    /// Whether the kernel OOM-killed a process in this group
+    fn oom_killed(&self) -> bool {
+        fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|events| {
+                events.lines().find_map(|line| {
+                    line.strip_prefix("oom_kill ")
+                        .and_then(|n| n.trim().parse::<u64>().ok())
+                })
+            })
+            .is_some_and(|n| n > 0)
+    }
+}
+
+impl Drop for CgroupScope {
+    fn drop(&mut self) {
+        // Fails while background processes started by the run are still alive; leave it then
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Apply the configured limits to `cmd` before exec. Returns the run's cgroup scope,
+/// which the caller keeps alive until the child has exited.
+/// If the scope cannot be created the run continues with rlimits only.
+pub fn apply(cmd: &mut Command) -> Option<CgroupScope> {
+    apply_with(cmd, current())
+}
+
+fn apply_with(cmd: &mut Command, limits: &ResourceLimits) -> Option<CgroupScope> {
+    let scope = limits.cgroup_parent.as_ref().and_then(|parent| {
+        match CgroupScope::create(parent, limits) {
+            Ok(scope) => Some(scope),
+            Err(e) => {
+                tracing::warn!("cgroup disabled for this run: {e}");
+                None
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        const MIB: u64 = 1024 * 1024;
+        let rlimits: Vec<_> = [
+            (libc::RLIMIT_AS, limits.address_space_mb.map(|mb| mb * MIB)),
+            (libc::RLIMIT_CPU, limits.cpu_seconds),
+            (libc::RLIMIT_FSIZE, limits.file_size_mb.map(|mb| mb * MIB)),
+            (libc::RLIMIT_NPROC, limits.max_processes),
+        ]
+        .into_iter()
+        .filter_map(|(resource, value)| value.map(|v| (resource, v as libc::rlim_t)))
+        .collect();
+        let procs_file = scope.as_ref().and_then(|s| {
+            std::ffi::CString::new(s.path.join("cgroup.procs").to_string_lossy().as_bytes()).ok()
+        });
+
+        if !rlimits.is_empty() || procs_file.is_some() {
+            // SAFETY: the closure runs in the forked child before exec and only makes
+            // async-signal-safe calls (open, write, close, setrlimit) on data prepared
+            // before the fork, without allocating.
+            #[allow(unsafe_code)]
+            unsafe {
+                cmd.pre_exec(move || {
+                    if let Some(path) = &procs_file {
+                        // Writing "0" moves the writing process (this child) into the group
+                        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+                        if fd < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                        libc::close(fd);
+                        if written != 1 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    for (resource, value) in &rlimits {
+                        let rlim = libc::rlimit {
+                            rlim_cur: *value,
+                            rlim_max: *value,
+                        };
+                        if libc::setrlimit(*resource, &rlim) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    scope
+}
+
+/// Signal that ended a process: from the exit status, or from a shell's 128+N exit code
+fn termination_signal(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return Some(signal);
+        }
+    }
+    status
+        .code()
+        .filter(|c| *c > 128 && *c < 160)
+        .map(|c| c - 128)
+}
+
+/// Explain which limit a run most likely hit, given how it ended
+fn limit_violation(
+    signal: Option<i32>,
+    stderr: &str,
+    oom_killed: bool,
+    limits: &ResourceLimits,
+) -> Option<String> {
+    #[cfg(unix)]
+    {
+        match (signal, limits.cpu_seconds, limits.file_size_mb) {
+            (Some(libc::SIGXCPU), Some(secs), _) => {
+                return Some(format!("CPU time limit exceeded ({} s)", secs));
+            }
+            (Some(libc::SIGXFSZ), _, Some(mb)) => {
+                return Some(format!("File size limit exceeded ({} MB)", mb));
+            }
+            _ => {}
+        }
+    }
+    let lower = stderr.to_lowercase();
+    let out_of_memory = lower.contains("cannot allocate memory") || lower.contains("out of memory");
+    if let Some(mb) = limits.memory_mb {
+        if oom_killed || out_of_memory {
+            return Some(format!("Memory limit exceeded ({} MB)", mb));
+        }
+    }
+    if let Some(mb) = limits.address_space_mb {
+        if out_of_memory {
+            return Some(format!("Address space limit exceeded ({} MB)", mb));
+        }
+    }
+    if let Some(n) = limits.max_processes {
+        if stderr.contains("Resource temporarily unavailable") {
+            return Some(format!("Process limit reached ({} processes)", n));
+        }
+    }
+    None
+}
+
+/// Describe a resource limit violation for the chat, if the run ended because of one
+pub fn describe_exit(
+    status: &ExitStatus,
+    stderr: &str,
+    scope: Option<&CgroupScope>,
+) -> Option<String> {
+    let oom_killed = scope.is_some_and(|s| s.oom_killed());
+    limit_violation(termination_signal(status), stderr, oom_killed, current())
+        .map(|reason| format!("⛔ {}", reason))
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn limits() -> ResourceLimits {
+        ResourceLimits {
+            memory_mb: Some(512),
+            address_space_mb: None,
+            cpu_seconds: Some(60),
+            file_size_mb: Some(100),
+            max_processes: Some(64),
+            cgroup_parent: None,
+        }
+    }
+
+    #[test]
+    fn test_limit_violation_signals() {
+        assert_eq!(
+            limit_violation(Some(libc::SIGXCPU), "", false, &limits()).as_deref(),
+            Some("CPU time limit exceeded (60 s)")
+        );
+        assert_eq!(
+            limit_violation(Some(libc::SIGXFSZ), "", false, &limits()).as_deref(),
+            Some("File size limit exceeded (100 MB)")
+        );
+        assert_eq!(
+            limit_violation(Some(libc::SIGKILL), "", true, &limits()).as_deref(),
+            Some("Memory limit exceeded (512 MB)")
+        );
+        // Not configured: not reported as a limit
+        assert!(
+            limit_violation(Some(libc::SIGXCPU), "", false, &ResourceLimits::default()).is_none()
+        );
+        assert!(limit_violation(Some(libc::SIGTERM), "", false, &limits()).is_none());
+    }
+
+    #[test]
+    fn test_limit_violation_stderr() {
+        assert_eq!(
+            limit_violation(
+                None,
+                "bash: fork: Resource temporarily unavailable",
+                false,
+                &limits()
+            )
+            .as_deref(),
+            Some("Process limit reached (64 processes)")
+        );
+        assert_eq!(
+            limit_violation(None, "x: Cannot allocate memory", false, &limits()).as_deref(),
+            Some("Memory limit exceeded (512 MB)")
+        );
+        let address_space = ResourceLimits {
+            address_space_mb: Some(256),
+            ..ResourceLimits::default()
+        };
+        assert_eq!(
+            limit_violation(None, "x: Cannot allocate memory", false, &address_space).as_deref(),
+            Some("Address space limit exceeded (256 MB)")
+        );
+        // Without a cgroup an OOM kill is not attributed to the address space limit
+        assert!(limit_violation(Some(libc::SIGKILL), "", true, &address_space).is_none());
+    }
+
+    #[test]
+    fn test_file_size_limit_reported() {
+        let dir = std::env::temp_dir().join(format!("openclaude-limits-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        let limits = ResourceLimits {
+            file_size_mb: Some(1),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "head -c 2097152 /dev/zero > big.bin"])
+            .current_dir(&dir);
+        let scope = apply_with(&mut cmd, &limits);
+        assert!(scope.is_none());
+        let output = cmd.output().expect("run sh");
+        let written = std::fs::metadata(dir.join("big.bin")).map(|m| m.len());
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(!output.status.success());
+        assert_eq!(written.ok(), Some(1024 * 1024));
+        let signal = termination_signal(&output.status);
+        assert_eq!(
+            limit_violation(signal, "", false, &limits).as_deref(),
+            Some("File size limit exceeded (1 MB)")
+        );
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::claude::{
+    self, CancelToken, ModelOptions, ProjectOptions, RunOptions, StreamDecoder, StreamMessage,
+};
+use crate::limits::{self, CgroupScope};
+use crate::trace::RunTrace;
+
+/// How often idle processes are looked for
+const REAP_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Idle time after which a persistent process is stopped; unset when
+/// persistent mode is off
+static IDLE_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// Persistent process of each chat with one
+static PROCESSES: OnceLock<Mutex<HashMap<i64, Arc<LiveProcess>>>> = OnceLock::new();
+
+/// Turn on persistent mode: each chat keeps one CLI process that reads prompts
+/// as stream-json messages, stopped after `idle_timeout` without a run
+pub fn configure(idle_timeout: Duration) {
+    if IDLE_TIMEOUT.set(idle_timeout).is_ok() {
+        std::thread::spawn(reap_idle_processes);
+    }
+}
+
+pub fn enabled() -> bool {
+    IDLE_TIMEOUT.get().is_some()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn processes() -> MutexGuard<'static, HashMap<i64, Arc<LiveProcess>>> {
+    lock(PROCESSES.get_or_init(|| Mutex::new(HashMap::new())))
+}
+
+/// What a process was started with. A run that needs anything different
+/// gets a new process.
+#[derive(Debug, Clone, PartialEq)]
+struct Spec {
+    working_dir: String,
+    enforced: Option<Vec<String>>,
+    thinking_budget: Option<u32>,
+    model: ModelOptions,
+    project: ProjectOptions,
+    /// Given to --append-system-prompt
+    system_prompt: Option<String>,
+}
+
+/// A CLI process started with `--input-format stream-json`, kept between prompts
+struct LiveProcess {
+    spec: Spec,
+    pid: u32,
+    child: Mutex<Child>,
+    stdin: Mutex<ChildStdin>,
+    /// Stdout lines, read by a separate thread; disconnected when the process exits
+    lines: Mutex<Receiver<String>>,
+    stderr: Arc<Mutex<String>>,
+    /// Conversation the process is in, from its init messages
+    session_id: Mutex<Option<String>>,
+    /// User messages written whose result has not arrived yet. Steering
+    /// messages are only written while this is above zero.
+    pending_turns: Mutex<usize>,
+    last_used: Mutex<Instant>,
+    /// Kept until the process has exited (see `limits::apply`)
+    cgroup_scope: Option<CgroupScope>,
+}
+
+impl LiveProcess {
+    fn spawn(
+        spec: Spec,
+        session_id: Option<&str>,
+        trace: Option<&mut RunTrace>,
+    ) -> Result<Self, String> {
+        let ai_bin = claude::get_ai_binary_path().ok_or_else(|| {
+            format!(
+                "{} CLI not found. Is {} CLI installed?",
+                claude::ai_binary_name(),
+                claude::ai_binary_name()
+            )
+        })?;
+        let args = claude::ai_args(
+            session_id,
+            spec.enforced.as_deref(),
+            &spec.model,
+            &spec.project,
+            spec.system_prompt.as_deref(),
+        )?;
+        tracing::debug!(command = ai_bin, ?args, "Starting persistent AI process");
+        if let Some(trace) = trace {
+            trace.argv(ai_bin, &args, &spec.working_dir);
+        }
+
+        let (mut child, cgroup_scope) = claude::spawn_ai_process(
+            ai_bin,
+            &args,
+            &spec.working_dir,
+            &spec.project.add_dirs,
+            spec.thinking_budget,
+        )?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| "Failed to capture stdin".to_string())?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| "Failed to capture stdout".to_string())?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| "Failed to capture stderr".to_string())?;
+
+        let (line_tx, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if line_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let stderr_buf = Arc::new(Mutex::new(String::new()));
+        let stderr_sink = stderr_buf.clone();
+        std::thread::spawn(move || {
+            let mut buf = String::new();
+            let _ = BufReader::new(stderr).read_to_string(&mut buf);
+            lock(&stderr_sink).push_str(&buf);
+        });
+
+        Ok(LiveProcess {
+            spec,
+            pid: child.id(),
+            child: Mutex::new(child),
+            stdin: Mutex::new(stdin),
+            lines: Mutex::new(lines),
+            stderr: stderr_buf,
+            session_id: Mutex::new(session_id.map(String::from)),
+            pending_turns: Mutex::new(0),
+            last_used: Mutex::new(Instant::now()),
+            cgroup_scope,
+        })
+    }
+
+    fn write_line(&self, line: &str) -> Result<(), String> {
+        let mut stdin = lock(&self.stdin);
+        writeln!(stdin, "{}", line)
+            .and_then(|_| stdin.flush())
+            .map_err(|e| format!("Failed to write to Claude stdin: {}", e))
+    }
+
+    /// Write a user message and count the turn it starts
+    fn send_prompt(&self, text: &str, attachments: &[Value]) -> Result<(), String> {
+        let mut pending = lock(&self.pending_turns);
+        self.write_line(&claude::user_message_line(text, attachments))?;
+        *pending += 1;
+        Ok(())
+    }
+
+    fn is_busy(&self) -> bool {
+        *lock(&self.pending_turns) > 0
+    }
+
+    fn kill(&self) {
+        let mut child = lock(&self.child);
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// How a run on a persistent process ended
+enum Outcome {
+    /// Every turn got its result; the process stays for the next prompt
+    Finished,
+    Cancelled,
+    /// The process exited; holds the decoder for its final Done
+    Exited(StreamDecoder),
+}
+
+/// Stream the replies to the prompts written so far until the last result
+/// arrives. Done is only forwarded for the final one, so steering messages
+/// sent mid-run extend the same run.
+fn pump(
+    process: &LiveProcess,
+    sender: &Sender<StreamMessage>,
+    cancel_token: Option<&CancelToken>,
+    mut trace: Option<&mut RunTrace>,
+) -> Outcome {
+    let lines = lock(&process.lines);
+    let mut decoder = StreamDecoder::default();
+    loop {
+        if cancel_token.is_some_and(|t| t.cancelled.load(Ordering::Relaxed)) {
+            return Outcome::Cancelled;
+        }
+        let line = match lines.recv_timeout(Duration::from_millis(200)) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Outcome::Exited(decoder),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        tracing::trace!(line, "stream line");
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.stdout_line(line);
+        }
+        let Ok(json) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+
+        if json.get("type").and_then(|v| v.as_str()) == Some("control_request") {
+            let response = claude::answer_control_request(&json, sender, cancel_token);
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.control_response(&response);
+            }
+            if let Err(e) = process.write_line(&response.to_string()) {
+                tracing::warn!("Failed to answer permission request: {e}");
+            }
+            continue;
+        }
+
+        for msg in decoder.decode(&json) {
+            match &msg {
+                StreamMessage::Init { session_id, .. } => {
+                    *lock(&process.session_id) = Some(session_id.clone());
+                }
+                StreamMessage::Done { .. } => {
+                    let mut pending = lock(&process.pending_turns);
+                    *pending = pending.saturating_sub(1);
+                    if *pending > 0 {
+                        continue;
+                    }
+                    drop(pending);
+                    let _ = sender.send(msg);
+                    return Outcome::Finished;
+                }
+                _ => {}
+            }
+            if sender.send(msg).is_err() {
+                tracing::debug!("Receiver dropped while streaming");
+            }
+        }
+    }
+}
+
+/// The chat's process if it can take a prompt for `spec` in `session_id`,
+/// otherwise a new one (stopping the old)
+fn acquire(
+    chat_id: i64,
+    spec: &Spec,
+    session_id: Option<&str>,
+    trace: Option<&mut RunTrace>,
+) -> Result<Arc<LiveProcess>, String> {
+    let existing = processes().remove(&chat_id);
+    if let Some(process) = existing {
+        let alive = matches!(lock(&process.child).try_wait(), Ok(None));
+        let same_session = lock(&process.session_id).as_deref() == session_id;
+        if alive && same_session && process.spec == *spec {
+            tracing::debug!(pid = process.pid, "Reusing persistent AI process");
+            processes().insert(chat_id, process.clone());
+            return Ok(process);
+        }
+        tracing::debug!(pid = process.pid, "Replacing persistent AI process");
+        process.kill();
+    }
+    let process = Arc::new(LiveProcess::spawn(spec.clone(), session_id, trace)?);
+    processes().insert(chat_id, process.clone());
+    Ok(process)
+}
+
+/// Run a prompt on the chat's persistent process, starting one (resuming
+/// `session_id`) if there is none or it cannot be reused. Sends the same
+/// messages as `claude::execute_command_streaming`.
+#[allow(clippy::too_many_arguments)]
+pub fn execute(
+    chat_id: i64,
+    prompt: &str,
+    session_id: Option<&str>,
+    working_dir: &str,
+    sender: Sender<StreamMessage>,
+    system_prompt: Option<&str>,
+    options: &RunOptions,
+    cancel_token: Option<Arc<CancelToken>>,
+    mut trace: Option<&mut RunTrace>,
+) -> Result<(), String> {
+    let spec = Spec {
+        working_dir: working_dir.to_string(),
+        enforced: options.tools.enforced.clone(),
+        thinking_budget: options.thinking_budget,
+        model: options.model.clone(),
+        project: options.project.clone(),
+        system_prompt: claude::build_system_prompt(system_prompt, options.tools.allowed.as_deref()),
+    };
+    let mut resume = session_id.map(String::from);
+    let mut retried = false;
+
+    loop {
+        let process = acquire(chat_id, &spec, resume.as_deref(), trace.as_deref_mut())?;
+        if let Some(token) = &cancel_token {
+            if let Ok(mut guard) = token.child_pid.lock() {
+                *guard = Some(process.pid);
+            }
+        }
+        // A failed write means the process is gone; pump reports how it exited
+        if let Err(e) = process.send_prompt(prompt, &options.attachments) {
+            tracing::debug!("{e}");
+        }
+
+        let outcome = pump(
+            &process,
+            &sender,
+            cancel_token.as_deref(),
+            trace.as_deref_mut(),
+        );
+        *lock(&process.last_used) = Instant::now();
+        let decoder = match outcome {
+            Outcome::Finished => return Ok(()),
+            Outcome::Cancelled => {
+                tracing::debug!("Cancel detected — killing persistent AI process");
+                forget(chat_id, &process);
+                process.kill();
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.cancelled();
+                }
+                return Ok(());
+            }
+            Outcome::Exited(decoder) => decoder,
+        };
+
+        forget(chat_id, &process);
+        let status = lock(&process.child)
+            .wait()
+            .map_err(|e| format!("Claude process wait failed: {}", e))?;
+        // Give the stderr thread a moment to collect the last output
+        std::thread::sleep(Duration::from_millis(50));
+        let stderr = lock(&process.stderr).clone();
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.exit(&status, &stderr);
+        }
+        if !status.success() {
+            if !retried
+                && resume.is_some()
+                && stderr.to_lowercase().contains("no conversation found")
+            {
+                tracing::debug!("Stale session detected — retrying without --resume");
+                resume = None;
+                retried = true;
+                continue;
+            }
+            let mut message = claude::exit_error_message(status.code(), &stderr);
+            if let Some(reason) =
+                limits::describe_exit(&status, &stderr, process.cgroup_scope.as_ref())
+            {
+                message = format!("{}\n{}", reason, message);
+            }
+            let _ = sender.send(StreamMessage::Error { message });
+        }
+        if let Some(done) = decoder.finish() {
+            let _ = sender.send(done);
+        }
+        return Ok(());
+    }
+}
+
+/// Remove `process` from the registry if it is still the chat's process
+fn forget(chat_id: i64, process: &Arc<LiveProcess>) {
+    let mut map = processes();
+    if map.get(&chat_id).is_some_and(|p| Arc::ptr_eq(p, process)) {
+        map.remove(&chat_id);
+    }
+}
+
+/// Write `text` into the chat's process while it is answering a prompt; the
+/// reply streams into the run in progress. Returns false if no run is in
+/// progress on a persistent process.
+pub fn steer(chat_id: i64, text: &str) -> bool {
+    let Some(process) = processes().get(&chat_id).cloned() else {
+        return false;
+    };
+    let mut pending = lock(&process.pending_turns);
+    if *pending == 0 {
+        return false;
+    }
+    match process.write_line(&claude::user_message_line(text, &[])) {
+        Ok(()) => {
+            *pending += 1;
+            true
+        }
+        Err(e) => {
+            tracing::warn!("{e}");
+            false
+        }
+    }
+}
+
+/// Stop processes that have been idle longer than the configured timeout
+fn reap_idle_processes() {
+    loop {
+        std::thread::sleep(REAP_INTERVAL);
+        let Some(timeout) = IDLE_TIMEOUT.get() else {
+            return;
+        };
+        let idle: Vec<Arc<LiveProcess>> = {
+            let mut map = processes();
+            let expired: Vec<i64> = map
+                .iter()
+                .filter(|(_, p)| !p.is_busy() && lock(&p.last_used).elapsed() >= *timeout)
+                .map(|(chat_id, _)| *chat_id)
+                .collect();
+            expired.iter().filter_map(|id| map.remove(id)).collect()
+        };
+        for process in idle {
+            tracing::info!(pid = process.pid, "Stopping idle AI process");
+            process.kill();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steer_without_process() {
+        assert!(!steer(-1, "hello"));
+    }
+
+    #[test]
+    fn test_spec_change_needs_new_process() {
+        let spec = Spec {
+            working_dir: "/tmp".to_string(),
+            enforced: None,
+            thinking_budget: None,
+            model: ModelOptions::default(),
+            project: ProjectOptions::default(),
+            system_prompt: None,
+        };
+        let mut other = spec.clone();
+        assert_eq!(spec, other);
+        other.thinking_budget = Some(2048);
+        assert_ne!(spec, other);
+    }
+}
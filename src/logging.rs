@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sha2::{Digest, Sha256};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{Builder, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+/// Daily log files kept in the log directory
+const KEEP_LOG_FILES: usize = 7;
+
+/// Console log format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+/// Default log directory: ~/<app_dir>/logs
+pub fn default_log_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|h| h.join(crate::app::dir_name()).join("logs"))
+}
+
+/// Install the global subscriber: console output in `format`, plus daily-rotated
+/// JSON files in `log_dir`. `RUST_LOG` overrides `level` when set.
+/// Keep the returned guard alive until exit so buffered file output is flushed.
+pub fn init(
+    level: tracing::Level,
+    format: LogFormat,
+    log_dir: Option<PathBuf>,
+) -> Option<WorkerGuard> {
+    // Dependencies (teloxide, reqwest, hyper) stay at warn unless RUST_LOG says otherwise
+    let filter = || {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+            EnvFilter::new(format!("warn,{}={}", env!("CARGO_CRATE_NAME"), level))
+        })
+    };
+
+    let console = match format {
+        LogFormat::Pretty => fmt::layer().with_target(false).boxed(),
+        LogFormat::Json => fmt::layer().json().with_current_span(true).boxed(),
+    };
+
+    let appender = log_dir.and_then(|dir| {
+        Builder::new()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(env!("CARGO_BIN_NAME"))
+            .filename_suffix("log")
+            .max_log_files(KEEP_LOG_FILES)
+            .build(&dir)
+            .map_err(|e| eprintln!("Log files disabled ({}): {}", dir.display(), e))
+            .ok()
+    });
+    let (file, guard) = match appender {
+        Some(appender) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_ansi(false)
+                .with_writer(writer)
+                .with_filter(filter());
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(console.with_filter(filter()))
+        .with(file)
+        .init();
+    guard
+}
+
+/// Short random-looking id correlating one AI run across logs, audit entries and error replies
+pub fn new_run_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut hasher = Sha256::new();
+    hasher.update(nanos.to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    hex::encode(&hasher.finalize()[..4])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_ids_unique() {
+        let a = new_run_id();
+        let b = new_run_id();
+        assert_eq!(a.len(), 8);
+        assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+}
@@ -1,8 +1,21 @@
 mod app;
+mod audit;
 mod auth;
+mod checkpoint;
+mod child_env;
 mod claude;
+mod confine;
+mod git;
+mod limits;
+mod live;
+mod logging;
+mod media;
+mod redact;
 mod session;
+mod shell_guard;
+mod snapshot;
 mod telegram;
+mod trace;
 
 use std::env;
 use std::fs;
@@ -28,6 +41,84 @@ struct Cli {
     #[arg(long)]
     madmax: bool,
 
+    /// Extra environment variables to hide from Claude and shell commands
+    /// (comma-separated, `*` wildcard allowed, e.g. `DB_*,INTERNAL_API_KEY`)
+    #[arg(long, value_name = "VARS", value_delimiter = ',')]
+    env_deny: Vec<String>,
+
+    /// Pass only these environment variables (plus PATH, HOME, LANG and similar)
+    /// to Claude and shell commands (comma-separated, `*` wildcard allowed)
+    #[arg(long, value_name = "VARS", value_delimiter = ',')]
+    env_allow: Option<Vec<String>>,
+
+    /// Memory limit per run (cgroup memory.max) for Claude and shell commands,
+    /// in MiB. Needs --cgroup
+    #[arg(long, value_name = "MB")]
+    limit_memory: Option<u64>,
+
+    /// Address space limit (RLIMIT_AS) per process for Claude and shell commands,
+    /// in MiB. Node-based CLIs reserve several GiB of virtual memory and fail to
+    /// start under a low value; use --limit-memory with --cgroup for Claude
+    #[arg(long, value_name = "MB")]
+    limit_address_space: Option<u64>,
+
+    /// CPU time limit per process for Claude and shell commands, in seconds
+    #[arg(long, value_name = "SECS")]
+    limit_cpu: Option<u64>,
+
+    /// Largest file Claude and shell commands may write, in MiB
+    #[arg(long, value_name = "MB")]
+    limit_file_size: Option<u64>,
+
+    /// Maximum number of processes for the bot's user while a command runs
+    #[arg(long, value_name = "N")]
+    limit_procs: Option<u64>,
+
+    /// Delegated cgroup v2 directory; each run gets its own group with
+    /// memory.max / pids.max from --limit-memory / --limit-procs
+    #[arg(long, value_name = "DIR")]
+    cgroup: Option<std::path::PathBuf>,
+
+    /// Confine Claude and shell commands to the session directory (plus read-only
+    /// system directories) with Landlock. Linux 5.13+; warns and runs unconfined otherwise
+    #[arg(long)]
+    confine: bool,
+
+    /// Extra directories writable under --confine (comma-separated)
+    #[arg(long, value_name = "DIRS", value_delimiter = ',', requires = "confine")]
+    confine_allow: Vec<std::path::PathBuf>,
+
+    /// Keep one Claude process per chat between prompts instead of starting one per
+    /// prompt; messages sent during a run are passed to it as they arrive
+    #[arg(long)]
+    persistent: bool,
+
+    /// With --persistent, stop a chat's Claude process after this many idle seconds
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 600,
+        requires = "persistent"
+    )]
+    idle_timeout: u64,
+
+    /// Log level for openclaude itself (trace, debug, info, warn, error); RUST_LOG overrides
+    #[arg(long, value_name = "LEVEL", default_value = "info")]
+    log_level: tracing::Level,
+
+    /// Console log format
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "pretty")]
+    log_format: logging::LogFormat,
+
+    /// Directory for daily-rotated JSON log files (default: ~/.openclaude/logs)
+    #[arg(long, value_name = "DIR")]
+    log_dir: Option<PathBuf>,
+
+    /// Render a captured run (a /trace file) offline and print the messages
+    /// the bot would send for it, then exit
+    #[arg(long, value_name = "TRACE_FILE")]
+    replay: Option<PathBuf>,
+
     /// Internal: send file to Telegram (used by AI output automation)
     #[arg(long, value_name = "FILE_PATH")]
     sendfile: Option<String>,
@@ -187,6 +278,19 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(path) = cli.replay.as_deref() {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        print!("{}", telegram::replay_transcript(&content));
+        return Ok(());
+    }
+
+    let _log_guard = logging::init(
+        cli.log_level,
+        cli.log_format,
+        cli.log_dir.or_else(logging::default_log_dir),
+    );
+
     let project_dir = cli.project_dir.as_deref().context(format!(
         "Usage: {} <project_dir> [--token <TOKEN>] [--madmax]",
         env!("CARGO_BIN_NAME"),
@@ -204,10 +308,44 @@ async fn main() -> Result<()> {
 
     let token = resolve_token(cli.token)?;
     validate_telegram_token(&token).await?;
+    child_env::configure(
+        child_env::EnvPolicy {
+            deny: cli.env_deny,
+            allow: cli.env_allow,
+        },
+        &token,
+    );
+    if cli.limit_memory.is_some() && cli.cgroup.is_none() {
+        tracing::warn!("--limit-memory needs --cgroup; no memory limit will be applied");
+    }
+    limits::configure(limits::ResourceLimits {
+        memory_mb: cli.limit_memory,
+        address_space_mb: cli.limit_address_space,
+        cpu_seconds: cli.limit_cpu,
+        file_size_mb: cli.limit_file_size,
+        max_processes: cli.limit_procs,
+        cgroup_parent: cli.cgroup,
+    });
+    if cli.confine {
+        match confine::configure(cli.confine_allow) {
+            Ok(abi) => tracing::info!(abi, "Landlock confinement enabled"),
+            Err(e) => tracing::warn!("{} — running without confinement", e),
+        }
+    }
 
-    println!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
-    println!("project_dir: {}", canonical_project);
-    println!("status: connecting Telegram bot...");
+    if cli.persistent {
+        live::configure(std::time::Duration::from_secs(cli.idle_timeout));
+        tracing::info!(
+            idle_timeout = cli.idle_timeout,
+            "Persistent Claude processes enabled"
+        );
+    }
+
+    tracing::info!(
+        version = env!("CARGO_PKG_VERSION"),
+        project_dir = %canonical_project,
+        "Connecting Telegram bot"
+    );
 
     telegram::run_bot(&token, &canonical_project).await;
 
@@ -0,0 +1,164 @@
+use std::io::Cursor;
+
+use base64::Engine;
+use image::{GenericImageView, ImageFormat, ImageReader};
+use serde_json::Value;
+
+/// Longest image edge sent to the model; the API scales larger images down anyway
+const MAX_IMAGE_EDGE: u32 = 1568;
+
+/// Largest image the API accepts, before base64 encoding
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Base64 data attached to one prompt, in total; the API refuses requests over 32 MB
+pub(crate) const MAX_PROMPT_ATTACHMENT_BYTES: usize = 20 * 1024 * 1024;
+
+/// Largest PDF attached as a document block, so that it fits in one prompt once encoded
+const MAX_PDF_BYTES: usize = MAX_PROMPT_ATTACHMENT_BYTES / 4 * 3;
+
+/// Content block for an uploaded file, to be sent with the next prompt.
+/// Images are scaled down and re-encoded when too large; PDFs are attached
+/// as documents. Returns None for other files, which Claude reads from disk.
+pub(crate) fn content_block(bytes: &[u8]) -> Result<Option<Value>, String> {
+    if bytes.starts_with(b"%PDF-") {
+        if bytes.len() > MAX_PDF_BYTES {
+            return Err(format!(
+                "PDF larger than {} MiB",
+                MAX_PDF_BYTES / 1024 / 1024
+            ));
+        }
+        return Ok(Some(base64_block("document", "application/pdf", bytes)));
+    }
+
+    let Ok(format) = image::guess_format(bytes) else {
+        return Ok(None);
+    };
+    let Some(media_type) = media_type(format) else {
+        return Err(format!(
+            "{} images are not supported (use PNG, JPEG, GIF or WebP)",
+            format.extensions_str().first().unwrap_or(&"these")
+        ));
+    };
+
+    let reader = ImageReader::with_format(Cursor::new(bytes), format);
+    let (width, height) = reader
+        .into_dimensions()
+        .map_err(|e| format!("Unreadable image: {}", e))?;
+    if width.max(height) <= MAX_IMAGE_EDGE && bytes.len() <= MAX_IMAGE_BYTES {
+        return Ok(Some(base64_block("image", media_type, bytes)));
+    }
+
+    let (normalized, media_type) = downscale(bytes, format)?;
+    if normalized.len() > MAX_IMAGE_BYTES {
+        return Err("Image too large even after scaling down".to_string());
+    }
+    Ok(Some(base64_block("image", media_type, &normalized)))
+}
+
+/// Size of a content block's base64 data
+pub(crate) fn encoded_len(block: &Value) -> usize {
+    block["source"]["data"].as_str().map_or(0, str::len)
+}
+
+/// Media type of the image formats the API accepts
+fn media_type(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Png => Some("image/png"),
+        ImageFormat::Jpeg => Some("image/jpeg"),
+        ImageFormat::Gif => Some("image/gif"),
+        ImageFormat::WebP => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Fit the image within MAX_IMAGE_EDGE and re-encode it: PNG when it has
+/// transparency, JPEG otherwise
+fn downscale(bytes: &[u8], format: ImageFormat) -> Result<(Vec<u8>, &'static str), String> {
+    let img = image::load_from_memory_with_format(bytes, format)
+        .map_err(|e| format!("Unreadable image: {}", e))?;
+    let (width, height) = img.dimensions();
+    let img = if width.max(height) > MAX_IMAGE_EDGE {
+        img.resize(
+            MAX_IMAGE_EDGE,
+            MAX_IMAGE_EDGE,
+            image::imageops::FilterType::Triangle,
+        )
+    } else {
+        img
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    let media_type = if img.color().has_alpha() {
+        img.write_to(&mut out, ImageFormat::Png)
+            .map(|_| "image/png")
+    } else {
+        img.to_rgb8()
+            .write_to(&mut out, ImageFormat::Jpeg)
+            .map(|_| "image/jpeg")
+    }
+    .map_err(|e| format!("Failed to re-encode image: {}", e))?;
+    Ok((out.into_inner(), media_type))
+}
+
+fn base64_block(block_type: &str, media_type: &str, bytes: &[u8]) -> Value {
+    serde_json::json!({
+        "type": block_type,
+        "source": {
+            "type": "base64",
+            "media_type": media_type,
+            "data": base64::engine::general_purpose::STANDARD.encode(bytes),
+        },
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn encode(img: image::DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        img.write_to(&mut out, format).expect("encode");
+        out.into_inner()
+    }
+
+    fn decoded(block: &Value) -> Vec<u8> {
+        let data = block["source"]["data"].as_str().expect("data");
+        base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .expect("base64")
+    }
+
+    #[test]
+    fn test_small_image_passes_through() {
+        let png = encode(image::DynamicImage::new_rgba8(32, 16), ImageFormat::Png);
+        let block = content_block(&png).expect("ok").expect("block");
+        assert_eq!(block["type"], "image");
+        assert_eq!(block["source"]["media_type"], "image/png");
+        assert_eq!(decoded(&block), png);
+    }
+
+    #[test]
+    fn test_large_image_is_scaled_down() {
+        let png = encode(image::DynamicImage::new_rgb8(3000, 1000), ImageFormat::Png);
+        let block = content_block(&png).expect("ok").expect("block");
+        assert_eq!(block["source"]["media_type"], "image/jpeg");
+        let img = image::load_from_memory(&decoded(&block)).expect("decode");
+        assert_eq!(img.dimensions(), (MAX_IMAGE_EDGE, 523));
+    }
+
+    #[test]
+    fn test_pdf_and_other_files() {
+        let block = content_block(b"%PDF-1.7\n...").expect("ok").expect("block");
+        assert_eq!(block["type"], "document");
+        assert_eq!(block["source"]["media_type"], "application/pdf");
+        assert_eq!(encoded_len(&block), 16);
+
+        let mut large = b"%PDF-".to_vec();
+        large.resize(MAX_PDF_BYTES + 1, b' ');
+        assert!(content_block(&large).is_err());
+
+        assert!(content_block(b"fn main() {}\n").expect("ok").is_none());
+        assert!(content_block(b"BM\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Directories skipped while scanning (build output, dependencies, VCS metadata)
+pub const SKIP_DIRS: &[&str] = &[
+    ".git",
+    "node_modules",
+    "target",
+    ".venv",
+    "venv",
+    "__pycache__",
+    "dist",
+    "build",
+    ".next",
+];
+
+/// Upper bound on files recorded per scan, so huge trees stay cheap
+const MAX_FILES: usize = 20_000;
+
+/// Modification time and size of a file at scan time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+/// mtime/size view of a working tree, used to detect what an AI turn changed
+#[derive(Debug, Clone)]
+pub struct TreeSnapshot {
+    root: PathBuf,
+    files: HashMap<PathBuf, FileStamp>,
+    /// True if MAX_FILES was hit and some files were not recorded
+    truncated: bool,
+}
+
+impl TreeSnapshot {
+    /// True if the tree had more than MAX_FILES files
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Combined size of all recorded files in bytes
+    pub fn total_size(&self) -> u64 {
+        self.files.values().map(|f| f.size).sum()
+    }
+}
+
+/// Files created, modified and deleted between two snapshots
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSummary {
+    pub created: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+}
+
+impl ChangeSummary {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.modified.is_empty() && self.deleted.is_empty()
+    }
+
+    /// One-line summary, e.g. "Files changed: +2 ~3 -1"
+    pub fn headline(&self) -> String {
+        format!(
+            "Files changed: +{} ~{} -{}",
+            self.created.len(),
+            self.modified.len(),
+            self.deleted.len()
+        )
+    }
+
+    /// All changed paths with their marker (+ created, ~ modified, - deleted), sorted by path
+    pub fn entries(&self) -> Vec<(char, &Path)> {
+        let mut entries: Vec<(char, &Path)> = self
+            .created
+            .iter()
+            .map(|p| ('+', p.as_path()))
+            .chain(self.modified.iter().map(|p| ('~', p.as_path())))
+            .chain(self.deleted.iter().map(|p| ('-', p.as_path())))
+            .collect();
+        entries.sort_by(|a, b| a.1.cmp(b.1));
+        entries
+    }
+}
+
+fn stamp(metadata: &fs::Metadata) -> FileStamp {
+    FileStamp {
+        modified: metadata.modified().ok(),
+        size: metadata.len(),
+    }
+}
+
+/// Record mtime and size for every file under `root`, skipping SKIP_DIRS.
+/// Symlinks are not followed.
+pub fn scan(root: &Path) -> TreeSnapshot {
+    let mut files = HashMap::new();
+    let mut truncated = false;
+    let mut stack = vec![root.to_path_buf()];
+
+    'walk: while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                let name = entry.file_name();
+                if !SKIP_DIRS.iter().any(|s| name == *s) {
+                    stack.push(path);
+                }
+            } else if file_type.is_file() {
+                if files.len() >= MAX_FILES {
+                    truncated = true;
+                    break 'walk;
+                }
+                if let Ok(metadata) = entry.metadata() {
+                    files.insert(path, stamp(&metadata));
+                }
+            }
+        }
+    }
+
+    TreeSnapshot {
+        root: root.to_path_buf(),
+        files,
+        truncated,
+    }
+}
+
+/// Whether `path` lies in the part of the tree a snapshot covers
+fn is_covered(snapshot: &TreeSnapshot, path: &Path) -> bool {
+    if snapshot.truncated {
+        return false;
+    }
+    let Ok(rel) = path.strip_prefix(&snapshot.root) else {
+        return false;
+    };
+    !rel.components()
+        .any(|c| SKIP_DIRS.iter().any(|s| c.as_os_str() == *s))
+}
+
+/// Compare two scans of the same root. `touched` lists files the AI reported
+/// writing (absolute paths); they are included even when outside the scanned area,
+/// classified by whether they exist now.
+pub fn compare(before: &TreeSnapshot, after: &TreeSnapshot, touched: &[PathBuf]) -> ChangeSummary {
+    let mut summary = ChangeSummary::default();
+
+    for (path, stamp) in &after.files {
+        match before.files.get(path) {
+            None if is_covered(before, path) => summary.created.push(path.clone()),
+            None => {}
+            Some(old) if old != stamp => summary.modified.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    for path in before.files.keys() {
+        if !after.files.contains_key(path) && is_covered(after, path) {
+            summary.deleted.push(path.clone());
+        }
+    }
+
+    for path in touched {
+        let known = summary.created.contains(path)
+            || summary.modified.contains(path)
+            || summary.deleted.contains(path);
+        if known || (is_covered(before, path) && is_covered(after, path)) {
+            continue;
+        }
+        if !path.exists() {
+            if before.files.contains_key(path) {
+                summary.deleted.push(path.clone());
+            }
+        } else if before.files.contains_key(path) || !is_covered(before, path) {
+            summary.modified.push(path.clone());
+        } else {
+            summary.created.push(path.clone());
+        }
+    }
+
+    summary.created.sort();
+    summary.modified.sort();
+    summary.deleted.sort();
+    summary
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "openclaude-snapshot-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    #[test]
+    fn test_compare_detects_created_modified_deleted() {
+        let root = temp_root("cmd");
+        fs::write(root.join("keep.txt"), "same").expect("write");
+        fs::write(root.join("edit.txt"), "old").expect("write");
+        fs::write(root.join("gone.txt"), "bye").expect("write");
+        let before = scan(&root);
+
+        fs::write(root.join("edit.txt"), "new content").expect("write");
+        fs::remove_file(root.join("gone.txt")).expect("remove");
+        fs::write(root.join("new.txt"), "hi").expect("write");
+        let after = scan(&root);
+
+        let summary = compare(&before, &after, &[]);
+        assert_eq!(summary.created, vec![root.join("new.txt")]);
+        assert_eq!(summary.modified, vec![root.join("edit.txt")]);
+        assert_eq!(summary.deleted, vec![root.join("gone.txt")]);
+        assert_eq!(summary.headline(), "Files changed: +1 ~1 -1");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_scan_skips_dependency_dirs() {
+        let root = temp_root("skip");
+        fs::create_dir_all(root.join("node_modules/pkg")).expect("mkdir");
+        fs::write(root.join("node_modules/pkg/index.js"), "x").expect("write");
+        fs::write(root.join("main.js"), "x").expect("write");
+
+        let snapshot = scan(&root);
+        assert_eq!(snapshot.files.len(), 1);
+        assert!(snapshot.files.contains_key(&root.join("main.js")));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_compare_includes_touched_outside_scan() {
+        let root = temp_root("touched");
+        let before = scan(&root);
+        let outside = root.join("node_modules").join("patched.js");
+        fs::create_dir_all(root.join("node_modules")).expect("mkdir");
+        fs::write(&outside, "x").expect("write");
+        let after = scan(&root);
+
+        let summary = compare(&before, &after, std::slice::from_ref(&outside));
+        assert_eq!(summary.modified, vec![outside]);
+        assert!(summary.created.is_empty());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_empty_summary() {
+        let summary = ChangeSummary::default();
+        assert!(summary.is_empty());
+        assert_eq!(summary.headline(), "Files changed: +0 ~0 -0");
+    }
+}
@@ -0,0 +1,112 @@
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::audit::{self, AuditEntry, AuditEvent};
+use crate::auth::CommandRisk;
+
+use super::bot::{shared_rate_limit_wait, SharedState};
+use super::streaming::{html_escape, redact_outbound, send_long_message, truncate_str};
+
+/// Default and maximum number of entries shown by /audit
+const AUDIT_DEFAULT_COUNT: usize = 20;
+const AUDIT_MAX_COUNT: usize = 100;
+
+/// Record a command or callback and the permission decision made for it
+pub(crate) async fn record_decision(
+    state: &SharedState,
+    event: AuditEvent,
+    user_id: u64,
+    chat_id: ChatId,
+    command: &str,
+    risk: CommandRisk,
+    allowed: bool,
+) {
+    let working_dir = {
+        let data = state.lock().await;
+        data.sessions
+            .get(&chat_id)
+            .and_then(|s| s.current_path.clone())
+    };
+    let command = redact_outbound(state, command).await;
+    let mut entry = AuditEntry::new(event, user_id, chat_id.0, &command, allowed);
+    entry.risk = Some(format!("{:?}", risk));
+    entry.working_dir = working_dir;
+    audit::record(&entry);
+}
+
+/// Short one-line rendering of an entry for /audit
+fn format_entry(e: &AuditEntry) -> String {
+    let when = chrono::DateTime::parse_from_rfc3339(&e.time)
+        .map(|t| t.format("%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|_| e.time.clone());
+    let mut line = format!(
+        "<code>{}</code> {} <code>{}</code>",
+        when,
+        e.user_id,
+        html_escape(&truncate_str(&e.command, 80))
+    );
+    match e.event {
+        AuditEvent::Command | AuditEvent::Callback => {
+            let mark = if e.allowed { "✅" } else { "⛔ denied" };
+            line.push_str(&format!(
+                " {} {}",
+                mark,
+                html_escape(e.risk.as_deref().unwrap_or(""))
+            ));
+        }
+        AuditEvent::Shell | AuditEvent::AiRun => {
+            if let Some(code) = e.exit_code {
+                line.push_str(&format!(" → exit {}", code));
+            }
+            if let Some(outcome) = &e.outcome {
+                line.push_str(&format!(" → {}", html_escape(outcome)));
+            }
+            if let Some(ms) = e.duration_ms {
+                line.push_str(&format!(" · {:.1}s", ms as f64 / 1000.0));
+            }
+            if !e.files_touched.is_empty() {
+                line.push_str(&format!(" · {} file(s)", e.files_touched.len()));
+            }
+            if let Some(run_id) = &e.run_id {
+                line.push_str(&format!(" · run <code>{}</code>", html_escape(run_id)));
+            }
+        }
+    }
+    line
+}
+
+/// Handle /audit [n] - show the newest audit log entries
+pub(crate) async fn handle_audit_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let arg = text.strip_prefix("/audit").unwrap_or("").trim();
+    let count = if arg.is_empty() {
+        AUDIT_DEFAULT_COUNT
+    } else {
+        match arg.parse::<usize>() {
+            Ok(n) if n > 0 => n.min(AUDIT_MAX_COUNT),
+            _ => {
+                shared_rate_limit_wait(state, chat_id).await;
+                bot.send_message(chat_id, "Usage: /audit [count]\nExample: /audit 50")
+                    .await?;
+                return Ok(());
+            }
+        }
+    };
+
+    let response = match tokio::task::spawn_blocking(move || audit::recent(count)).await {
+        Ok(entries) if entries.is_empty() => "Audit log is empty.".to_string(),
+        Ok(entries) => {
+            let mut lines = vec![format!("<b>Audit log</b> (last {})\n", entries.len())];
+            lines.extend(entries.iter().map(format_entry));
+            lines.join("\n")
+        }
+        Err(e) => format!("Task error: {}", html_escape(&e.to_string())),
+    };
+
+    send_long_message(bot, chat_id, &response, Some(ParseMode::Html), state).await?;
+    Ok(())
+}
@@ -4,8 +4,14 @@ use std::sync::Arc;
 use teloxide::prelude::*;
 use tokio::sync::Mutex;
 
-use crate::claude::CancelToken;
+use crate::claude::{CancelToken, McpServerStatus};
 
+use super::browser::BrowseView;
+use super::checkpoints::CheckpointView;
+use super::file_ops::PendingShell;
+use super::git_ops::{DiffView, PendingCommit};
+use super::interaction::PendingInteraction;
+use super::preview::CatView;
 use super::storage::{BotSettings, ChatSession};
 
 /// Shared state: per-chat sessions + bot settings
@@ -14,10 +20,30 @@ pub(crate) struct SharedData {
     pub(crate) settings: BotSettings,
     /// Per-chat cancel tokens for stopping in-progress AI requests
     pub(crate) cancel_tokens: HashMap<ChatId, Arc<CancelToken>>,
+    /// Per-chat user whose prompt the in-progress AI request is answering
+    pub(crate) run_users: HashMap<ChatId, u64>,
     /// Message ID of the "Stopping..." message sent by /stop, so the polling loop can update it
     pub(crate) stop_message_ids: HashMap<ChatId, teloxide::types::MessageId>,
     /// Per-chat timestamp of the last Telegram API call (for rate limiting)
     pub(crate) api_timestamps: HashMap<ChatId, tokio::time::Instant>,
+    /// Per-chat file browser state backing the /ls inline keyboard
+    pub(crate) browse_views: HashMap<ChatId, BrowseView>,
+    /// Per-chat /cat view backing its paging buttons
+    pub(crate) cat_views: HashMap<ChatId, CatView>,
+    /// Per-chat /diff file list backing its expand buttons
+    pub(crate) diff_views: HashMap<ChatId, DiffView>,
+    /// Per-chat /commit awaiting confirmation
+    pub(crate) pending_commits: HashMap<ChatId, PendingCommit>,
+    /// Per-chat /checkpoints list backing its restore buttons
+    pub(crate) checkpoint_views: HashMap<ChatId, CheckpointView>,
+    /// Per-chat destructive `!` command awaiting confirmation
+    pub(crate) pending_shells: HashMap<ChatId, PendingShell>,
+    /// Per-chat question or plan from the running AI, awaiting the user's answer
+    pub(crate) interactions: HashMap<ChatId, PendingInteraction>,
+    /// Per-chat MCP servers reported by the last AI run, for /mcp list
+    pub(crate) mcp_status: HashMap<ChatId, Vec<McpServerStatus>>,
+    /// The owner's /redact patterns, compiled; rebuilt whenever the list changes
+    pub(crate) redact_regexes: Vec<regex::Regex>,
 }
 
 pub(crate) type SharedState = Arc<Mutex<SharedData>>;
@@ -0,0 +1,459 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode};
+
+use crate::auth::is_path_within_sandbox;
+
+use super::bot::{shared_rate_limit_wait, SharedState};
+use super::commands::change_current_path;
+use super::preview::send_file_page;
+use super::streaming::{html_escape, truncate_str};
+
+/// Entries shown per page in the file browser
+const BROWSE_PAGE_SIZE: usize = 12;
+
+/// Max number of ancestor buttons in the breadcrumb row
+const BREADCRUMB_DEPTH: usize = 3;
+
+/// One directory entry rendered as a button
+#[derive(Clone)]
+pub(crate) struct BrowseEntry {
+    pub(crate) name: String,
+    pub(crate) is_dir: bool,
+    pub(crate) size: u64,
+}
+
+/// File browser state for a chat.
+/// Callback data only carries entry indices (Telegram limits it to 64 bytes),
+/// so the listing the buttons refer to is kept here.
+pub(crate) struct BrowseView {
+    /// Message holding the browser keyboard; callbacks from older messages are rejected
+    pub(crate) message_id: MessageId,
+    pub(crate) dir: String,
+    pub(crate) entries: Vec<BrowseEntry>,
+    pub(crate) page: usize,
+}
+
+/// Browsing is confined to the same sandbox as /cd and /down
+fn sandbox_root() -> PathBuf {
+    dirs::home_dir().unwrap_or_else(|| Path::new("/").to_path_buf())
+}
+
+/// List a directory: folders first, then files, each sorted case-insensitively
+fn read_entries(dir: &Path) -> std::io::Result<Vec<BrowseEntry>> {
+    let mut entries: Vec<BrowseEntry> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| {
+            // Follow symlinks so linked folders are browsable
+            let metadata = fs::metadata(e.path()).ok();
+            BrowseEntry {
+                name: e.file_name().to_string_lossy().to_string(),
+                is_dir: metadata.as_ref().map(|m| m.is_dir()).unwrap_or(false),
+                size: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+    Ok(entries)
+}
+
+/// Human-readable byte size (e.g. "1.2 KB")
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Shorten a path for display by replacing the home directory with ~
+fn display_path(path: &str) -> String {
+    if let Some(home) = dirs::home_dir() {
+        if let Ok(rest) = Path::new(path).strip_prefix(&home) {
+            if rest.as_os_str().is_empty() {
+                return "~".to_string();
+            }
+            return format!("~/{}", rest.display());
+        }
+    }
+    path.to_string()
+}
+
+fn page_count(view: &BrowseView) -> usize {
+    view.entries.len().div_ceil(BROWSE_PAGE_SIZE).max(1)
+}
+
+/// Build the listing text and keyboard for the current page
+fn render_listing(view: &BrowseView) -> (String, InlineKeyboardMarkup) {
+    let dir = Path::new(&view.dir);
+    let root = sandbox_root();
+    let pages = page_count(view);
+    let page = view.page.min(pages - 1);
+
+    let dir_count = view.entries.iter().filter(|e| e.is_dir).count();
+    let file_count = view.entries.len() - dir_count;
+    let mut text = format!("📂 <b>{}</b>\n", html_escape(&display_path(&view.dir)));
+    if view.entries.is_empty() {
+        text.push_str("(empty directory)");
+    } else {
+        text.push_str(&format!("{} folders, {} files", dir_count, file_count));
+        if pages > 1 {
+            text.push_str(&format!(" · page {}/{}", page + 1, pages));
+        }
+    }
+
+    let mut keyboard = InlineKeyboardMarkup::default();
+
+    // Breadcrumb: nearest ancestors that are still inside the sandbox
+    let crumbs: Vec<InlineKeyboardButton> = dir
+        .ancestors()
+        .enumerate()
+        .skip(1)
+        .take_while(|(_, p)| p.starts_with(&root))
+        .take(BREADCRUMB_DEPTH)
+        .map(|(levels_up, p)| {
+            let label = if p == root.as_path() {
+                "~".to_string()
+            } else {
+                p.file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "/".to_string())
+            };
+            InlineKeyboardButton::callback(truncate_str(&label, 20), format!("ls:bc:{}", levels_up))
+        })
+        .collect();
+    if !crumbs.is_empty() {
+        keyboard = keyboard.append_row(crumbs.into_iter().rev());
+        keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback("⬆️ ..", "ls:up")]);
+    }
+
+    let start = page * BROWSE_PAGE_SIZE;
+    for (idx, entry) in view
+        .entries
+        .iter()
+        .enumerate()
+        .skip(start)
+        .take(BROWSE_PAGE_SIZE)
+    {
+        let label = if entry.is_dir {
+            format!("📁 {}/", truncate_str(&entry.name, 40))
+        } else {
+            format!(
+                "📄 {} · {}",
+                truncate_str(&entry.name, 40),
+                format_size(entry.size)
+            )
+        };
+        keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+            label,
+            format!("ls:o:{}", idx),
+        )]);
+    }
+
+    if pages > 1 {
+        let mut nav = Vec::new();
+        if page > 0 {
+            nav.push(InlineKeyboardButton::callback(
+                "◀",
+                format!("ls:pg:{}", page - 1),
+            ));
+        }
+        nav.push(InlineKeyboardButton::callback(
+            format!("{}/{}", page + 1, pages),
+            "ls:nop",
+        ));
+        if page + 1 < pages {
+            nav.push(InlineKeyboardButton::callback(
+                "▶",
+                format!("ls:pg:{}", page + 1),
+            ));
+        }
+        keyboard = keyboard.append_row(nav);
+    }
+    keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+        "🔄 Refresh",
+        "ls:back",
+    )]);
+
+    (text, keyboard)
+}
+
+/// Build the detail view for a single file entry
+fn render_file(
+    view: &BrowseView,
+    idx: usize,
+    entry: &BrowseEntry,
+) -> (String, InlineKeyboardMarkup) {
+    let path = Path::new(&view.dir).join(&entry.name);
+    let text = format!(
+        "📄 <b>{}</b>\n<code>{}</code>\nSize: {}",
+        html_escape(&entry.name),
+        html_escape(&display_path(&path.display().to_string())),
+        format_size(entry.size)
+    );
+    let keyboard = InlineKeyboardMarkup::default()
+        .append_row(vec![
+            InlineKeyboardButton::callback("👁 Preview", format!("ls:pv:{}", idx)),
+            InlineKeyboardButton::callback("⬇️ Download", format!("ls:dl:{}", idx)),
+        ])
+        .append_row(vec![InlineKeyboardButton::callback("« Back", "ls:back")]);
+    (text, keyboard)
+}
+
+/// Handle /ls and /browse - show the session directory as an inline keyboard
+pub(crate) async fn handle_ls_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let current_path = {
+        let data = state.lock().await;
+        data.sessions
+            .get(&chat_id)
+            .and_then(|s| s.current_path.clone())
+    };
+    let Some(dir) = current_path else {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, "No active session. Use /start <path> first.")
+            .await?;
+        return Ok(());
+    };
+
+    let entries = match read_entries(Path::new(&dir)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, format!("Failed to read directory: {}", e))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let mut view = BrowseView {
+        message_id: MessageId(0),
+        dir,
+        entries,
+        page: 0,
+    };
+    let (text, keyboard) = render_listing(&view);
+
+    shared_rate_limit_wait(state, chat_id).await;
+    let sent = bot
+        .send_message(chat_id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
+        .await?;
+    view.message_id = sent.id;
+
+    let mut data = state.lock().await;
+    data.browse_views.insert(chat_id, view);
+
+    Ok(())
+}
+
+/// Replace the browser message with new content
+async fn edit_view(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    rendered: (String, InlineKeyboardMarkup),
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let (text, keyboard) = rendered;
+    shared_rate_limit_wait(state, chat_id).await;
+    if let Err(e) = bot
+        .edit_message_text(chat_id, message_id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
+        .await
+    {
+        tracing::warn!("edit_message failed (browser): {e}");
+    }
+    Ok(())
+}
+
+/// Change the session into `target` and re-render the browser there.
+/// Returns a toast message when navigation is refused.
+async fn navigate(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    target: &Path,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<Option<String>> {
+    if !target.is_dir() {
+        return Ok(Some("Not a directory anymore. Tap Refresh.".to_string()));
+    }
+    if !is_path_within_sandbox(target, &sandbox_root()) {
+        return Ok(Some(
+            "Access denied: outside the allowed path sandbox.".to_string(),
+        ));
+    }
+    let canonical = target
+        .canonicalize()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| target.display().to_string());
+    let entries = match read_entries(Path::new(&canonical)) {
+        Ok(entries) => entries,
+        Err(e) => return Ok(Some(format!("Failed to read directory: {}", e))),
+    };
+
+    let rendered = {
+        let mut data = state.lock().await;
+        if !change_current_path(&mut data, chat_id, &canonical, token) {
+            return Ok(Some(
+                "No active session. Use /start <path> first.".to_string(),
+            ));
+        }
+        let Some(view) = data.browse_views.get_mut(&chat_id) else {
+            return Ok(None);
+        };
+        view.dir = canonical.clone();
+        view.entries = entries;
+        view.page = 0;
+        render_listing(view)
+    };
+
+    tracing::info!("Browser cd: {canonical}");
+
+    edit_view(bot, chat_id, message_id, rendered, state).await?;
+    Ok(None)
+}
+
+/// Handle `ls:*` callbacks from the browser keyboard.
+/// Returns an optional toast to show in the callback answer.
+pub(crate) async fn handle_browse_callback(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    action: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<Option<String>> {
+    if action == "nop" {
+        return Ok(None);
+    }
+
+    let (dir, page) = {
+        let data = state.lock().await;
+        match data.browse_views.get(&chat_id) {
+            Some(view) if view.message_id == message_id => (view.dir.clone(), view.page),
+            _ => return Ok(Some("This browser has expired. Use /ls again.".to_string())),
+        }
+    };
+
+    let (verb, arg) = action.split_once(':').unwrap_or((action, ""));
+    let index: Option<usize> = arg.parse().ok();
+
+    match verb {
+        "up" => {
+            let parent = Path::new(&dir).parent().map(Path::to_path_buf);
+            match parent {
+                Some(parent) => navigate(bot, chat_id, message_id, &parent, state, token).await,
+                None => Ok(Some("Already at the top.".to_string())),
+            }
+        }
+        "bc" => {
+            let levels_up = index.unwrap_or(0);
+            match Path::new(&dir).ancestors().nth(levels_up) {
+                Some(target) => {
+                    let target = target.to_path_buf();
+                    navigate(bot, chat_id, message_id, &target, state, token).await
+                }
+                None => Ok(None),
+            }
+        }
+        "pg" => {
+            let rendered = {
+                let mut data = state.lock().await;
+                let Some(view) = data.browse_views.get_mut(&chat_id) else {
+                    return Ok(None);
+                };
+                view.page = index.unwrap_or(page).min(page_count(view) - 1);
+                render_listing(view)
+            };
+            edit_view(bot, chat_id, message_id, rendered, state).await?;
+            Ok(None)
+        }
+        "back" => {
+            let entries = match read_entries(Path::new(&dir)) {
+                Ok(entries) => entries,
+                Err(e) => return Ok(Some(format!("Failed to read directory: {}", e))),
+            };
+            let rendered = {
+                let mut data = state.lock().await;
+                let Some(view) = data.browse_views.get_mut(&chat_id) else {
+                    return Ok(None);
+                };
+                view.entries = entries;
+                view.page = view.page.min(page_count(view) - 1);
+                render_listing(view)
+            };
+            edit_view(bot, chat_id, message_id, rendered, state).await?;
+            Ok(None)
+        }
+        "o" | "pv" | "dl" => {
+            let entry = {
+                let data = state.lock().await;
+                data.browse_views
+                    .get(&chat_id)
+                    .and_then(|v| index.and_then(|i| v.entries.get(i)).cloned())
+            };
+            let (Some(idx), Some(entry)) = (index, entry) else {
+                return Ok(Some("Entry not found. Tap Refresh.".to_string()));
+            };
+            let path = Path::new(&dir).join(&entry.name);
+
+            if verb == "o" && entry.is_dir {
+                return navigate(bot, chat_id, message_id, &path, state, token).await;
+            }
+            if !path.is_file() {
+                return Ok(Some("File no longer exists. Tap Refresh.".to_string()));
+            }
+            if !is_path_within_sandbox(&path, &sandbox_root()) {
+                return Ok(Some(
+                    "Access denied: outside the allowed path sandbox.".to_string(),
+                ));
+            }
+
+            match verb {
+                "o" => {
+                    let rendered = {
+                        let data = state.lock().await;
+                        let Some(view) = data.browse_views.get(&chat_id) else {
+                            return Ok(None);
+                        };
+                        render_file(view, idx, &entry)
+                    };
+                    edit_view(bot, chat_id, message_id, rendered, state).await?;
+                    Ok(None)
+                }
+                "pv" => {
+                    send_file_page(bot, chat_id, &path, 1, None, state).await?;
+                    Ok(None)
+                }
+                _ => {
+                    shared_rate_limit_wait(state, chat_id).await;
+                    bot.send_document(chat_id, teloxide::types::InputFile::file(&path))
+                        .await?;
+                    Ok(None)
+                }
+            }
+        }
+        _ => Ok(None),
+    }
+}
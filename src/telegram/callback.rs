@@ -0,0 +1,125 @@
+use teloxide::prelude::*;
+
+use crate::audit::AuditEvent;
+use crate::auth::{can_execute, CommandRisk};
+
+use super::audit_log::record_decision;
+use super::bot::SharedState;
+use super::browser::handle_browse_callback;
+use super::checkpoints::handle_checkpoint_callback;
+use super::file_ops::handle_shell_callback;
+use super::git_ops::handle_git_callback;
+use super::interaction::handle_ask_callback;
+use super::preview::handle_cat_callback;
+
+/// Classify an inline keyboard callback by the command it stands in for.
+fn callback_risk(data: &str) -> CommandRisk {
+    match data.split(':').next().unwrap_or("") {
+        // Browser buttons change directory or download files, like /cd and /down
+        "ls" => CommandRisk::Elevated,
+        // File view paging: reads the file already opened with /cat
+        "cat" => CommandRisk::Elevated,
+        // Diff expansion and commit confirmation, like /diff and /commit
+        "git" => CommandRisk::Elevated,
+        // Checkpoint restore buttons, like /undo
+        "ck" => CommandRisk::Elevated,
+        // Destructive shell confirmation, like `!`
+        "sh" => CommandRisk::Dangerous,
+        // Answers to the running AI's questions, like sending it a message;
+        // the handler also checks the answer comes from whoever sent the prompt
+        "ask" => CommandRisk::Safe,
+        // Unknown callbacks: treat as elevated to be cautious
+        _ => CommandRisk::Elevated,
+    }
+}
+
+/// Route inline keyboard callbacks by the `<prefix>:` namespace of their data.
+/// Applies the same owner/public access rules as text commands.
+pub(crate) async fn handle_callback_query(
+    bot: Bot,
+    q: CallbackQuery,
+    state: SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let (Some(data), Some(message)) = (q.data.as_deref(), q.message.as_ref()) else {
+        bot.answer_callback_query(q.id.clone()).await?;
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+    let message_id = message.id();
+    let is_group_chat = matches!(message.chat().kind, teloxide::types::ChatKind::Public(_));
+    let uid = q.from.id.0;
+
+    let (is_owner, is_public, ai_busy) = {
+        let data = state.lock().await;
+        let is_public = is_group_chat
+            && data
+                .settings
+                .as_public_for_group_chat
+                .get(&chat_id.0.to_string())
+                .copied()
+                .unwrap_or(false);
+        (
+            data.settings.owner_user_id == Some(uid),
+            is_public,
+            data.cancel_tokens.contains_key(&chat_id),
+        )
+    };
+
+    if !is_owner && !is_public {
+        tracing::warn!("Rejected callback: not the owner");
+        bot.answer_callback_query(q.id.clone()).await?;
+        return Ok(());
+    }
+    let risk = callback_risk(data);
+    let allowed = can_execute(is_owner, is_public, risk);
+    record_decision(
+        &state,
+        AuditEvent::Callback,
+        uid,
+        chat_id,
+        data,
+        risk,
+        allowed,
+    )
+    .await;
+    if !allowed {
+        bot.answer_callback_query(q.id.clone())
+            .text("Permission denied: this action requires owner access.")
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+    // Questions from the AI are answered while its run is in progress
+    if ai_busy && !data.starts_with("ask:") {
+        bot.answer_callback_query(q.id.clone())
+            .text("AI request in progress. Use /stop to cancel.")
+            .await?;
+        return Ok(());
+    }
+
+    tracing::info!(data, "Callback");
+
+    let (prefix, action) = data.split_once(':').unwrap_or((data, ""));
+    let toast = match prefix {
+        "ls" => handle_browse_callback(&bot, chat_id, message_id, action, &state, token).await?,
+        "cat" => handle_cat_callback(&bot, chat_id, message_id, action, &state).await?,
+        "git" => handle_git_callback(&bot, chat_id, message_id, action, &state).await?,
+        "ck" => handle_checkpoint_callback(&bot, chat_id, message_id, action, &state).await?,
+        "sh" => {
+            handle_shell_callback(&bot, chat_id, message_id, action, &state, token, uid).await?
+        }
+        "ask" => {
+            handle_ask_callback(&bot, chat_id, message_id, action, &state, uid, is_owner).await?
+        }
+        _ => None,
+    };
+
+    let mut answer = bot.answer_callback_query(q.id.clone());
+    if let Some(text) = toast {
+        answer = answer.text(text);
+    }
+    answer.await?;
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+
+use crate::git;
+use crate::snapshot::{self, TreeSnapshot};
+
+use super::bot::{shared_rate_limit_wait, SharedState};
+use super::git_ops::{format_change_counts, DiffView};
+use super::streaming::{html_escape, truncate_str};
+
+/// Max changed files listed (and given expand buttons) in the post-run summary
+const SUMMARY_MAX_FILES: usize = 20;
+
+/// Show `path` relative to the session directory when possible
+fn relative_display(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base)
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| path.display().to_string())
+}
+
+/// Post a "Files changed: +2 ~3 -1" summary after an AI turn.
+/// Compares `before` with a fresh scan of `current_path`, plus files the AI reported
+/// writing via Write/Edit tool uses. In a git repository each file gets a button
+/// that expands its diff, like /diff.
+pub(crate) async fn send_change_summary(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+    current_path: &str,
+    before: TreeSnapshot,
+    touched: Vec<PathBuf>,
+) -> ResponseResult<()> {
+    let root = current_path.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        let after = snapshot::scan(Path::new(&root));
+        let summary = snapshot::compare(&before, &after, &touched);
+        if summary.is_empty() {
+            return None;
+        }
+        let repo = git::repo_root(&root).and_then(|repo_root| {
+            git::changed_files(&repo_root)
+                .ok()
+                .map(|files| (repo_root, files))
+        });
+        Some((summary, repo))
+    })
+    .await;
+
+    let Ok(Some((summary, repo))) = result else {
+        return Ok(());
+    };
+
+    let base = Path::new(current_path);
+    let entries = summary.entries();
+
+    // Git status for a changed path, if it is inside the repository
+    let git_change = |path: &Path| -> Option<&git::FileChange> {
+        let (repo_root, files) = repo.as_ref()?;
+        files
+            .iter()
+            .find(|f| Path::new(repo_root).join(&f.path) == path)
+    };
+
+    // Git changes made in this turn, in summary order, for the expand buttons
+    let diff_files: Vec<git::FileChange> = entries
+        .iter()
+        .take(SUMMARY_MAX_FILES)
+        .filter_map(|(_, path)| git_change(path).cloned())
+        .collect();
+
+    let mut text = format!("📝 <b>{}</b>", summary.headline());
+    for (marker, path) in entries.iter().take(SUMMARY_MAX_FILES) {
+        let rel = relative_display(path, base);
+        let counts = git_change(path)
+            .map(|f| format!(" {}", format_change_counts(f)))
+            .unwrap_or_default();
+        text.push_str(&format!(
+            "\n<code>{}</code> {}{}",
+            marker,
+            html_escape(&rel),
+            counts
+        ));
+    }
+    if entries.len() > SUMMARY_MAX_FILES {
+        text.push_str(&format!(
+            "\n… and {} more",
+            entries.len() - SUMMARY_MAX_FILES
+        ));
+    }
+
+    shared_rate_limit_wait(state, chat_id).await;
+    let mut req = bot.send_message(chat_id, text).parse_mode(ParseMode::Html);
+    if !diff_files.is_empty() {
+        let mut keyboard = InlineKeyboardMarkup::default();
+        for (idx, file) in diff_files.iter().enumerate() {
+            keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+                format!("{} {}", file.status, truncate_str(&file.path, 50)),
+                format!("git:d:{}", idx),
+            )]);
+        }
+        req = req.reply_markup(keyboard);
+    }
+    let sent = req.await?;
+
+    if let Some((repo_root, _)) = repo {
+        if !diff_files.is_empty() {
+            let mut data = state.lock().await;
+            data.diff_views.insert(
+                chat_id,
+                DiffView {
+                    message_id: sent.id,
+                    repo_root,
+                    files: diff_files,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use teloxide::prelude::*;
+use teloxide::types::{MessageId, ParseMode};
+
+use crate::claude::{TodoItem, TodoStatus};
+
+use super::bot::{shared_rate_limit_wait, SharedState, TELEGRAM_MSG_LIMIT};
+use super::streaming::{html_escape, redact_outbound, truncate_str};
+
+/// Longest todo or task text shown, before escaping
+const ITEM_MAX: usize = 300;
+
+/// The AI's todo list plus background tasks that finished during the run
+#[derive(Default)]
+pub(crate) struct Checklist {
+    pub todos: Vec<TodoItem>,
+    /// (status, summary) of each finished background task
+    pub tasks: Vec<(String, String)>,
+}
+
+impl Checklist {
+    pub fn is_empty(&self) -> bool {
+        self.todos.is_empty() && self.tasks.is_empty()
+    }
+
+    /// HTML checklist: ☐ pending, ◐ in progress, ☑ completed
+    pub fn render(&self) -> String {
+        let mut lines = Vec::new();
+        if !self.todos.is_empty() {
+            let completed = self
+                .todos
+                .iter()
+                .filter(|t| t.status == TodoStatus::Completed)
+                .count();
+            lines.push(format!(
+                "<b>📋 Tasks {}/{}</b>",
+                completed,
+                self.todos.len()
+            ));
+            for todo in &self.todos {
+                lines.push(match todo.status {
+                    TodoStatus::Pending => {
+                        format!("☐ {}", html_escape(&truncate_str(&todo.content, ITEM_MAX)))
+                    }
+                    TodoStatus::InProgress => format!(
+                        "◐ <b>{}</b>",
+                        html_escape(&truncate_str(
+                            todo.active_form.as_deref().unwrap_or(&todo.content),
+                            ITEM_MAX
+                        ))
+                    ),
+                    TodoStatus::Completed => {
+                        format!("☑ {}", html_escape(&truncate_str(&todo.content, ITEM_MAX)))
+                    }
+                });
+            }
+        }
+        if !self.tasks.is_empty() {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.push("<b>Background tasks</b>".to_string());
+            for (status, summary) in &self.tasks {
+                let mark = match status.as_str() {
+                    "completed" => "☑",
+                    "failed" => "✖",
+                    _ => "◼",
+                };
+                lines.push(format!(
+                    "{} {}",
+                    mark,
+                    html_escape(&truncate_str(summary, ITEM_MAX))
+                ));
+            }
+        }
+        // Whole lines are dropped so no tag is cut in half
+        let mut text = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            if text.len() + line.len() + 1 > TELEGRAM_MSG_LIMIT - 30 {
+                text.push_str(&format!("… and {} more", lines.len() - i));
+                break;
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        text.trim_end_matches('\n').to_string()
+    }
+}
+
+/// The separate checklist message of one run: sent and pinned on the first
+/// todo update, edited on later ones, unpinned when the run ends
+#[derive(Default)]
+pub(crate) struct PinnedChecklist {
+    message_id: Option<MessageId>,
+    last_html: String,
+}
+
+impl PinnedChecklist {
+    pub async fn update(&mut self, bot: &Bot, chat_id: ChatId, state: &SharedState, html: &str) {
+        let html = redact_outbound(state, html).await;
+        if html == self.last_html {
+            return;
+        }
+        shared_rate_limit_wait(state, chat_id).await;
+        match self.message_id {
+            Some(id) => {
+                if let Err(e) = bot
+                    .edit_message_text(chat_id, id, &html)
+                    .parse_mode(ParseMode::Html)
+                    .await
+                {
+                    tracing::warn!("edit_message failed (checklist): {e}");
+                }
+            }
+            None => {
+                let sent = bot
+                    .send_message(chat_id, &html)
+                    .parse_mode(ParseMode::Html)
+                    .disable_notification(true)
+                    .await;
+                match sent {
+                    Ok(msg) => {
+                        self.message_id = Some(msg.id);
+                        // Needs admin rights in groups; the checklist works unpinned too
+                        shared_rate_limit_wait(state, chat_id).await;
+                        if let Err(e) = bot
+                            .pin_chat_message(chat_id, msg.id)
+                            .disable_notification(true)
+                            .await
+                        {
+                            tracing::debug!("pin_chat_message failed (checklist): {e}");
+                        }
+                    }
+                    Err(e) => tracing::warn!("send_message failed (checklist): {e}"),
+                }
+            }
+        }
+        self.last_html = html;
+    }
+
+    /// Show the final state and unpin. The message itself stays in the chat.
+    pub async fn finish(
+        mut self,
+        bot: &Bot,
+        chat_id: ChatId,
+        state: &SharedState,
+        html: Option<String>,
+    ) {
+        if let Some(html) = html {
+            self.update(bot, chat_id, state, &html).await;
+        }
+        if let Some(id) = self.message_id {
+            shared_rate_limit_wait(state, chat_id).await;
+            let _ = bot.unpin_chat_message(chat_id).message_id(id).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_drops_whole_items() {
+        let checklist = Checklist {
+            todos: (0..200)
+                .map(|n| TodoItem {
+                    content: format!("Step {} of a long plan", n),
+                    status: TodoStatus::InProgress,
+                    active_form: None,
+                })
+                .collect(),
+            tasks: Vec::new(),
+        };
+        let html = checklist.render();
+        assert!(html.len() <= TELEGRAM_MSG_LIMIT);
+        assert_eq!(html.matches("<b>").count(), html.matches("</b>").count());
+        assert!(html.ends_with("more"));
+    }
+}
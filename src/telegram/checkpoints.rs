@@ -0,0 +1,224 @@
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode};
+
+use crate::checkpoint::{self, Checkpoint};
+
+use super::bot::{shared_rate_limit_wait, SharedState};
+use super::streaming::{html_escape, truncate_str};
+
+/// Number of checkpoints listed by /checkpoints
+const CHECKPOINT_LIST_COUNT: usize = 10;
+
+/// How far back /undo and the restore buttons look up checkpoints
+const CHECKPOINT_SEARCH_COUNT: usize = 50;
+
+/// Per-chat /checkpoints list backing its restore buttons
+pub(crate) struct CheckpointView {
+    pub(crate) message_id: MessageId,
+    /// Project directory the listed checkpoints belong to
+    pub(crate) project: String,
+}
+
+/// Current session directory, or None after telling the user there is no session
+async fn session_project(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+) -> ResponseResult<Option<String>> {
+    let current_path = {
+        let data = state.lock().await;
+        data.sessions
+            .get(&chat_id)
+            .and_then(|s| s.current_path.clone())
+    };
+    if current_path.is_none() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, "No active session. Use /start <path> first.")
+            .await?;
+    }
+    Ok(current_path)
+}
+
+/// Restore `project` to `target` in a blocking task and describe the outcome as HTML
+async fn restore_checkpoint(project: &str, target: &Checkpoint) -> String {
+    let project_clone = project.to_string();
+    let id = target.id.clone();
+    let result =
+        tokio::task::spawn_blocking(move || checkpoint::restore(&project_clone, &id)).await;
+    match result {
+        Ok(Ok(())) => {
+            tracing::info!("Restored checkpoint {}", target.id);
+            format!(
+                "↩️ Restored checkpoint <code>{}</code> ({})\n<i>{}</i>\n\nThe state before this restore was saved too; use /checkpoints to go back.",
+                html_escape(&target.id),
+                html_escape(&target.when),
+                html_escape(&target.label)
+            )
+        }
+        Ok(Err(e)) => format!(
+            "❌ Restore failed\n<pre>{}</pre>",
+            html_escape(&truncate_str(&e, 3000))
+        ),
+        Err(e) => format!("Task error: {}", html_escape(&e.to_string())),
+    }
+}
+
+/// Handle /undo - restore the working tree to before the last AI turn
+pub(crate) async fn handle_undo_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let Some(project) = session_project(bot, chat_id, state).await? else {
+        return Ok(());
+    };
+
+    let project_clone = project.clone();
+    let listed = tokio::task::spawn_blocking(move || {
+        checkpoint::list(&project_clone, CHECKPOINT_SEARCH_COUNT)
+    })
+    .await;
+    let response = match listed {
+        Ok(Ok(list)) => match checkpoint::undo_target(&list) {
+            Some(target) => restore_checkpoint(&project, target).await,
+            None if list.is_empty() => "No checkpoints for this directory yet.".to_string(),
+            None => "Nothing left to undo; every recent turn has been restored already. \
+                     Use /checkpoints to pick one."
+                .to_string(),
+        },
+        Ok(Err(e)) => format!("Checkpoint error: {}", html_escape(&e)),
+        Err(e) => format!("Task error: {}", html_escape(&e.to_string())),
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, response)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// Handle /checkpoints - list recent checkpoints with restore buttons
+pub(crate) async fn handle_checkpoints_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let Some(project) = session_project(bot, chat_id, state).await? else {
+        return Ok(());
+    };
+
+    let project_clone = project.clone();
+    let listed = tokio::task::spawn_blocking(move || {
+        checkpoint::list(&project_clone, CHECKPOINT_LIST_COUNT)
+    })
+    .await;
+    let list = match listed {
+        Ok(Ok(list)) if list.is_empty() => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, "No checkpoints for this directory yet.")
+                .await?;
+            return Ok(());
+        }
+        Ok(Ok(list)) => list,
+        Ok(Err(e)) => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, format!("Checkpoint error: {}", e))
+                .await?;
+            return Ok(());
+        }
+        Err(e) => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, format!("Task error: {}", e))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let mut lines = vec![format!(
+        "<b>Checkpoints</b> for <code>{}</code>\nTap one to restore it.\n",
+        html_escape(&project)
+    )];
+    let mut keyboard = InlineKeyboardMarkup::default();
+    for c in &list {
+        lines.push(format!(
+            "<code>{}</code> {} — <i>{}</i>",
+            html_escape(&c.id),
+            html_escape(&truncate_str(&c.label, 80)),
+            html_escape(&c.when)
+        ));
+        keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+            format!("↩ {} {}", c.id, truncate_str(&c.label, 40)),
+            format!("ck:r:{}", c.id),
+        )]);
+    }
+
+    shared_rate_limit_wait(state, chat_id).await;
+    let sent = bot
+        .send_message(chat_id, lines.join("\n"))
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
+        .await?;
+
+    let mut data = state.lock().await;
+    data.checkpoint_views.insert(
+        chat_id,
+        CheckpointView {
+            message_id: sent.id,
+            project,
+        },
+    );
+    Ok(())
+}
+
+/// Handle `ck:` callbacks from the /checkpoints keyboard
+pub(crate) async fn handle_checkpoint_callback(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    action: &str,
+    state: &SharedState,
+) -> ResponseResult<Option<String>> {
+    let (verb, id) = action.split_once(':').unwrap_or((action, ""));
+    if verb != "r" {
+        return Ok(None);
+    }
+
+    let project = {
+        let mut data = state.lock().await;
+        match data.checkpoint_views.get(&chat_id) {
+            Some(v) if v.message_id == message_id => {
+                data.checkpoint_views.remove(&chat_id).map(|v| v.project)
+            }
+            _ => None,
+        }
+    };
+    let Some(project) = project else {
+        return Ok(Some(
+            "This list has expired. Run /checkpoints again.".to_string(),
+        ));
+    };
+
+    let project_clone = project.clone();
+    let listed = tokio::task::spawn_blocking(move || {
+        checkpoint::list(&project_clone, CHECKPOINT_SEARCH_COUNT)
+    })
+    .await;
+    let target = match listed {
+        Ok(Ok(list)) => list.into_iter().find(|c| c.id == id),
+        _ => None,
+    };
+    let Some(target) = target else {
+        return Ok(Some("Checkpoint not found.".to_string()));
+    };
+
+    let result_text = restore_checkpoint(&project, &target).await;
+    shared_rate_limit_wait(state, chat_id).await;
+    if let Err(e) = bot
+        .edit_message_text(chat_id, message_id, result_text)
+        .parse_mode(ParseMode::Html)
+        .await
+    {
+        tracing::warn!("edit_message failed (checkpoint): {e}");
+    }
+    Ok(None)
+}
@@ -5,11 +5,20 @@ use teloxide::prelude::*;
 use teloxide::types::ParseMode;
 
 use crate::auth::is_path_within_sandbox;
+use crate::claude::{
+    effort_budget, is_known_model, ModelOptions, EFFORT_LEVELS, KNOWN_MODELS, MAX_TURNS_LIMIT,
+};
+use crate::redact;
 use crate::session::HistoryType;
 
 use super::bot::{shared_rate_limit_wait, SharedState};
-use super::storage::{load_existing_session, save_bot_settings, ChatSession};
-use super::streaming::send_long_message;
+use super::render::ThinkingDisplay;
+use super::storage::{load_existing_session, save_bot_settings, BotSettings, ChatSession};
+use super::streaming::{html_escape, send_long_message};
+
+/// Range accepted by /thinking budget (the API's minimum thinking budget is 1024)
+const MIN_THINKING_BUDGET: u32 = 1024;
+const MAX_THINKING_BUDGET: u32 = 64_000;
 
 /// Handle /help command
 pub(crate) async fn handle_help_command(
@@ -27,15 +36,26 @@ Manage server files &amp; chat with Claude Code AI.
 <code>/start</code> — Start in default startup project directory
 <code>/pwd</code> — Show current working directory
 <code>/cd &lt;path&gt;</code> — Change working directory
+<code>/ls</code> — Browse files with buttons (also <code>/browse</code>)
 <code>/clear</code> — Clear AI conversation history
 <code>/stop</code> — Stop current AI request
 
 <b>File Transfer</b>
 <code>/down &lt;file&gt;</code> — Download file from server
+<code>/cat &lt;file&gt; [start:end]</code> — Show file with line numbers
 Send a file/photo — Upload to session directory
 
+<b>Git</b>
+<code>/diff</code> — Changed files, tap to expand a file's diff
+<code>/log [n]</code> — Recent commits
+<code>/commit &lt;message&gt;</code> — Stage and commit all changes (asks to confirm)
+
+<b>Checkpoints</b>
+<code>/undo</code> — Restore files to before the last AI turn
+<code>/checkpoints</code> — List checkpoints, tap to restore
+
 <b>Shell</b>
-<code>!&lt;command&gt;</code> — Run shell command directly
+<code>!&lt;command&gt;</code> — Run shell command directly (destructive ones ask first)
   e.g. <code>!ls -la</code>, <code>!git status</code>
 
 <b>AI Chat</b>
@@ -53,6 +73,28 @@ AI can read, edit, and run commands in your session.
 <code>;</code><i>caption</i> — Upload file with AI prompt
 <code>/public on</code> — Allow all members to use bot
 <code>/public off</code> — Owner only (default)
+<code>/publictools [+name|-name]</code> — Tools for non-owner prompts (read-only by default)
+
+<b>Thinking</b>
+<code>/thinking on|off|summary</code> — Show extended thinking in full, hide it, or summarized
+<code>/thinking budget &lt;tokens&gt;</code> — Thinking budget for the next prompt
+
+<b>Model</b>
+<code>/model [name|default]</code> — Show or set the model (opus, sonnet, haiku, …)
+<code>/model fallback &lt;name&gt;|off</code> — Model used when the main one is overloaded
+<code>/maxturns [n|off]</code> — Limit agentic turns per prompt
+<code>/effort [low|medium|high|default]</code> — How much the AI thinks before answering
+<code>;opus: prompt</code> — Use a model for one prompt
+<code>/systemprompt [show|set|append|reset]</code> — Extra instructions for this chat (also read from <code>.openclaude.md</code>)
+
+<b>Project</b>
+<code>/mcp [list|add &lt;file&gt;|remove &lt;n&gt;]</code> — MCP server config files of this project
+<code>/adddir [path|remove &lt;n&gt;]</code> — Extra directories the AI may use
+
+<b>Security</b>
+<code>/redact [+regex|-N]</code> — Patterns masked in outbound messages
+<code>/audit [n]</code> — Recent commands, AI runs and permission decisions
+<code>/trace</code> — Raw stream capture of the last AI run
 
 <code>/help</code> — Show this help",
         env!("CARGO_BIN_NAME")
@@ -152,7 +194,9 @@ pub(crate) async fn handle_start_command(
             current_path: None,
             history: Vec::new(),
             pending_uploads: Vec::new(),
+            pending_attachments: Vec::new(),
             cleared: false,
+            thinking_budget: None,
         });
 
         if let Some((session_data, _)) = &existing {
@@ -160,8 +204,7 @@ pub(crate) async fn handle_start_command(
             session.current_path = Some(canonical_path.clone());
             session.history = session_data.history.clone();
 
-            let ts = chrono::Local::now().format("%H:%M:%S");
-            println!("  [{ts}] ▶ Session restored: {canonical_path}");
+            tracing::info!("Session restored: {canonical_path}");
             response_lines.push(format!("Session restored at `{}`.", canonical_path));
             response_lines.push(String::new());
 
@@ -191,8 +234,7 @@ pub(crate) async fn handle_start_command(
             session.current_path = Some(canonical_path.clone());
             session.history.clear();
 
-            let ts = chrono::Local::now().format("%H:%M:%S");
-            println!("  [{ts}] ▶ Session started: {canonical_path}");
+            tracing::info!("Session started: {canonical_path}");
             response_lines.push(format!("Session started at `{}`.", canonical_path));
         }
     }
@@ -244,9 +286,11 @@ pub(crate) async fn handle_clear_command(
             session.session_id = None;
             session.history.clear();
             session.pending_uploads.clear();
+            session.pending_attachments.clear();
             session.cleared = true;
         }
         data.cancel_tokens.remove(&chat_id);
+        data.run_users.remove(&chat_id);
         data.stop_message_ids.remove(&chat_id);
     }
 
@@ -372,26 +416,15 @@ pub(crate) async fn handle_cd_command(
         return Ok(());
     }
 
-    // Update only current_path, preserve session and history
-    {
+    let changed = {
         let mut data = state.lock().await;
-        if let Some(session) = data.sessions.get_mut(&chat_id) {
-            session.current_path = Some(canonical.clone());
-        } else {
-            shared_rate_limit_wait(state, chat_id).await;
-            bot.send_message(chat_id, "No active session. Use /start <path> first.")
-                .await?;
-            return Ok(());
-        }
-    }
-
-    // Persist the new path for auto-restore after bot restart (same as /start)
-    {
-        let mut data = state.lock().await;
-        data.settings
-            .last_sessions
-            .insert(chat_id.0.to_string(), canonical.clone());
-        save_bot_settings(token, &data.settings);
+        change_current_path(&mut data, chat_id, &canonical, token)
+    };
+    if !changed {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, "No active session. Use /start <path> first.")
+            .await?;
+        return Ok(());
     }
 
     shared_rate_limit_wait(state, chat_id).await;
@@ -401,6 +434,26 @@ pub(crate) async fn handle_cd_command(
     Ok(())
 }
 
+/// Point the chat's session at `canonical` without resetting session or history,
+/// and persist the path for auto-restore after bot restart (same as /start).
+/// Returns false if the chat has no active session.
+pub(crate) fn change_current_path(
+    data: &mut super::bot::SharedData,
+    chat_id: ChatId,
+    canonical: &str,
+    token: &str,
+) -> bool {
+    let Some(session) = data.sessions.get_mut(&chat_id) else {
+        return false;
+    };
+    session.current_path = Some(canonical.to_string());
+    data.settings
+        .last_sessions
+        .insert(chat_id.0.to_string(), canonical.to_string());
+    save_bot_settings(token, &data.settings);
+    true
+}
+
 /// Handle /stop command - cancel in-progress AI request
 pub(crate) async fn handle_stop_command(
     bot: &Bot,
@@ -446,8 +499,7 @@ pub(crate) async fn handle_stop_command(
                 }
             }
 
-            let ts = chrono::Local::now().format("%H:%M:%S");
-            println!("  [{ts}] ■ Cancel signal sent");
+            tracing::info!("Cancel signal sent");
         }
         None => {
             shared_rate_limit_wait(state, chat_id).await;
@@ -500,7 +552,7 @@ pub(crate) async fn handle_public_command(
                 .as_public_for_group_chat
                 .insert(chat_key, true);
             save_bot_settings(token, &data.settings);
-            "✅ Public access <b>enabled</b> for this group.\nAll members can now use the bot."
+            "✅ Public access <b>enabled</b> for this group.\nAll members can now use the bot.\nPrompts from non-owners use the /publictools profile (read-only by default)."
                 .to_string()
         }
         "off" => {
@@ -539,13 +591,383 @@ pub(crate) async fn handle_public_command(
     Ok(())
 }
 
+/// Handle /redact command - manage extra regexes masked in outbound messages
+/// Usage: /redact           (list built-in formats and custom patterns)
+///        /redact +<regex>  (add)
+///        /redact -<n>      (remove the n-th custom pattern)
+pub(crate) async fn handle_redact_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let arg = text.strip_prefix("/redact").unwrap_or("").trim();
+
+    let response_msg = if let Some(pattern) = arg.strip_prefix('+') {
+        let pattern = pattern.trim();
+        match regex::Regex::new(pattern) {
+            Ok(_) if !pattern.is_empty() => {
+                let mut data = state.lock().await;
+                data.settings.redact_patterns.push(pattern.to_string());
+                data.redact_regexes = redact::compile_patterns(&data.settings.redact_patterns);
+                save_bot_settings(token, &data.settings);
+                format!("✅ Added pattern <code>{}</code>", html_escape(pattern))
+            }
+            Ok(_) => "Pattern cannot be empty.".to_string(),
+            Err(e) => format!("Invalid regex:\n<pre>{}</pre>", html_escape(&e.to_string())),
+        }
+    } else if let Some(index) = arg.strip_prefix('-') {
+        let mut data = state.lock().await;
+        match index.trim().parse::<usize>() {
+            Ok(n) if n >= 1 && n <= data.settings.redact_patterns.len() => {
+                let removed = data.settings.redact_patterns.remove(n - 1);
+                data.redact_regexes = redact::compile_patterns(&data.settings.redact_patterns);
+                save_bot_settings(token, &data.settings);
+                format!("❌ Removed pattern <code>{}</code>", html_escape(&removed))
+            }
+            _ => "Use /redact -N with a number from the /redact list.".to_string(),
+        }
+    } else if arg.is_empty() {
+        let data = state.lock().await;
+        let mut msg =
+            String::from("<b>Redaction</b>\nMasked in all outbound messages.\n\n<b>Built-in</b>\n");
+        for (name, _) in redact::BUILTIN_PATTERNS {
+            msg.push_str(&format!("• {}\n", html_escape(name)));
+        }
+        msg.push_str(
+            "• Random-looking values after KEY=, TOKEN=, SECRET=, PASSWORD=\n\n<b>Custom</b>\n",
+        );
+        if data.settings.redact_patterns.is_empty() {
+            msg.push_str("(none)\n");
+        }
+        for (i, pattern) in data.settings.redact_patterns.iter().enumerate() {
+            msg.push_str(&format!(
+                "{}. <code>{}</code>\n",
+                i + 1,
+                html_escape(pattern)
+            ));
+        }
+        msg.push_str("\n/redact +regex — Add a pattern\n/redact -N — Remove pattern N");
+        msg
+    } else {
+        "Usage:\n/redact — List patterns\n/redact +regex — Add a pattern\n/redact -N — Remove pattern N"
+            .to_string()
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle /thinking command - how extended thinking is shown, and the budget of the next prompt
+/// Usage: /thinking                  (show the current setting)
+///        /thinking on|off|summary   (quote thinking in full, hide it, or one line per block)
+///        /thinking budget <tokens>  (thinking budget for the next prompt; 0 cancels)
+pub(crate) async fn handle_thinking_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let arg = text
+        .strip_prefix("/thinking")
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    let chat_key = chat_id.0.to_string();
+    let usage = "<code>/thinking on</code> — Show thinking as an expandable quote\n\
+                 <code>/thinking summary</code> — One line per thinking block\n\
+                 <code>/thinking off</code> — Hide thinking (default)\n\
+                 <code>/thinking budget &lt;tokens&gt;</code> — Thinking budget for the next prompt";
+
+    let response_msg = if let Some(display) = ThinkingDisplay::parse(&arg) {
+        let mut data = state.lock().await;
+        match display {
+            ThinkingDisplay::Off => data.settings.thinking_display.remove(&chat_key),
+            _ => data
+                .settings
+                .thinking_display
+                .insert(chat_key, display.as_str().to_string()),
+        };
+        save_bot_settings(token, &data.settings);
+        format!("Thinking display: <b>{}</b>", display.as_str())
+    } else if let Some(budget) = arg.strip_prefix("budget") {
+        match budget.trim().parse::<u32>() {
+            Ok(tokens)
+                if tokens == 0 || (MIN_THINKING_BUDGET..=MAX_THINKING_BUDGET).contains(&tokens) =>
+            {
+                let mut data = state.lock().await;
+                match data.sessions.get_mut(&chat_id) {
+                    Some(session) if tokens == 0 => {
+                        session.thinking_budget = None;
+                        "Thinking budget cancelled.".to_string()
+                    }
+                    Some(session) => {
+                        session.thinking_budget = Some(tokens);
+                        format!(
+                            "Your next prompt may think for up to <b>{}</b> tokens.",
+                            tokens
+                        )
+                    }
+                    None => "No active session. Use /start &lt;path&gt; first.".to_string(),
+                }
+            }
+            _ => format!(
+                "Budget must be {}–{} tokens (0 cancels).",
+                MIN_THINKING_BUDGET, MAX_THINKING_BUDGET
+            ),
+        }
+    } else if arg.is_empty() {
+        let data = state.lock().await;
+        let display = ThinkingDisplay::for_chat(&data.settings, chat_id);
+        let budget = data
+            .sessions
+            .get(&chat_id)
+            .and_then(|s| s.thinking_budget)
+            .map(|tokens| format!("\nNext prompt budget: <b>{}</b> tokens", tokens))
+            .unwrap_or_default();
+        format!(
+            "Thinking display is <b>{}</b>.{}\n\n{}",
+            display.as_str(),
+            budget,
+            usage
+        )
+    } else {
+        format!("Usage:\n{}", usage)
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// The chat's /model and /maxturns settings
+pub(crate) fn chat_model_options(settings: &BotSettings, chat_id: ChatId) -> ModelOptions {
+    let key = chat_id.0.to_string();
+    ModelOptions {
+        model: settings.models.get(&key).cloned(),
+        fallback_model: settings.fallback_models.get(&key).cloned(),
+        max_turns: settings.max_turns.get(&key).copied(),
+    }
+}
+
+/// Handle /model command - the model this chat's prompts run on
+/// Usage: /model                        (show the current model and the choices)
+///        /model <name>                 (use a model alias or full model id)
+///        /model default                (back to the CLI default)
+///        /model fallback <name>|off    (model used when the main one is overloaded)
+pub(crate) async fn handle_model_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let arg = text
+        .strip_prefix("/model")
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    let chat_key = chat_id.0.to_string();
+    let choices = KNOWN_MODELS
+        .iter()
+        .map(|m| format!("<code>{}</code>", m))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let usage = format!(
+        "<code>/model &lt;name&gt;</code> — {} or a full <code>claude-…</code> id\n\
+         <code>/model default</code> — Use the CLI default\n\
+         <code>/model fallback &lt;name&gt;|off</code> — Model used when the main one is overloaded\n\
+         <code>;name: prompt</code> — Use a model for one prompt",
+        choices
+    );
+
+    let response_msg = if arg.is_empty() {
+        let data = state.lock().await;
+        let options = chat_model_options(&data.settings, chat_id);
+        format!(
+            "Model: <b>{}</b>\nFallback: <b>{}</b>\n\n{}",
+            html_escape(options.model.as_deref().unwrap_or("default")),
+            html_escape(options.fallback_model.as_deref().unwrap_or("none")),
+            usage
+        )
+    } else if let Some(fallback) = arg.strip_prefix("fallback") {
+        match fallback.trim() {
+            "off" | "none" => {
+                let mut data = state.lock().await;
+                data.settings.fallback_models.remove(&chat_key);
+                save_bot_settings(token, &data.settings);
+                "Fallback model removed.".to_string()
+            }
+            name if is_known_model(name) => {
+                let mut data = state.lock().await;
+                data.settings
+                    .fallback_models
+                    .insert(chat_key, name.to_string());
+                save_bot_settings(token, &data.settings);
+                format!("Fallback model: <b>{}</b>", html_escape(name))
+            }
+            name => format!(
+                "Unknown model <code>{}</code>.\n\n{}",
+                html_escape(name),
+                usage
+            ),
+        }
+    } else if arg == "default" || arg == "reset" {
+        let mut data = state.lock().await;
+        data.settings.models.remove(&chat_key);
+        save_bot_settings(token, &data.settings);
+        "Model: <b>default</b>".to_string()
+    } else if is_known_model(&arg) {
+        let mut data = state.lock().await;
+        data.settings.models.insert(chat_key, arg.clone());
+        save_bot_settings(token, &data.settings);
+        format!("Model: <b>{}</b>", html_escape(&arg))
+    } else {
+        format!(
+            "Unknown model <code>{}</code>.\n\n{}",
+            html_escape(&arg),
+            usage
+        )
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle /maxturns command - how many agentic turns a prompt may take
+/// Usage: /maxturns          (show the current limit)
+///        /maxturns <n>      (stop after n turns)
+///        /maxturns off      (no limit; also 0)
+pub(crate) async fn handle_maxturns_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let arg = text
+        .strip_prefix("/maxturns")
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    let chat_key = chat_id.0.to_string();
+
+    let response_msg = if arg.is_empty() {
+        let data = state.lock().await;
+        match data.settings.max_turns.get(&chat_key) {
+            Some(turns) => format!("Max turns: <b>{}</b>", turns),
+            None => "Max turns: <b>no limit</b>".to_string(),
+        }
+    } else if arg == "off" || arg == "0" {
+        let mut data = state.lock().await;
+        data.settings.max_turns.remove(&chat_key);
+        save_bot_settings(token, &data.settings);
+        "Max turns: <b>no limit</b>".to_string()
+    } else {
+        match arg.parse::<u32>() {
+            Ok(turns) if turns <= MAX_TURNS_LIMIT => {
+                let mut data = state.lock().await;
+                data.settings.max_turns.insert(chat_key, turns);
+                save_bot_settings(token, &data.settings);
+                format!("Max turns: <b>{}</b>", turns)
+            }
+            _ => format!(
+                "Usage: <code>/maxturns &lt;1–{}&gt;</code> or <code>/maxturns off</code>",
+                MAX_TURNS_LIMIT
+            ),
+        }
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle /effort command - how much the AI thinks before answering, as a
+/// thinking budget for every prompt of this chat
+/// Usage: /effort            (show the current level)
+///        /effort <level>    (low, medium or high)
+///        /effort default    (back to the CLI default)
+pub(crate) async fn handle_effort_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let arg = text
+        .strip_prefix("/effort")
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    let chat_key = chat_id.0.to_string();
+    let levels = EFFORT_LEVELS
+        .iter()
+        .map(|(name, tokens)| format!("<code>{}</code> ({} tokens)", name, tokens))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let response_msg = if arg.is_empty() {
+        let data = state.lock().await;
+        format!(
+            "Effort: <b>{}</b>\n\nLevels: {}\n<code>/effort default</code> — Use the CLI default\n\
+             A <code>/thinking budget</code> overrides the level for one prompt.",
+            html_escape(
+                data.settings
+                    .efforts
+                    .get(&chat_key)
+                    .map_or("default", String::as_str)
+            ),
+            levels
+        )
+    } else if arg == "default" || arg == "reset" || arg == "off" {
+        let mut data = state.lock().await;
+        data.settings.efforts.remove(&chat_key);
+        save_bot_settings(token, &data.settings);
+        "Effort: <b>default</b>".to_string()
+    } else if let Some(tokens) = effort_budget(&arg) {
+        let mut data = state.lock().await;
+        data.settings.efforts.insert(chat_key, arg.clone());
+        save_bot_settings(token, &data.settings);
+        format!("Effort: <b>{}</b> ({} thinking tokens)", arg, tokens)
+    } else {
+        format!(
+            "Unknown effort <code>{}</code>. Levels: {}",
+            html_escape(&arg),
+            levels
+        )
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
 /// Auto-restore session from bot_settings.json if not in memory.
 /// If there is no previous path, fall back to startup project dir.
 pub(crate) fn auto_restore_session(
     data: &mut super::bot::SharedData,
     chat_id: ChatId,
     default_project_dir: &str,
-    user_name: &str,
 ) {
     if !data.sessions.contains_key(&chat_id) {
         let candidate_path = data
@@ -561,15 +983,16 @@ pub(crate) fn auto_restore_session(
                 current_path: None,
                 history: Vec::new(),
                 pending_uploads: Vec::new(),
+                pending_attachments: Vec::new(),
                 cleared: false,
+                thinking_budget: None,
             });
             session.current_path = Some(candidate_path.clone());
             if let Some((session_data, _)) = existing {
                 session.session_id = Some(session_data.session_id.clone());
                 session.history = session_data.history.clone();
             }
-            let ts = chrono::Local::now().format("%H:%M:%S");
-            println!("  [{ts}] ↻ [{user_name}] Auto-restored session: {candidate_path}");
+            tracing::info!("Auto-restored session: {candidate_path}");
         }
     }
 }
@@ -2,13 +2,21 @@ use std::fs;
 use std::path::Path;
 
 use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode};
 
+use crate::audit::{self, AuditEntry, AuditEvent};
 use crate::auth::{is_path_within_sandbox, DEFAULT_UPLOAD_LIMIT};
+use crate::child_env;
+use crate::confine;
+use crate::limits;
+use crate::media;
 use crate::session::{HistoryItem, HistoryType};
+use crate::shell_guard;
+use crate::trace;
 
 use super::bot::{shared_rate_limit_wait, SharedState};
-use super::storage::save_session_to_file;
-use super::streaming::send_long_message;
+use super::storage::{save_bot_settings, save_session_to_file};
+use super::streaming::{html_escape, redact_outbound, send_long_message, truncate_str};
 
 /// Handle /down <filepath> - send file to user
 pub(crate) async fn handle_down_command(
@@ -89,6 +97,44 @@ pub(crate) async fn handle_down_command(
     Ok(())
 }
 
+/// Handle /trace - send the raw stream capture of this chat's last AI run
+pub(crate) async fn handle_trace_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let loaded = tokio::task::spawn_blocking(move || {
+        let path = trace::latest(chat_id.0)?;
+        let content = fs::read_to_string(&path).ok()?;
+        let name = path.file_name()?.to_string_lossy().into_owned();
+        Some((name, content))
+    })
+    .await
+    .ok()
+    .flatten();
+
+    let Some((name, content)) = loaded else {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, "No AI run has been traced in this chat yet.")
+            .await?;
+        return Ok(());
+    };
+
+    // Tool results in the stream can contain anything the AI read
+    let content = redact_outbound(state, &content).await;
+    let caption = format!("Trace {} ({} lines)", name, content.lines().count());
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_document(
+        chat_id,
+        teloxide::types::InputFile::memory(content.into_bytes()).file_name(name),
+    )
+    .caption(caption)
+    .await?;
+
+    Ok(())
+}
+
 /// Handle file/photo upload - save to current session path
 pub(crate) async fn handle_file_upload(
     bot: &Bot,
@@ -171,6 +217,33 @@ pub(crate) async fn handle_file_upload(
         return Ok(());
     }
 
+    // Images and PDFs also go to Claude as content with the next prompt
+    let bytes = buf.clone();
+    let attachment = tokio::task::spawn_blocking(move || media::content_block(&bytes))
+        .await
+        .unwrap_or_else(|e| Err(format!("Failed to read file: {}", e)));
+    // Everything attached to one prompt shares the API's request size limit
+    let attachment = match attachment {
+        Ok(Some(block)) => {
+            let data = state.lock().await;
+            let attached: usize = data
+                .sessions
+                .get(&chat_id)
+                .map(|s| s.pending_attachments.iter().map(media::encoded_len).sum())
+                .unwrap_or(0);
+            if attached + media::encoded_len(&block) > media::MAX_PROMPT_ATTACHMENT_BYTES {
+                Err(format!(
+                    "the files attached to one prompt are limited to {} MB in total. \
+                     Send a prompt first, then upload the rest.",
+                    media::MAX_PROMPT_ATTACHMENT_BYTES / 1024 / 1024
+                ))
+            } else {
+                Ok(Some(block))
+            }
+        }
+        other => other,
+    };
+
     // Save to session path (sanitize file_name to prevent path traversal)
     let safe_name = Path::new(&file_name)
         .file_name()
@@ -179,7 +252,12 @@ pub(crate) async fn handle_file_upload(
     let file_size = buf.len();
     match fs::write(&dest, &buf) {
         Ok(_) => {
-            let msg_text = format!("Saved: {}\n({} bytes)", dest.display(), file_size);
+            let note = match &attachment {
+                Ok(Some(_)) => "\nAttached to your next prompt.".to_string(),
+                Ok(None) => String::new(),
+                Err(e) => format!("\nNot attached to the prompt: {}", e),
+            };
+            let msg_text = format!("Saved: {}\n({} bytes){}", dest.display(), file_size, note);
             shared_rate_limit_wait(state, chat_id).await;
             bot.send_message(chat_id, msg_text).await?;
         }
@@ -206,6 +284,9 @@ pub(crate) async fn handle_file_upload(
                 content: upload_record.clone(),
             });
             session.pending_uploads.push(upload_record);
+            if let Ok(Some(block)) = attachment {
+                session.pending_attachments.push(block);
+            }
             save_session_to_file(session, &save_dir);
         }
     }
@@ -213,12 +294,22 @@ pub(crate) async fn handle_file_upload(
     Ok(())
 }
 
-/// Handle !command - execute shell command directly
+/// A destructive `!` command waiting for the Confirm/Cancel keyboard
+pub(crate) struct PendingShell {
+    pub(crate) message_id: MessageId,
+    pub(crate) command: String,
+    pub(crate) working_dir: String,
+    /// Destructive pattern ids that triggered the prompt
+    pub(crate) pattern_ids: Vec<String>,
+}
+
+/// Handle !command - execute shell command, asking first if it looks destructive
 pub(crate) async fn handle_shell_command(
     bot: &Bot,
     chat_id: ChatId,
     text: &str,
     state: &SharedState,
+    user_id: u64,
 ) -> ResponseResult<()> {
     let cmd_str = text.strip_prefix('!').unwrap_or("").trim();
 
@@ -233,40 +324,202 @@ pub(crate) async fn handle_shell_command(
     }
 
     // Get current_path for working directory (default to home directory)
-    let working_dir = {
+    let (working_dir, always_allowed) = {
         let data = state.lock().await;
-        data.sessions
+        let working_dir = data
+            .sessions
             .get(&chat_id)
             .and_then(|s| s.current_path.clone())
             .unwrap_or_else(|| {
                 dirs::home_dir()
                     .map(|h| h.display().to_string())
                     .unwrap_or_else(|| "/".to_string())
-            })
+            });
+        let always_allowed = data
+            .settings
+            .shell_always_allow
+            .get(&working_dir)
+            .cloned()
+            .unwrap_or_default();
+        (working_dir, always_allowed)
     };
 
+    let matched: Vec<_> = shell_guard::analyze(cmd_str)
+        .into_iter()
+        .filter(|p| !always_allowed.iter().any(|id| id == p.id))
+        .collect();
+    if matched.is_empty() {
+        return run_shell_command(bot, chat_id, cmd_str, &working_dir, state, user_id).await;
+    }
+
+    let reasons: Vec<String> = matched
+        .iter()
+        .map(|p| format!("• {}", html_escape(p.description)))
+        .collect();
+    // Cut before escaping so the <pre> block always stays closed
+    let prompt = format!(
+        "⚠️ <b>This command looks destructive</b>\n<pre>{}</pre>\n{}\n\nDirectory: <code>{}</code>",
+        html_escape(&truncate_str(cmd_str, 500)),
+        reasons.join("\n"),
+        html_escape(&truncate_str(&working_dir, 200))
+    );
+    let keyboard = InlineKeyboardMarkup::new(vec![
+        vec![
+            InlineKeyboardButton::callback("▶ Run", "sh:ok"),
+            InlineKeyboardButton::callback("✖ Cancel", "sh:no"),
+        ],
+        vec![InlineKeyboardButton::callback(
+            "Always allow in this project",
+            "sh:always",
+        )],
+    ]);
+
+    shared_rate_limit_wait(state, chat_id).await;
+    let sent = bot
+        .send_message(chat_id, prompt)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
+        .await?;
+
+    let mut data = state.lock().await;
+    data.pending_shells.insert(
+        chat_id,
+        PendingShell {
+            message_id: sent.id,
+            command: cmd_str.to_string(),
+            working_dir,
+            pattern_ids: matched.iter().map(|p| p.id.to_string()).collect(),
+        },
+    );
+    Ok(())
+}
+
+/// Handle `sh:` callbacks: `ok` runs the pending command, `always` also remembers its
+/// patterns for the project, `no` cancels it
+pub(crate) async fn handle_shell_callback(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    action: &str,
+    state: &SharedState,
+    token: &str,
+    user_id: u64,
+) -> ResponseResult<Option<String>> {
+    let pending = {
+        let mut data = state.lock().await;
+        match data.pending_shells.get(&chat_id) {
+            Some(p) if p.message_id == message_id => data.pending_shells.remove(&chat_id),
+            _ => None,
+        }
+    };
+    let Some(pending) = pending else {
+        return Ok(Some("This command request has expired.".to_string()));
+    };
+
+    let status = match action {
+        "ok" => "▶ Running",
+        "always" => {
+            let mut data = state.lock().await;
+            let allowed = data
+                .settings
+                .shell_always_allow
+                .entry(pending.working_dir.clone())
+                .or_default();
+            for id in &pending.pattern_ids {
+                if !allowed.contains(id) {
+                    allowed.push(id.clone());
+                }
+            }
+            save_bot_settings(token, &data.settings);
+            "▶ Running (always allowed in this project)"
+        }
+        _ => "✖ Cancelled",
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    if let Err(e) = bot
+        .edit_message_text(
+            chat_id,
+            message_id,
+            format!("{}\n<pre>{}</pre>", status, html_escape(&pending.command)),
+        )
+        .parse_mode(ParseMode::Html)
+        .await
+    {
+        tracing::warn!("edit_message failed (shell confirm): {e}");
+    }
+
+    if action == "ok" || action == "always" {
+        tracing::info!("Confirmed shell: {}", truncate_str(&pending.command, 60));
+        run_shell_command(
+            bot,
+            chat_id,
+            &pending.command,
+            &pending.working_dir,
+            state,
+            user_id,
+        )
+        .await?;
+    }
+    Ok(None)
+}
+
+/// Run `cmd_str` with bash in `working_dir` and send its output
+async fn run_shell_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    cmd_str: &str,
+    working_dir: &str,
+    state: &SharedState,
+    user_id: u64,
+) -> ResponseResult<()> {
     let cmd_owned = cmd_str.to_string();
-    let working_dir_clone = working_dir.clone();
+    let working_dir_clone = working_dir.to_string();
+
+    let mut entry = AuditEntry::new(
+        AuditEvent::Shell,
+        user_id,
+        chat_id.0,
+        &redact_outbound(state, &format!("!{}", cmd_str)).await,
+        true,
+    );
+    entry.working_dir = Some(working_dir.to_string());
+    let started = std::time::Instant::now();
 
     // Run shell command in blocking thread with stdin closed and timeout
     let result = tokio::task::spawn_blocking(move || {
-        let child = std::process::Command::new("bash")
+        let mut command = std::process::Command::new("bash");
+        command
             .args(["-c", &cmd_owned])
             .current_dir(&working_dir_clone)
+            .env_clear()
+            .envs(child_env::vars_for(Path::new(&working_dir_clone)))
             .stdin(std::process::Stdio::null())
             .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn();
+            .stderr(std::process::Stdio::piped());
+        let cgroup_scope = limits::apply(&mut command);
+        confine::apply(&mut command, Path::new(&working_dir_clone), &[])
+            .map_err(std::io::Error::other)?;
 
-        match child {
-            Ok(child) => child.wait_with_output(),
-            Err(e) => Err(e),
-        }
+        let output = command.spawn().and_then(|child| child.wait_with_output())?;
+        let violation = limits::describe_exit(
+            &output.status,
+            &String::from_utf8_lossy(&output.stderr),
+            cgroup_scope.as_ref(),
+        );
+        Ok::<_, std::io::Error>((output, violation))
     })
     .await;
 
+    entry.duration_ms = Some(started.elapsed().as_millis() as u64);
+    match &result {
+        Ok(Ok((output, _))) => entry.exit_code = Some(output.status.code().unwrap_or(-1)),
+        _ => entry.outcome = Some("error".to_string()),
+    }
+    audit::record(&entry);
+
     let response = match result {
-        Ok(Ok(output)) => {
+        Ok(Ok((output, violation))) => {
             let stdout = String::from_utf8_lossy(&output.stdout);
             let stderr = String::from_utf8_lossy(&output.stderr);
             let exit_code = output.status.code().unwrap_or(-1);
@@ -274,41 +527,28 @@ pub(crate) async fn handle_shell_command(
             let mut parts = Vec::new();
 
             if !stdout.is_empty() {
-                parts.push(format!(
-                    "<pre>{}</pre>",
-                    super::streaming::html_escape(stdout.trim_end())
-                ));
+                parts.push(format!("<pre>{}</pre>", html_escape(stdout.trim_end())));
             }
             if !stderr.is_empty() {
                 parts.push(format!(
                     "stderr:\n<pre>{}</pre>",
-                    super::streaming::html_escape(stderr.trim_end())
+                    html_escape(stderr.trim_end())
                 ));
             }
             if parts.is_empty() || exit_code != 0 {
                 parts.push(format!("(exit code: {})", exit_code));
             }
+            if let Some(reason) = violation {
+                parts.push(html_escape(&reason));
+            }
 
             parts.join("\n")
         }
-        Ok(Err(e)) => format!(
-            "Failed to execute: {}",
-            super::streaming::html_escape(&e.to_string())
-        ),
-        Err(e) => format!(
-            "Task error: {}",
-            super::streaming::html_escape(&e.to_string())
-        ),
+        Ok(Err(e)) => format!("Failed to execute: {}", html_escape(&e.to_string())),
+        Err(e) => format!("Task error: {}", html_escape(&e.to_string())),
     };
 
-    send_long_message(
-        bot,
-        chat_id,
-        &response,
-        Some(teloxide::types::ParseMode::Html),
-        state,
-    )
-    .await?;
+    send_long_message(bot, chat_id, &response, Some(ParseMode::Html), state).await?;
 
     Ok(())
 }
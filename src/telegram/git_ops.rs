@@ -0,0 +1,451 @@
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode};
+
+use crate::git::{self, FileChange};
+
+use super::bot::{shared_rate_limit_wait, SharedState, TELEGRAM_MSG_LIMIT};
+use super::streaming::{html_escape, send_long_message, truncate_str};
+
+/// Max file buttons shown under a /diff summary
+const DIFF_MAX_BUTTONS: usize = 30;
+
+/// Diffs longer than this are cut before sending
+const DIFF_MAX_BYTES: usize = 30_000;
+
+/// Space for the file list in /diff and /commit messages; further files are counted only
+const CHANGE_LIST_BYTES: usize = 2400;
+
+/// Longest path shown in a file list, before escaping
+const CHANGE_PATH_MAX: usize = 150;
+
+/// Default and maximum number of commits listed by /log
+const LOG_DEFAULT_COUNT: usize = 10;
+const LOG_MAX_COUNT: usize = 50;
+
+/// Per-chat /diff file list backing the expand buttons
+pub(crate) struct DiffView {
+    pub(crate) message_id: MessageId,
+    pub(crate) repo_root: String,
+    pub(crate) files: Vec<FileChange>,
+}
+
+/// A /commit waiting for the Confirm/Cancel keyboard
+pub(crate) struct PendingCommit {
+    pub(crate) message_id: MessageId,
+    pub(crate) repo_root: String,
+    pub(crate) message: String,
+}
+
+/// Format "+added -removed" for a change, or a short note when counts are unknown
+pub(crate) fn format_change_counts(change: &FileChange) -> String {
+    match (change.status, change.added, change.removed) {
+        ('?', _, _) => "(new)".to_string(),
+        (_, Some(a), Some(r)) => format!("+{} -{}", a, r),
+        _ => "(binary)".to_string(),
+    }
+}
+
+/// Render a file list as "M path +1 -2" lines with an overall total.
+/// Lines are dropped whole once they fill CHANGE_LIST_BYTES, so the markup stays intact.
+pub(crate) fn format_change_list(files: &[FileChange]) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut used = 0;
+    for f in files {
+        let line = format!(
+            "<code>{}</code> {} {}",
+            f.status,
+            html_escape(&truncate_str(&f.path, CHANGE_PATH_MAX)),
+            format_change_counts(f)
+        );
+        if used + line.len() + 1 > CHANGE_LIST_BYTES {
+            lines.push(format!("… and {} more", files.len() - lines.len()));
+            break;
+        }
+        used += line.len() + 1;
+        lines.push(line);
+    }
+    let added: u64 = files.iter().filter_map(|f| f.added).sum();
+    let removed: u64 = files.iter().filter_map(|f| f.removed).sum();
+    lines.push(format!(
+        "\n{} file{} · +{} -{}",
+        files.len(),
+        if files.len() == 1 { "" } else { "s" },
+        added,
+        removed
+    ));
+    lines.join("\n")
+}
+
+/// Resolve the session directory and its git repository root.
+/// Sends an explanatory message and returns None when either is missing.
+async fn session_repo_root(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+) -> ResponseResult<Option<String>> {
+    let current_path = {
+        let data = state.lock().await;
+        data.sessions
+            .get(&chat_id)
+            .and_then(|s| s.current_path.clone())
+    };
+    let Some(dir) = current_path else {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, "No active session. Use /start <path> first.")
+            .await?;
+        return Ok(None);
+    };
+
+    let dir_clone = dir.clone();
+    let root = tokio::task::spawn_blocking(move || git::repo_root(&dir_clone))
+        .await
+        .ok()
+        .flatten();
+    if root.is_none() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, format!("Not a git repository: {}", dir))
+            .await?;
+    }
+    Ok(root)
+}
+
+/// Handle /diff - per-file change summary with buttons to expand each file
+pub(crate) async fn handle_diff_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let Some(root) = session_repo_root(bot, chat_id, state).await? else {
+        return Ok(());
+    };
+
+    let root_clone = root.clone();
+    let files = match tokio::task::spawn_blocking(move || git::changed_files(&root_clone)).await {
+        Ok(Ok(files)) => files,
+        Ok(Err(e)) => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, format!("git error: {}", e))
+                .await?;
+            return Ok(());
+        }
+        Err(e) => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, format!("Task error: {}", e))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    if files.is_empty() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, "No changes. Working tree is clean.")
+            .await?;
+        return Ok(());
+    }
+
+    let text = format!(
+        "<b>Changes</b> in <code>{}</code>\n\n{}",
+        html_escape(&truncate_str(&root, CHANGE_PATH_MAX)),
+        format_change_list(&files)
+    );
+    let mut keyboard = InlineKeyboardMarkup::default();
+    for (idx, file) in files.iter().enumerate().take(DIFF_MAX_BUTTONS) {
+        keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+            format!("{} {}", file.status, truncate_str(&file.path, 50)),
+            format!("git:d:{}", idx),
+        )]);
+    }
+
+    shared_rate_limit_wait(state, chat_id).await;
+    let sent = bot
+        .send_message(chat_id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
+        .await?;
+
+    let mut data = state.lock().await;
+    data.diff_views.insert(
+        chat_id,
+        DiffView {
+            message_id: sent.id,
+            repo_root: root,
+            files,
+        },
+    );
+
+    Ok(())
+}
+
+/// Send one file's unified diff as a code block
+async fn send_file_diff(
+    bot: &Bot,
+    chat_id: ChatId,
+    root: &str,
+    change: FileChange,
+    state: &SharedState,
+) -> ResponseResult<Option<String>> {
+    let root_owned = root.to_string();
+    let path = change.path.clone();
+    let diff = match tokio::task::spawn_blocking(move || git::file_diff(&root_owned, &change)).await
+    {
+        Ok(Ok(diff)) => diff,
+        Ok(Err(e)) => return Ok(Some(format!("git error: {}", truncate_str(&e, 150)))),
+        Err(e) => return Ok(Some(format!("Task error: {}", e))),
+    };
+    if diff.trim().is_empty() {
+        return Ok(Some("No textual diff for this file.".to_string()));
+    }
+
+    let mut body = truncate_str(diff.trim_end(), DIFF_MAX_BYTES);
+    if body.len() < diff.trim_end().len() {
+        body.push_str("\n… (diff truncated)");
+    }
+    let header = format!("<b>{}</b>\n", html_escape(&path));
+    let single = format!(
+        "{}<pre><code class=\"language-diff\">{}</code></pre>",
+        header,
+        html_escape(&body)
+    );
+    // send_long_message only re-balances plain <pre>, so use it for multi-part diffs
+    let text = if single.len() <= TELEGRAM_MSG_LIMIT {
+        single
+    } else {
+        format!("{}<pre>{}</pre>", header, html_escape(&body))
+    };
+    send_long_message(bot, chat_id, &text, Some(ParseMode::Html), state).await?;
+    Ok(None)
+}
+
+/// Handle /log [n] - list recent commits
+pub(crate) async fn handle_log_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let arg = text.strip_prefix("/log").unwrap_or("").trim();
+    let count = if arg.is_empty() {
+        LOG_DEFAULT_COUNT
+    } else {
+        match arg.parse::<usize>() {
+            Ok(n) if n > 0 => n.min(LOG_MAX_COUNT),
+            _ => {
+                shared_rate_limit_wait(state, chat_id).await;
+                bot.send_message(chat_id, "Usage: /log [count]\nExample: /log 20")
+                    .await?;
+                return Ok(());
+            }
+        }
+    };
+
+    let Some(root) = session_repo_root(bot, chat_id, state).await? else {
+        return Ok(());
+    };
+
+    let root_clone = root.clone();
+    let response =
+        match tokio::task::spawn_blocking(move || git::recent_log(&root_clone, count)).await {
+            Ok(Ok(entries)) if entries.is_empty() => "No commits yet.".to_string(),
+            Ok(Ok(entries)) => {
+                let mut lines = vec![format!(
+                    "<b>Recent commits</b> in <code>{}</code>\n",
+                    html_escape(&root)
+                )];
+                for e in entries {
+                    lines.push(format!(
+                        "<code>{}</code> {} — <i>{}, {}</i>",
+                        html_escape(&e.hash),
+                        html_escape(&e.subject),
+                        html_escape(&e.author),
+                        html_escape(&e.when)
+                    ));
+                }
+                lines.join("\n")
+            }
+            Ok(Err(e)) => format!("git error: {}", html_escape(&e)),
+            Err(e) => format!("Task error: {}", html_escape(&e.to_string())),
+        };
+
+    send_long_message(bot, chat_id, &response, Some(ParseMode::Html), state).await?;
+    Ok(())
+}
+
+/// Handle /commit <message> - show pending changes with a Confirm/Cancel keyboard
+pub(crate) async fn handle_commit_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let message = text.strip_prefix("/commit").unwrap_or("").trim();
+    if message.is_empty() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(
+            chat_id,
+            "Usage: /commit <message>\nExample: /commit Fix login redirect",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(root) = session_repo_root(bot, chat_id, state).await? else {
+        return Ok(());
+    };
+
+    let root_clone = root.clone();
+    let files = match tokio::task::spawn_blocking(move || git::changed_files(&root_clone)).await {
+        Ok(Ok(files)) => files,
+        Ok(Err(e)) => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, format!("git error: {}", e))
+                .await?;
+            return Ok(());
+        }
+        Err(e) => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, format!("Task error: {}", e))
+                .await?;
+            return Ok(());
+        }
+    };
+    if files.is_empty() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, "Nothing to commit. Working tree is clean.")
+            .await?;
+        return Ok(());
+    }
+
+    let prompt = format!(
+        "<b>Commit all changes?</b>\n<code>{}</code>\n\nMessage: <i>{}</i>\n\n{}",
+        html_escape(&truncate_str(&root, CHANGE_PATH_MAX)),
+        html_escape(&truncate_str(message, 150)),
+        format_change_list(&files)
+    );
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("✅ Commit", "git:c:ok"),
+        InlineKeyboardButton::callback("✖ Cancel", "git:c:no"),
+    ]]);
+
+    shared_rate_limit_wait(state, chat_id).await;
+    let sent = bot
+        .send_message(chat_id, prompt)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
+        .await?;
+
+    let mut data = state.lock().await;
+    data.pending_commits.insert(
+        chat_id,
+        PendingCommit {
+            message_id: sent.id,
+            repo_root: root,
+            message: message.to_string(),
+        },
+    );
+
+    Ok(())
+}
+
+/// Handle `git:*` callbacks: `d:<idx>` expands a /diff file, `c:ok`/`c:no` resolves a /commit.
+/// Returns an optional toast to show in the callback answer.
+pub(crate) async fn handle_git_callback(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    action: &str,
+    state: &SharedState,
+) -> ResponseResult<Option<String>> {
+    let (verb, arg) = action.split_once(':').unwrap_or((action, ""));
+    match verb {
+        "d" => {
+            let target = {
+                let data = state.lock().await;
+                data.diff_views
+                    .get(&chat_id)
+                    .filter(|v| v.message_id == message_id)
+                    .map(|v| {
+                        let file = arg.parse::<usize>().ok().and_then(|i| v.files.get(i));
+                        (v.repo_root.clone(), file.cloned())
+                    })
+            };
+            match target {
+                Some((root, Some(change))) => {
+                    send_file_diff(bot, chat_id, &root, change, state).await
+                }
+                Some((_, None)) => Ok(Some("File not found. Run /diff again.".to_string())),
+                None => Ok(Some("This diff has expired. Run /diff again.".to_string())),
+            }
+        }
+        "c" => {
+            let pending = {
+                let mut data = state.lock().await;
+                match data.pending_commits.get(&chat_id) {
+                    Some(p) if p.message_id == message_id => data.pending_commits.remove(&chat_id),
+                    _ => None,
+                }
+            };
+            let Some(pending) = pending else {
+                return Ok(Some("This commit request has expired.".to_string()));
+            };
+
+            let result_text = if arg == "ok" {
+                let root = pending.repo_root.clone();
+                let message = pending.message.clone();
+                let result =
+                    tokio::task::spawn_blocking(move || git::commit_all(&root, &message)).await;
+                match result {
+                    Ok(Ok(output)) => {
+                        tracing::info!("Committed: {}", truncate_str(&pending.message, 60));
+                        format!(
+                            "✅ Committed\n<pre>{}</pre>",
+                            html_escape(&truncate_str(output.trim(), 3000))
+                        )
+                    }
+                    Ok(Err(e)) => format!(
+                        "❌ Commit failed\n<pre>{}</pre>",
+                        html_escape(&truncate_str(&e, 3000))
+                    ),
+                    Err(e) => format!("Task error: {}", html_escape(&e.to_string())),
+                }
+            } else {
+                "✖ Commit cancelled.".to_string()
+            };
+
+            shared_rate_limit_wait(state, chat_id).await;
+            if let Err(e) = bot
+                .edit_message_text(chat_id, message_id, result_text)
+                .parse_mode(ParseMode::Html)
+                .await
+            {
+                tracing::warn!("edit_message failed (commit): {e}");
+            }
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_change_list_drops_whole_lines() {
+        let files: Vec<FileChange> = (0..500)
+            .map(|n| FileChange {
+                path: format!("src/<dir>/file_{}.rs", n),
+                status: 'M',
+                added: Some(1),
+                removed: Some(0),
+            })
+            .collect();
+        let text = format_change_list(&files);
+        assert!(text.len() < CHANGE_LIST_BYTES + 100);
+        assert!(text.contains("more"));
+        assert!(text.ends_with("500 files · +500 -0"));
+        assert_eq!(
+            text.matches("<code>").count(),
+            text.matches("</code>").count()
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::Path;
+
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use super::bot::{shared_rate_limit_wait, SharedState};
+use super::storage::save_bot_settings;
+use super::streaming::{html_escape, truncate_str};
+
+/// Longest project instructions file read; the rest is left out
+const PROJECT_FILE_LIMIT: usize = 16 * 1024;
+
+/// Longest chat instructions accepted by /systemprompt set|append
+const CHAT_PROMPT_LIMIT: usize = 8 * 1024;
+
+/// Instructions file read from the root of the session directory
+pub(crate) fn project_file_name() -> String {
+    format!("{}.md", crate::app::dir_name())
+}
+
+/// Contents of the session directory's instructions file, if it has one
+pub(crate) fn load_project_instructions(working_dir: &str) -> Option<String> {
+    let content = fs::read_to_string(Path::new(working_dir).join(project_file_name())).ok()?;
+    let content = content.trim();
+    (!content.is_empty()).then(|| truncate_str(content, PROJECT_FILE_LIMIT))
+}
+
+/// System prompt of a run, lowest precedence first: the project file, then
+/// the chat's /systemprompt, then the bot's Telegram rules, which always apply.
+/// The CLI's own system prompt comes before all of them.
+pub(crate) fn compose(project: Option<&str>, chat: Option<&str>, telegram: &str) -> String {
+    let mut sections = Vec::new();
+    if let Some(project) = project {
+        sections.push(format!(
+            "PROJECT INSTRUCTIONS ({}):\n{}",
+            project_file_name(),
+            project
+        ));
+    }
+    if let Some(chat) = chat {
+        sections.push(format!(
+            "CHAT INSTRUCTIONS (take precedence over project instructions):\n{}",
+            chat
+        ));
+    }
+    sections.push(telegram.to_string());
+    sections.join("\n\n")
+}
+
+/// Handle /systemprompt command - extra instructions for this chat's AI runs
+/// Usage: /systemprompt [show]        (chat and project instructions in effect)
+///        /systemprompt set <text>    (replace the chat instructions)
+///        /systemprompt append <text> (add a line to them)
+///        /systemprompt reset         (remove them)
+pub(crate) async fn handle_systemprompt_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let arg = text.strip_prefix("/systemprompt").unwrap_or("").trim();
+    let (action, rest) = arg
+        .split_once(char::is_whitespace)
+        .map(|(a, r)| (a, r.trim()))
+        .unwrap_or((arg, ""));
+    let action = action.to_lowercase();
+    let chat_key = chat_id.0.to_string();
+    let usage = "<code>/systemprompt show</code> — Instructions in effect\n\
+                 <code>/systemprompt set &lt;text&gt;</code> — Replace this chat's instructions\n\
+                 <code>/systemprompt append &lt;text&gt;</code> — Add to them\n\
+                 <code>/systemprompt reset</code> — Remove them";
+
+    let response_msg = match action.as_str() {
+        "" | "show" => {
+            let (chat, working_dir) = {
+                let data = state.lock().await;
+                (
+                    data.settings.system_prompts.get(&chat_key).cloned(),
+                    data.sessions
+                        .get(&chat_id)
+                        .and_then(|s| s.current_path.clone()),
+                )
+            };
+            let project = working_dir.as_deref().and_then(load_project_instructions);
+            let section = |text: Option<&str>| match text {
+                Some(text) => format!("<pre>{}</pre>", html_escape(&truncate_str(text, 1500))),
+                None => "(none)".to_string(),
+            };
+            format!(
+                "<b>Chat instructions</b> (/systemprompt)\n{}\n\n<b>Project instructions</b> ({})\n{}\n\n\
+                 Chat instructions take precedence over the project file. \
+                 The bot's Telegram rules always apply.\n\n{}",
+                section(chat.as_deref()),
+                html_escape(&project_file_name()),
+                section(project.as_deref()),
+                usage
+            )
+        }
+        "set" | "append" if rest.is_empty() => format!("Usage:\n{}", usage),
+        "set" | "append" => {
+            let mut data = state.lock().await;
+            let combined = match (action.as_str(), data.settings.system_prompts.get(&chat_key)) {
+                ("append", Some(current)) => format!("{}\n{}", current, rest),
+                _ => rest.to_string(),
+            };
+            if combined.len() > CHAT_PROMPT_LIMIT {
+                format!(
+                    "Chat instructions are limited to {} bytes.",
+                    CHAT_PROMPT_LIMIT
+                )
+            } else {
+                data.settings.system_prompts.insert(chat_key, combined);
+                save_bot_settings(token, &data.settings);
+                "✅ Chat instructions updated. They apply from the next prompt.".to_string()
+            }
+        }
+        "reset" => {
+            let mut data = state.lock().await;
+            data.settings.system_prompts.remove(&chat_key);
+            save_bot_settings(token, &data.settings);
+            "Chat instructions removed.".to_string()
+        }
+        _ => format!("Usage:\n{}", usage),
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose_precedence() {
+        let prompt = compose(Some("Use tabs."), Some("Use spaces."), "TELEGRAM");
+        let project = prompt.find("Use tabs.");
+        let chat = prompt.find("Use spaces.");
+        let telegram = prompt.find("TELEGRAM");
+        assert!(project < chat && chat < telegram);
+        assert!(prompt.starts_with("PROJECT INSTRUCTIONS (.openclaude.md):"));
+
+        assert_eq!(compose(None, None, "TELEGRAM"), "TELEGRAM");
+    }
+}
@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+use serde_json::{json, Value};
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode};
+
+use crate::claude::ToolDecision;
+
+use super::bot::{shared_rate_limit_wait, SharedState};
+use super::streaming::{
+    escape_within, html_escape, markdown_to_telegram_html, redact_outbound, truncate_str,
+};
+
+/// Longest plan shown in the approval message (markdown, before conversion)
+const PLAN_PREVIEW_LIMIT: usize = 3000;
+
+/// Room for a rendered question, plan or answer summary; the rest of the
+/// message limit is left for the hints added around it
+const BODY_HTML_LIMIT: usize = 3500;
+
+/// Longest option label shown on a button
+const BUTTON_LABEL_LIMIT: usize = 40;
+
+/// One question of an AskUserQuestion call
+struct Question {
+    header: String,
+    question: String,
+    /// (label, description) of each choice
+    options: Vec<(String, String)>,
+    multi_select: bool,
+}
+
+enum Kind {
+    /// AskUserQuestion: the questions are asked one at a time in the same message
+    Questions {
+        questions: Vec<Question>,
+        answers: Vec<String>,
+        /// Options ticked so far on the current multi-select question
+        selected: Vec<usize>,
+    },
+    /// ExitPlanMode: approve the plan, or send feedback to revise it
+    Plan { plan: String },
+}
+
+/// A question or plan from the running AI, waiting for an answer in the chat
+pub(crate) struct PendingInteraction {
+    message_id: MessageId,
+    /// The user whose prompt started the run; they and the owner may answer
+    user_id: u64,
+    input: Value,
+    kind: Kind,
+    /// Set by "Other" / "Revise": the next text message is the answer
+    awaiting_text: bool,
+    reply: Sender<ToolDecision>,
+}
+
+/// Questions of an AskUserQuestion input
+fn parse_questions(input: &Value) -> Vec<Question> {
+    let text = |v: &Value, key: &str| {
+        v.get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    input
+        .get("questions")
+        .and_then(|v| v.as_array())
+        .map(|questions| {
+            questions
+                .iter()
+                .map(|q| Question {
+                    header: text(q, "header"),
+                    question: text(q, "question"),
+                    options: q
+                        .get("options")
+                        .and_then(|v| v.as_array())
+                        .map(|options| {
+                            options
+                                .iter()
+                                .map(|o| (text(o, "label"), text(o, "description")))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    multi_select: q
+                        .get("multiSelect")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The AskUserQuestion input with the user's answers added, keyed by question text
+fn answered_input(input: &Value, questions: &[Question], answers: &[String]) -> Value {
+    let answers: HashMap<&str, &str> = questions
+        .iter()
+        .zip(answers)
+        .map(|(q, a)| (q.question.as_str(), a.as_str()))
+        .collect();
+    let mut updated = input.clone();
+    updated["answers"] = json!(answers);
+    updated
+}
+
+/// Hint appended while a typed answer is expected
+fn typing_hint(prompt: &str) -> String {
+    format!(
+        "\n\n✏️ <i>{} (in groups, start it with ;)</i>",
+        html_escape(prompt)
+    )
+}
+
+impl PendingInteraction {
+    /// Message text and buttons for the current state
+    fn render(&self) -> (String, InlineKeyboardMarkup) {
+        match &self.kind {
+            Kind::Questions {
+                questions,
+                answers,
+                selected,
+            } => {
+                let index = answers.len();
+                let Some(q) = questions.get(index) else {
+                    return (String::new(), InlineKeyboardMarkup::default());
+                };
+                let mut text = format!("❓ <b>{}</b>", escape_within(&q.header, 200));
+                if questions.len() > 1 {
+                    text.push_str(&format!(" · {}/{}", index + 1, questions.len()));
+                }
+                text.push_str(&format!("\n{}\n", escape_within(&q.question, 1500)));
+                // Options that do not fit are left to their buttons
+                for (label, description) in &q.options {
+                    let mut option = format!("\n• <b>{}</b>", escape_within(label, 200));
+                    if !description.is_empty() {
+                        option.push_str(&format!(" — {}", escape_within(description, 400)));
+                    }
+                    if text.len() + option.len() > BODY_HTML_LIMIT {
+                        text.push_str("\n…");
+                        break;
+                    }
+                    text.push_str(&option);
+                }
+                if self.awaiting_text {
+                    text.push_str(&typing_hint("Send your answer as a message."));
+                }
+
+                let mut rows: Vec<Vec<InlineKeyboardButton>> = q
+                    .options
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (label, _))| {
+                        let label = truncate_str(label, BUTTON_LABEL_LIMIT);
+                        let label = match (q.multi_select, selected.contains(&i)) {
+                            (false, _) => label,
+                            (true, true) => format!("☑ {}", label),
+                            (true, false) => format!("☐ {}", label),
+                        };
+                        vec![InlineKeyboardButton::callback(label, format!("ask:o{}", i))]
+                    })
+                    .collect();
+                let mut last = vec![InlineKeyboardButton::callback("✏️ Other", "ask:other")];
+                if q.multi_select {
+                    last.insert(0, InlineKeyboardButton::callback("✔ Done", "ask:done"));
+                }
+                rows.push(last);
+                (text, InlineKeyboardMarkup::new(rows))
+            }
+            Kind::Plan { .. } => {
+                let mut text = self.plan_html();
+                if self.awaiting_text {
+                    text.push_str(&typing_hint("Send what to change as a message."));
+                }
+                let keyboard = InlineKeyboardMarkup::new(vec![vec![
+                    InlineKeyboardButton::callback("✅ Approve", "ask:ok"),
+                    InlineKeyboardButton::callback("✏️ Revise", "ask:revise"),
+                ]]);
+                (text, keyboard)
+            }
+        }
+    }
+
+    /// The plan as HTML, cut in its markdown form until the rendered markup fits
+    fn plan_html(&self) -> String {
+        let Kind::Plan { plan } = &self.kind else {
+            return String::new();
+        };
+        let mut budget = PLAN_PREVIEW_LIMIT;
+        loop {
+            let html = format!(
+                "📋 <b>Plan</b>\n\n{}",
+                markdown_to_telegram_html(&truncate_str(plan, budget))
+            );
+            if html.len() <= BODY_HTML_LIMIT - 600 || budget < 200 {
+                return html;
+            }
+            budget /= 2;
+        }
+    }
+
+    /// Record the answer to the current question. Returns the decision once
+    /// every question is answered, with the summary shown in place of the buttons.
+    fn answer(&mut self, answer: String) -> Option<(ToolDecision, String)> {
+        self.awaiting_text = false;
+        match &mut self.kind {
+            Kind::Questions {
+                questions,
+                answers,
+                selected,
+            } => {
+                answers.push(answer);
+                selected.clear();
+                if answers.len() < questions.len() {
+                    return None;
+                }
+                let mut summary = String::new();
+                for (q, a) in questions.iter().zip(answers.iter()) {
+                    let entry = format!(
+                        "❓ {}\n→ <b>{}</b>\n\n",
+                        escape_within(&q.question, 300),
+                        escape_within(a, 600)
+                    );
+                    if summary.len() + entry.len() > BODY_HTML_LIMIT {
+                        summary.push('…');
+                        break;
+                    }
+                    summary.push_str(&entry);
+                }
+                let decision = ToolDecision::Allow(answered_input(&self.input, questions, answers));
+                Some((decision, summary.trim_end().to_string()))
+            }
+            Kind::Plan { .. } => {
+                let summary = format!(
+                    "{}\n\n✏️ <b>Revision requested:</b> {}",
+                    self.plan_html(),
+                    escape_within(&answer, 500)
+                );
+                let decision = ToolDecision::Deny(format!(
+                    "The user did not approve the plan and asked for changes: {}",
+                    answer
+                ));
+                Some((decision, summary))
+            }
+        }
+    }
+}
+
+/// Show a question or plan from the running AI (see `StreamMessage::AwaitingUser`)
+/// and keep it until the user answers or the run ends
+pub(crate) async fn present(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+    user_id: u64,
+    tool_name: &str,
+    input: Value,
+    reply: Sender<ToolDecision>,
+) {
+    let kind = match tool_name {
+        "ExitPlanMode" => Kind::Plan {
+            plan: input
+                .get("plan")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string(),
+        },
+        _ => {
+            let questions = parse_questions(&input);
+            if questions.is_empty() {
+                let _ = reply.send(ToolDecision::Deny("No questions were given.".to_string()));
+                return;
+            }
+            Kind::Questions {
+                questions,
+                answers: Vec::new(),
+                selected: Vec::new(),
+            }
+        }
+    };
+    let mut pending = PendingInteraction {
+        message_id: MessageId(0),
+        user_id,
+        input,
+        kind,
+        awaiting_text: false,
+        reply,
+    };
+
+    let (text, keyboard) = pending.render();
+    let text = redact_outbound(state, &text).await;
+    shared_rate_limit_wait(state, chat_id).await;
+    let sent = bot
+        .send_message(chat_id, &text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
+        .await;
+    match sent {
+        Ok(msg) => {
+            pending.message_id = msg.id;
+            let mut data = state.lock().await;
+            data.interactions.insert(chat_id, pending);
+        }
+        Err(e) => {
+            tracing::warn!("send_message failed (user question): {e}");
+            let _ = pending.reply.send(ToolDecision::Deny(
+                "The question could not be shown to the user.".to_string(),
+            ));
+        }
+    }
+}
+
+/// Show the current state of a pending interaction, or its outcome once decided
+async fn update_message(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+    message_id: MessageId,
+    text: &str,
+    keyboard: Option<InlineKeyboardMarkup>,
+) {
+    let text = redact_outbound(state, text).await;
+    shared_rate_limit_wait(state, chat_id).await;
+    let mut request = bot
+        .edit_message_text(chat_id, message_id, &text)
+        .parse_mode(ParseMode::Html);
+    if let Some(keyboard) = keyboard {
+        request = request.reply_markup(keyboard);
+    }
+    if let Err(e) = request.await {
+        tracing::warn!("edit_message failed (user question): {e}");
+    }
+}
+
+/// Apply a step to the pending interaction: send the decision and show the
+/// outcome if it finished, otherwise show its next state
+async fn advance(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+    pending: PendingInteraction,
+    outcome: Option<(ToolDecision, String)>,
+) {
+    let message_id = pending.message_id;
+    match outcome {
+        Some((decision, summary)) => {
+            tracing::info!("User answered the AI's question");
+            let _ = pending.reply.send(decision);
+            update_message(bot, chat_id, state, message_id, &summary, None).await;
+        }
+        None => {
+            let (text, keyboard) = pending.render();
+            state.lock().await.interactions.insert(chat_id, pending);
+            update_message(bot, chat_id, state, message_id, &text, Some(keyboard)).await;
+        }
+    }
+}
+
+/// What a button press does to a pending interaction
+enum Step {
+    /// Finished, with the summary shown in place of the buttons
+    Decided(ToolDecision, String),
+    /// Answers the current question
+    Answer(String),
+    /// Still waiting; show the new state
+    Wait,
+    /// Nothing changes; tell the user why
+    Toast(&'static str),
+}
+
+/// Handle `ask:` callbacks: `o<N>` picks (or, on multi-select questions, ticks)
+/// option N, `done` submits the ticked options, `other` and `revise` wait for a
+/// typed answer, `ok` approves the plan
+pub(crate) async fn handle_ask_callback(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    action: &str,
+    state: &SharedState,
+    user_id: u64,
+    is_owner: bool,
+) -> ResponseResult<Option<String>> {
+    let pending = {
+        let mut data = state.lock().await;
+        match data.interactions.get(&chat_id) {
+            Some(p) if p.message_id != message_id => None,
+            Some(p) if p.user_id != user_id && !is_owner => {
+                return Ok(Some(
+                    "Only the user who sent the prompt can answer.".to_string(),
+                ));
+            }
+            Some(_) => data.interactions.remove(&chat_id),
+            None => None,
+        }
+    };
+    let Some(mut pending) = pending else {
+        return Ok(Some("This question has expired.".to_string()));
+    };
+
+    let step = match (&mut pending.kind, action) {
+        (Kind::Plan { .. }, "ok") => Step::Decided(
+            ToolDecision::Allow(pending.input.clone()),
+            format!("{}\n\n✅ <b>Approved</b>", pending.plan_html()),
+        ),
+        (_, "other" | "revise") => {
+            pending.awaiting_text = true;
+            Step::Wait
+        }
+        (
+            Kind::Questions {
+                questions,
+                answers,
+                selected,
+            },
+            _,
+        ) => {
+            let options = questions
+                .get(answers.len())
+                .map(|q| (q.options.as_slice(), q.multi_select))
+                .unwrap_or_default();
+            let picked = action
+                .strip_prefix('o')
+                .and_then(|i| i.parse::<usize>().ok())
+                .filter(|i| *i < options.0.len());
+            match (picked, options.1) {
+                (Some(i), false) => Step::Answer(options.0[i].0.clone()),
+                (Some(i), true) => {
+                    match selected.iter().position(|s| *s == i) {
+                        Some(pos) => {
+                            selected.remove(pos);
+                        }
+                        None => selected.push(i),
+                    }
+                    Step::Wait
+                }
+                (None, true) if action == "done" && !selected.is_empty() => {
+                    let mut ticked = selected.clone();
+                    ticked.sort_unstable();
+                    let labels: Vec<&str> =
+                        ticked.iter().map(|i| options.0[*i].0.as_str()).collect();
+                    Step::Answer(labels.join(", "))
+                }
+                (None, true) if action == "done" => Step::Toast("Select at least one option."),
+                _ => Step::Wait,
+            }
+        }
+        (Kind::Plan { .. }, _) => Step::Wait,
+    };
+
+    let outcome = match step {
+        Step::Decided(decision, summary) => Some((decision, summary)),
+        Step::Answer(answer) => pending.answer(answer),
+        Step::Wait => None,
+        Step::Toast(toast) => {
+            state.lock().await.interactions.insert(chat_id, pending);
+            return Ok(Some(toast.to_string()));
+        }
+    };
+    advance(bot, chat_id, state, pending, outcome).await;
+    Ok(None)
+}
+
+/// Deliver a text message as the typed answer the pending interaction is
+/// waiting for. Returns false if none is waiting for this user.
+pub(crate) async fn take_text_answer(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: u64,
+    is_owner: bool,
+    text: &str,
+    state: &SharedState,
+) -> bool {
+    let pending = {
+        let mut data = state.lock().await;
+        match data.interactions.get(&chat_id) {
+            Some(p) if p.awaiting_text && (p.user_id == user_id || is_owner) => {
+                data.interactions.remove(&chat_id)
+            }
+            _ => None,
+        }
+    };
+    let Some(mut pending) = pending else {
+        return false;
+    };
+    let outcome = pending.answer(text.to_string());
+    advance(bot, chat_id, state, pending, outcome).await;
+    true
+}
+
+/// The run ended while a question was still open: remove its buttons
+pub(crate) async fn discard(bot: &Bot, chat_id: ChatId, state: &SharedState) {
+    let pending = state.lock().await.interactions.remove(&chat_id);
+    if let Some(pending) = pending {
+        let (text, _) = pending.render();
+        let text = format!("{}\n\n<i>No longer waiting for an answer.</i>", text);
+        update_message(bot, chat_id, state, pending.message_id, &text, None).await;
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answered_input_keys_answers_by_question() {
+        let input = json!({"questions": [
+            {"header": "Auth", "question": "Which method?", "multiSelect": false,
+             "options": [{"label": "OAuth", "description": "Via provider"}, {"label": "JWT", "description": ""}]},
+            {"header": "Extras", "question": "Which extras?", "multiSelect": true,
+             "options": [{"label": "2FA"}, {"label": "Audit log"}]}
+        ]});
+        let questions = parse_questions(&input);
+        assert_eq!(questions.len(), 2);
+        assert_eq!(questions[0].options[0].1, "Via provider");
+        assert!(questions[1].multi_select);
+
+        let answers = vec!["JWT".to_string(), "2FA, Audit log".to_string()];
+        let updated = answered_input(&input, &questions, &answers);
+        assert_eq!(updated["answers"]["Which method?"], "JWT");
+        assert_eq!(updated["answers"]["Which extras?"], "2FA, Audit log");
+        assert_eq!(updated["questions"], input["questions"]);
+    }
+
+    #[test]
+    fn test_plan_feedback_denies_with_the_users_words() {
+        let (reply, _answer) = std::sync::mpsc::channel();
+        let mut pending = PendingInteraction {
+            message_id: MessageId(1),
+            user_id: 1,
+            input: json!({"plan": "1. Do it"}),
+            kind: Kind::Plan {
+                plan: "1. Do it".to_string(),
+            },
+            awaiting_text: true,
+            reply,
+        };
+        let (decision, summary) = pending
+            .answer("skip step 1".to_string())
+            .expect("plan feedback decides");
+        assert_eq!(
+            decision,
+            ToolDecision::Deny(
+                "The user did not approve the plan and asked for changes: skip step 1".to_string()
+            )
+        );
+        assert!(summary.contains("Revision requested"));
+    }
+
+    #[test]
+    fn test_long_plans_and_questions_fit_one_message() {
+        use super::super::bot::TELEGRAM_MSG_LIMIT;
+
+        let (reply, _answer) = std::sync::mpsc::channel();
+        let plan = "- **R&D** <step> `a&b`\n".repeat(400);
+        let mut pending = PendingInteraction {
+            message_id: MessageId(1),
+            user_id: 1,
+            input: json!({"plan": plan}),
+            kind: Kind::Plan { plan },
+            awaiting_text: true,
+            reply,
+        };
+        let (text, _) = pending.render();
+        assert!(text.len() < TELEGRAM_MSG_LIMIT);
+        let (_, summary) = pending.answer("&".repeat(5000)).expect("decides");
+        assert!(summary.len() < TELEGRAM_MSG_LIMIT);
+
+        let (reply, _answer) = std::sync::mpsc::channel();
+        let option = json!({"label": "<&>".repeat(100), "description": "&".repeat(2000)});
+        let input = json!({"questions": [
+            {"header": "Pick", "question": "<?>".repeat(2000), "options": vec![option; 30]}
+        ]});
+        let pending = PendingInteraction {
+            message_id: MessageId(1),
+            user_id: 1,
+            kind: Kind::Questions {
+                questions: parse_questions(&input),
+                answers: Vec::new(),
+                selected: Vec::new(),
+            },
+            input,
+            awaiting_text: true,
+            reply,
+        };
+        let (text, keyboard) = pending.render();
+        assert!(text.len() < TELEGRAM_MSG_LIMIT);
+        assert_eq!(text.matches("<b>").count(), text.matches("</b>").count());
+        assert_eq!(keyboard.inline_keyboard.len(), 31);
+    }
+}
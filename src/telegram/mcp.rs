@@ -0,0 +1,343 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::auth::{is_path_within_sandbox, PermissionLevel};
+use crate::claude::ProjectOptions;
+
+use super::bot::{shared_rate_limit_wait, SharedState};
+use super::storage::{save_bot_settings, BotSettings};
+use super::streaming::html_escape;
+
+/// The project's /mcp and /adddir settings
+pub(crate) fn project_options(settings: &BotSettings, project: &str) -> ProjectOptions {
+    ProjectOptions {
+        mcp_configs: settings
+            .mcp_configs
+            .get(project)
+            .cloned()
+            .unwrap_or_default(),
+        add_dirs: settings.add_dirs.get(project).cloned().unwrap_or_default(),
+    }
+}
+
+/// Project options of a run. Only the owner's runs get the project's MCP servers
+/// and extra directories; other group members' runs would otherwise reach them
+/// through tools outside their allow-list.
+pub(crate) fn run_project_options(
+    settings: &BotSettings,
+    project: &str,
+    level: PermissionLevel,
+) -> ProjectOptions {
+    match level {
+        PermissionLevel::Owner => project_options(settings, project),
+        _ => ProjectOptions::default(),
+    }
+}
+
+/// Names of the servers in an MCP config file (`{"mcpServers": {...}}`)
+pub(crate) fn mcp_server_names(content: &str) -> Result<Vec<String>, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(content).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let servers = json
+        .get("mcpServers")
+        .and_then(|v| v.as_object())
+        .ok_or_else(|| "No \"mcpServers\" object in the file.".to_string())?;
+    if servers.is_empty() {
+        return Err("\"mcpServers\" is empty.".to_string());
+    }
+    if let Some((name, _)) = servers.iter().find(|(_, v)| !v.is_object()) {
+        return Err(format!("Server \"{}\" is not an object.", name));
+    }
+    Ok(servers.keys().cloned().collect())
+}
+
+/// `arg` resolved against the project directory, if it exists inside the sandbox
+fn resolve_path(project: &str, arg: &str) -> Result<PathBuf, String> {
+    let path = match arg.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => Path::new(project).join(arg),
+    };
+    let canonical = path
+        .canonicalize()
+        .map_err(|_| format!("'{}' does not exist.", arg))?;
+    let sandbox_root = dirs::home_dir().unwrap_or_else(|| Path::new("/").to_path_buf());
+    if !is_path_within_sandbox(&canonical, &sandbox_root) {
+        return Err(format!(
+            "Access denied: '{}' is outside the allowed path sandbox.",
+            canonical.display()
+        ));
+    }
+    Ok(canonical)
+}
+
+/// Current session directory, or None after telling the user there is no session
+async fn session_project(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &SharedState,
+) -> ResponseResult<Option<String>> {
+    let current_path = {
+        let data = state.lock().await;
+        data.sessions
+            .get(&chat_id)
+            .and_then(|s| s.current_path.clone())
+    };
+    if current_path.is_none() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, "No active session. Use /start <path> first.")
+            .await?;
+    }
+    Ok(current_path)
+}
+
+/// Remove entry `index` (1-based, as listed) of the project's list in `lists`
+fn remove_entry(
+    lists: &mut std::collections::HashMap<String, Vec<String>>,
+    project: &str,
+    index: &str,
+) -> Option<String> {
+    let list = lists.get_mut(project)?;
+    let n = index.trim().parse::<usize>().ok()?;
+    if n == 0 || n > list.len() {
+        return None;
+    }
+    let removed = list.remove(n - 1);
+    if list.is_empty() {
+        lists.remove(project);
+    }
+    Some(removed)
+}
+
+/// Handle /mcp command - MCP server config files of the current project
+/// Usage: /mcp [list]          (config files, and the servers of the last run)
+///        /mcp add <file>      (add a config file; relative to the project)
+///        /mcp remove <n>      (remove config file n of the list)
+pub(crate) async fn handle_mcp_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let Some(project) = session_project(bot, chat_id, state).await? else {
+        return Ok(());
+    };
+    let arg = text.strip_prefix("/mcp").unwrap_or("").trim();
+    let (action, rest) = arg
+        .split_once(char::is_whitespace)
+        .map(|(a, r)| (a, r.trim()))
+        .unwrap_or((arg, ""));
+    let usage = "<code>/mcp list</code> — Config files and server status\n\
+                 <code>/mcp add &lt;file&gt;</code> — Add an MCP config file (<code>{\"mcpServers\": …}</code>)\n\
+                 <code>/mcp remove &lt;n&gt;</code> — Remove config file n";
+
+    let response_msg = match action {
+        "" | "list" => {
+            let data = state.lock().await;
+            let configs = project_options(&data.settings, &project).mcp_configs;
+            let mut msg = format!(
+                "<b>MCP config files</b> for <code>{}</code>\n",
+                html_escape(&project)
+            );
+            if configs.is_empty() {
+                msg.push_str("(none)\n");
+            }
+            for (i, config) in configs.iter().enumerate() {
+                let servers = fs::read_to_string(config)
+                    .map_err(|e| e.to_string())
+                    .and_then(|c| mcp_server_names(&c));
+                let servers = match servers {
+                    Ok(names) => html_escape(&names.join(", ")),
+                    Err(e) => format!("⚠️ {}", html_escape(&e)),
+                };
+                msg.push_str(&format!(
+                    "{}. <code>{}</code> — {}\n",
+                    i + 1,
+                    html_escape(config),
+                    servers
+                ));
+            }
+            msg.push_str("\n<b>Last run</b>\n");
+            match data.mcp_status.get(&chat_id) {
+                Some(servers) => {
+                    for server in servers {
+                        let icon = match server.status.as_str() {
+                            "connected" => "✅",
+                            "failed" => "❌",
+                            _ => "⏳",
+                        };
+                        msg.push_str(&format!(
+                            "{} {} — {}\n",
+                            icon,
+                            html_escape(&server.name),
+                            html_escape(&server.status)
+                        ));
+                    }
+                }
+                None => msg.push_str("No MCP servers reported yet.\n"),
+            }
+            msg.push('\n');
+            msg.push_str(usage);
+            msg
+        }
+        "add" if !rest.is_empty() => {
+            let checked = resolve_path(&project, rest).and_then(|path| {
+                let content = fs::read_to_string(&path)
+                    .map_err(|e| format!("Cannot read '{}': {}", path.display(), e))?;
+                mcp_server_names(&content).map(|names| (path, names))
+            });
+            match checked {
+                Ok((path, names)) => {
+                    let path = path.display().to_string();
+                    let mut data = state.lock().await;
+                    let list = data.settings.mcp_configs.entry(project).or_default();
+                    if list.contains(&path) {
+                        "Already added.".to_string()
+                    } else {
+                        list.push(path.clone());
+                        save_bot_settings(token, &data.settings);
+                        format!(
+                            "✅ Added <code>{}</code> ({}). Servers start with the next prompt.",
+                            html_escape(&path),
+                            html_escape(&names.join(", "))
+                        )
+                    }
+                }
+                Err(e) => html_escape(&e),
+            }
+        }
+        "remove" => {
+            let mut data = state.lock().await;
+            match remove_entry(&mut data.settings.mcp_configs, &project, rest) {
+                Some(removed) => {
+                    save_bot_settings(token, &data.settings);
+                    format!("❌ Removed <code>{}</code>", html_escape(&removed))
+                }
+                None => "Use /mcp remove N with a number from /mcp list.".to_string(),
+            }
+        }
+        _ => format!("Usage:\n{}", usage),
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle /adddir command - directories the AI may use besides the current project
+/// Usage: /adddir               (list them)
+///        /adddir <path>        (add a directory; relative to the project)
+///        /adddir remove <n>    (remove directory n of the list)
+pub(crate) async fn handle_adddir_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let Some(project) = session_project(bot, chat_id, state).await? else {
+        return Ok(());
+    };
+    let arg = text.strip_prefix("/adddir").unwrap_or("").trim();
+
+    let response_msg = if arg.is_empty() {
+        let data = state.lock().await;
+        let dirs = project_options(&data.settings, &project).add_dirs;
+        let mut msg = format!(
+            "<b>Extra directories</b> for <code>{}</code>\n",
+            html_escape(&project)
+        );
+        if dirs.is_empty() {
+            msg.push_str("(none)\n");
+        }
+        for (i, dir) in dirs.iter().enumerate() {
+            msg.push_str(&format!("{}. <code>{}</code>\n", i + 1, html_escape(dir)));
+        }
+        msg.push_str(
+            "\n<code>/adddir &lt;path&gt;</code> — Add a directory\n\
+             <code>/adddir remove &lt;n&gt;</code> — Remove directory n",
+        );
+        msg
+    } else if let Some(index) = arg.strip_prefix("remove ") {
+        let mut data = state.lock().await;
+        match remove_entry(&mut data.settings.add_dirs, &project, index) {
+            Some(removed) => {
+                save_bot_settings(token, &data.settings);
+                format!("❌ Removed <code>{}</code>", html_escape(&removed))
+            }
+            None => "Use /adddir remove N with a number from /adddir.".to_string(),
+        }
+    } else {
+        match resolve_path(&project, arg) {
+            Ok(path) if !path.is_dir() => format!(
+                "'{}' is not a directory.",
+                html_escape(&path.display().to_string())
+            ),
+            Ok(path) => {
+                let path = path.display().to_string();
+                let mut data = state.lock().await;
+                let list = data.settings.add_dirs.entry(project).or_default();
+                if list.contains(&path) {
+                    "Already added.".to_string()
+                } else {
+                    list.push(path.clone());
+                    save_bot_settings(token, &data.settings);
+                    format!(
+                        "✅ The AI may now also use <code>{}</code>.",
+                        html_escape(&path)
+                    )
+                }
+            }
+            Err(e) => html_escape(&e),
+        }
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_project_options_owner_only() {
+        let mut settings = BotSettings::default();
+        settings
+            .mcp_configs
+            .insert("/p".to_string(), vec!["/p/mcp.json".to_string()]);
+        settings
+            .add_dirs
+            .insert("/p".to_string(), vec!["/lib".to_string()]);
+
+        let owner = run_project_options(&settings, "/p", PermissionLevel::Owner);
+        assert_eq!(owner.mcp_configs, vec!["/p/mcp.json".to_string()]);
+        assert_eq!(owner.add_dirs, vec!["/lib".to_string()]);
+
+        let public = run_project_options(&settings, "/p", PermissionLevel::Public);
+        assert_eq!(public, ProjectOptions::default());
+    }
+
+    #[test]
+    fn test_mcp_server_names() {
+        let names = mcp_server_names(
+            r#"{"mcpServers": {"github": {"command": "gh-mcp"}, "db": {"type": "http", "url": "http://localhost"}}}"#,
+        );
+        assert_eq!(names, Ok(vec!["db".to_string(), "github".to_string()]));
+
+        assert!(mcp_server_names("{}").is_err());
+        assert!(mcp_server_names(r#"{"mcpServers": {}}"#).is_err());
+        assert!(mcp_server_names(r#"{"mcpServers": {"x": "npx"}}"#).is_err());
+        assert!(mcp_server_names("not json").is_err());
+    }
+}
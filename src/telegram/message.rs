@@ -1,28 +1,53 @@
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::sync::Arc;
 
 use teloxide::prelude::*;
 use teloxide::types::ParseMode;
+use tracing::Instrument;
 
-use crate::auth::{can_execute, classify_command, CommandRisk};
-use crate::claude::{self, CancelToken, StreamMessage, DEFAULT_ALLOWED_TOOLS};
+use crate::audit::{self, AuditEntry, AuditEvent};
+use crate::auth::{can_execute, classify_command, permission_level, CommandRisk, PermissionLevel};
+use crate::checkpoint;
+use crate::claude::{
+    self, effort_budget, CancelToken, RunOptions, StreamMessage, ToolPolicy, DEFAULT_ALLOWED_TOOLS,
+};
+use crate::confine;
+use crate::live;
+use crate::logging;
 use crate::session::{sanitize_user_input, HistoryItem, HistoryType};
+use crate::snapshot;
+use crate::trace::RunTrace;
 
+use super::audit_log::{handle_audit_command, record_decision};
 use super::bot::{shared_rate_limit_wait, SharedState, TELEGRAM_MSG_LIMIT};
+use super::browser::handle_ls_command;
+use super::changes::send_change_summary;
+use super::checklist::PinnedChecklist;
+use super::checkpoints::{handle_checkpoints_command, handle_undo_command};
 use super::commands::{
-    auto_restore_session, handle_cd_command, handle_clear_command, handle_help_command,
-    handle_public_command, handle_pwd_command, handle_start_command, handle_stop_command,
+    auto_restore_session, chat_model_options, handle_cd_command, handle_clear_command,
+    handle_effort_command, handle_help_command, handle_maxturns_command, handle_model_command,
+    handle_public_command, handle_pwd_command, handle_redact_command, handle_start_command,
+    handle_stop_command, handle_thinking_command,
+};
+use super::file_ops::{
+    handle_down_command, handle_file_upload, handle_shell_command, handle_trace_command,
 };
-use super::file_ops::{handle_down_command, handle_file_upload, handle_shell_command};
+use super::git_ops::{handle_commit_command, handle_diff_command, handle_log_command};
+use super::instructions::{self, handle_systemprompt_command};
+use super::interaction;
+use super::mcp::{self, handle_adddir_command, handle_mcp_command};
+use super::preview::handle_cat_command;
+use super::render::{ResponseBuilder, ThinkingDisplay};
 use super::storage::{delete_session_file, save_bot_settings, save_session_to_file, token_hash};
 use super::streaming::{
-    format_tool_input, markdown_to_telegram_html, normalize_empty_lines, send_long_message,
-    truncate_str,
+    markdown_to_telegram_html, redact_outbound, send_long_message, truncate_str,
 };
 use super::tools::{
-    get_allowed_tools, handle_allowed_command, handle_allowedtools_command,
-    handle_availabletools_command,
+    get_allowed_tools, get_public_tools, handle_allowed_command, handle_allowedtools_command,
+    handle_availabletools_command, handle_publictools_command,
 };
 
 /// Route incoming messages to appropriate handlers
@@ -34,12 +59,6 @@ pub(crate) async fn handle_message(
     default_project_dir: &str,
 ) -> ResponseResult<()> {
     let chat_id = msg.chat.id;
-    let raw_user_name = msg
-        .from
-        .as_ref()
-        .map(|u| u.first_name.as_str())
-        .unwrap_or("unknown");
-    let timestamp = chrono::Local::now().format("%H:%M:%S");
     let user_id = msg.from.as_ref().map(|u| u.id.0);
 
     // Auth check (imprinting)
@@ -55,7 +74,7 @@ pub(crate) async fn handle_message(
                 // Imprint: register first user as owner
                 data.settings.owner_user_id = Some(uid);
                 save_bot_settings(token, &data.settings);
-                println!("  [{timestamp}] ★ Owner registered: {raw_user_name} (id:{uid})");
+                tracing::info!("Owner registered");
                 true
             }
             Some(owner_id) => {
@@ -71,11 +90,11 @@ pub(crate) async fn handle_message(
                             .unwrap_or(false);
                     if !is_public {
                         // Unregistered user -> reject silently (log only)
-                        println!("  [{timestamp}] ✗ Rejected: {raw_user_name} (id:{uid})");
+                        tracing::warn!("Rejected: not the owner");
                         return Ok(());
                     }
                     // Public group chat: allow non-owner user
-                    println!("  [{timestamp}] ○ [{raw_user_name}(id:{uid})] Public group access");
+                    tracing::info!("Public group access");
                 }
                 false
             }
@@ -91,8 +110,6 @@ pub(crate) async fn handle_message(
         data.settings.owner_user_id == Some(uid)
     };
 
-    let user_name = format!("{}({uid})", raw_user_name);
-
     // Permission check for file/photo uploads and text commands
     // is_public: whether this chat has public access enabled
     let is_public = {
@@ -110,7 +127,19 @@ pub(crate) async fn handle_message(
     // Handle file/photo uploads
     if msg.document().is_some() || msg.photo().is_some() {
         // Permission check: file uploads write to disk (Elevated risk)
-        if !can_execute(is_owner, is_public, CommandRisk::Elevated) {
+        let allowed = can_execute(is_owner, is_public, CommandRisk::Elevated);
+        let upload_label = format!("[upload] {}", msg.caption().unwrap_or(""));
+        record_decision(
+            &state,
+            AuditEvent::Command,
+            uid,
+            chat_id,
+            upload_label.trim_end(),
+            CommandRisk::Elevated,
+            allowed,
+        )
+        .await;
+        if !allowed {
             shared_rate_limit_wait(&state, chat_id).await;
             bot.send_message(
                 chat_id,
@@ -131,9 +160,9 @@ pub(crate) async fn handle_message(
         } else {
             "photo"
         };
-        println!("  [{timestamp}] ◀ [{user_name}] Upload: {file_hint}");
+        tracing::info!("Upload: {file_hint}");
         handle_file_upload(&bot, chat_id, &msg, &state).await?;
-        println!("  [{timestamp}] ▶ [{user_name}] Upload complete");
+        tracing::info!("Upload complete");
         // If caption contains text after ';', send it to AI as a follow-up message
         if let Some(caption) = msg.caption() {
             let text_part = if is_group_chat {
@@ -160,7 +189,8 @@ pub(crate) async fn handle_message(
                         bot.send_message(chat_id, "AI request in progress. Use /stop to cancel.")
                             .await?;
                     } else {
-                        handle_text_message(&bot, chat_id, text, &state).await?;
+                        let level = permission_level(is_owner, is_public);
+                        handle_text_message(&bot, chat_id, text, &state, level, uid, None).await?;
                     }
                 }
             }
@@ -194,12 +224,12 @@ pub(crate) async fn handle_message(
     } else {
         raw_text.to_string()
     };
-    let preview = truncate_str(&text, 60);
+    tracing::info!(text = %truncate_str(&text, 60), "Message");
 
     // Auto-restore session from bot_settings.json if not in memory.
     if !text.starts_with("/start") {
         let mut data = state.lock().await;
-        auto_restore_session(&mut data, chat_id, default_project_dir, &user_name);
+        auto_restore_session(&mut data, chat_id, default_project_dir);
     }
 
     // In group chats, ignore plain text (only /, !, ; prefixed messages are processed)
@@ -207,6 +237,43 @@ pub(crate) async fn handle_message(
         return Ok(());
     }
 
+    // A question or plan from the running AI may be waiting for a typed answer
+    if !text.starts_with('/') && !text.starts_with('!') {
+        let answer = text.strip_prefix(';').unwrap_or(&text).trim();
+        if !answer.is_empty()
+            && interaction::take_text_answer(&bot, chat_id, uid, is_owner, answer, &state).await
+        {
+            return Ok(());
+        }
+    }
+
+    // With persistent processes, a prompt sent during a run is passed to it
+    // to steer the work in progress, if it comes from whoever started the run
+    if live::enabled() && !text.starts_with('/') && !text.starts_with('!') {
+        let run_user = state.lock().await.run_users.get(&chat_id).copied();
+        let prompt = text.strip_prefix(';').unwrap_or(&text).trim();
+        if run_user.is_some_and(|u| u == uid || is_owner)
+            && !prompt.is_empty()
+            && live::steer(chat_id.0, &sanitize_user_input(prompt))
+        {
+            record_decision(
+                &state,
+                AuditEvent::Command,
+                uid,
+                chat_id,
+                &text,
+                CommandRisk::Safe,
+                true,
+            )
+            .await;
+            tracing::info!("Steering message sent to the running AI");
+            shared_rate_limit_wait(&state, chat_id).await;
+            bot.send_message(chat_id, "↪️ Sent to the running AI.")
+                .await?;
+            return Ok(());
+        }
+    }
+
     // Block all messages except /stop while an AI request is in progress
     if !text.starts_with("/stop") {
         let data = state.lock().await;
@@ -221,94 +288,136 @@ pub(crate) async fn handle_message(
 
     // Permission check: classify command risk and verify the user can execute it.
     // /stop and /help are always allowed (Safe). Other commands are checked here.
-    if !text.starts_with("/stop") && !text.starts_with("/help") {
-        let risk = classify_command(&text);
-        if !can_execute(is_owner, is_public, risk) {
-            shared_rate_limit_wait(&state, chat_id).await;
-            bot.send_message(
-                chat_id,
-                "Permission denied: this command requires owner access.",
-            )
-            .await?;
-            return Ok(());
-        }
+    let risk = classify_command(&text);
+    let allowed = text.starts_with("/stop")
+        || text.starts_with("/help")
+        || can_execute(is_owner, is_public, risk);
+    record_decision(
+        &state,
+        AuditEvent::Command,
+        uid,
+        chat_id,
+        &text,
+        risk,
+        allowed,
+    )
+    .await;
+    if !allowed {
+        shared_rate_limit_wait(&state, chat_id).await;
+        bot.send_message(
+            chat_id,
+            "Permission denied: this command requires owner access.",
+        )
+        .await?;
+        return Ok(());
     }
 
     if text.starts_with("/stop") {
-        println!("  [{timestamp}] ◀ [{user_name}] /stop");
         handle_stop_command(&bot, chat_id, &state).await?;
     } else if text.starts_with("/help") {
-        println!("  [{timestamp}] ◀ [{user_name}] /help");
         handle_help_command(&bot, chat_id, &state).await?;
     } else if text.starts_with("/start") {
-        println!("  [{timestamp}] ◀ [{user_name}] /start");
         handle_start_command(&bot, chat_id, &text, &state, token, default_project_dir).await?;
     } else if text.starts_with("/clear") {
-        println!("  [{timestamp}] ◀ [{user_name}] /clear");
         handle_clear_command(&bot, chat_id, &state).await?;
-        println!("  [{timestamp}] ▶ [{user_name}] Session cleared");
+        tracing::info!("Session cleared");
     } else if text.starts_with("/pwd") {
-        println!("  [{timestamp}] ◀ [{user_name}] /pwd");
         handle_pwd_command(&bot, chat_id, &state).await?;
     } else if text.starts_with("/cd") {
-        println!(
-            "  [{timestamp}] ◀ [{user_name}] /cd {}",
-            text.strip_prefix("/cd").unwrap_or("").trim()
-        );
         handle_cd_command(&bot, chat_id, &text, &state, token).await?;
+    } else if text.starts_with("/ls") || text.starts_with("/browse") {
+        handle_ls_command(&bot, chat_id, &state).await?;
+    } else if text.starts_with("/cat") {
+        handle_cat_command(&bot, chat_id, &text, &state).await?;
     } else if text.starts_with("/down") {
-        println!(
-            "  [{timestamp}] ◀ [{user_name}] /down {}",
-            text.strip_prefix("/down").unwrap_or("").trim()
-        );
         handle_down_command(&bot, chat_id, &text, &state).await?;
+    } else if text.starts_with("/diff") {
+        handle_diff_command(&bot, chat_id, &state).await?;
+    } else if text.starts_with("/log") {
+        handle_log_command(&bot, chat_id, &text, &state).await?;
+    } else if text.starts_with("/commit") {
+        handle_commit_command(&bot, chat_id, &text, &state).await?;
+    } else if text.starts_with("/undo") {
+        handle_undo_command(&bot, chat_id, &state).await?;
+    } else if text.starts_with("/checkpoints") {
+        handle_checkpoints_command(&bot, chat_id, &state).await?;
+    } else if text.starts_with("/trace") {
+        handle_trace_command(&bot, chat_id, &state).await?;
+    } else if text.starts_with("/audit") {
+        handle_audit_command(&bot, chat_id, &text, &state).await?;
+    } else if text.starts_with("/redact") {
+        handle_redact_command(&bot, chat_id, &text, &state, token).await?;
+    } else if text.starts_with("/publictools") {
+        handle_publictools_command(&bot, chat_id, &text, &state, token).await?;
     } else if text.starts_with("/public") {
-        println!(
-            "  [{timestamp}] ◀ [{user_name}] /public {}",
-            text.strip_prefix("/public").unwrap_or("").trim()
-        );
         handle_public_command(&bot, chat_id, &text, &state, token, is_group_chat, is_owner).await?;
+    } else if text.starts_with("/thinking") {
+        handle_thinking_command(&bot, chat_id, &text, &state, token).await?;
+    } else if text.starts_with("/systemprompt") {
+        handle_systemprompt_command(&bot, chat_id, &text, &state, token).await?;
+    } else if text.starts_with("/mcp") {
+        handle_mcp_command(&bot, chat_id, &text, &state, token).await?;
+    } else if text.starts_with("/adddir") {
+        handle_adddir_command(&bot, chat_id, &text, &state, token).await?;
+    } else if text.starts_with("/model") {
+        handle_model_command(&bot, chat_id, &text, &state, token).await?;
+    } else if text.starts_with("/maxturns") {
+        handle_maxturns_command(&bot, chat_id, &text, &state, token).await?;
+    } else if text.starts_with("/effort") {
+        handle_effort_command(&bot, chat_id, &text, &state, token).await?;
     } else if text.starts_with("/availabletools") {
-        println!("  [{timestamp}] ◀ [{user_name}] /availabletools");
         handle_availabletools_command(&bot, chat_id, &state).await?;
     } else if text.starts_with("/allowedtools") {
-        println!("  [{timestamp}] ◀ [{user_name}] /allowedtools");
         handle_allowedtools_command(&bot, chat_id, &state).await?;
     } else if text.starts_with("/allowed") {
-        println!(
-            "  [{timestamp}] ◀ [{user_name}] /allowed {}",
-            text.strip_prefix("/allowed").unwrap_or("").trim()
-        );
         handle_allowed_command(&bot, chat_id, &text, &state, token).await?;
     } else if text.starts_with('!') {
-        println!("  [{timestamp}] ◀ [{user_name}] Shell: {preview}");
-        handle_shell_command(&bot, chat_id, &text, &state).await?;
-        println!("  [{timestamp}] ▶ [{user_name}] Shell done");
+        handle_shell_command(&bot, chat_id, &text, &state, uid).await?;
+        tracing::info!("Shell done");
     } else if text.starts_with(';') {
         let stripped = text.strip_prefix(';').unwrap_or(&text).trim().to_string();
         if stripped.is_empty() {
             return Ok(());
         }
-        let preview = truncate_str(&stripped, 60);
-        println!("  [{timestamp}] ◀ [{user_name}] {preview}");
-        handle_text_message(&bot, chat_id, &stripped, &state).await?;
+        let level = permission_level(is_owner, is_public);
+        // `;opus: prompt` runs one prompt on another model (owner only)
+        match claude::split_model_override(&stripped).filter(|_| is_owner) {
+            Some((model, prompt)) => {
+                handle_text_message(&bot, chat_id, prompt, &state, level, uid, Some(model)).await?
+            }
+            None => handle_text_message(&bot, chat_id, &stripped, &state, level, uid, None).await?,
+        }
     } else {
-        println!("  [{timestamp}] ◀ [{user_name}] {preview}");
-        handle_text_message(&bot, chat_id, &text, &state).await?;
+        let level = permission_level(is_owner, is_public);
+        handle_text_message(&bot, chat_id, &text, &state, level, uid, None).await?;
     }
 
     Ok(())
 }
 
-/// Handle regular text messages - send to Claude Code AI
+/// Handle regular text messages - send to Claude Code AI.
+/// `level` is the sender's role: non-owners run with the chat's public tool profile.
+/// `model_override` replaces the chat's /model for this prompt only.
 async fn handle_text_message(
     bot: &Bot,
     chat_id: ChatId,
     user_text: &str,
     state: &SharedState,
+    level: PermissionLevel,
+    user_id: u64,
+    model_override: Option<&str>,
 ) -> ResponseResult<()> {
     // Get session info, allowed tools, and pending uploads (drop lock before any await)
-    let (session_info, allowed_tools, pending_uploads) = {
+    let (
+        session_info,
+        allowed_tools,
+        pending_uploads,
+        attachments,
+        thinking_budget,
+        thinking_display,
+        model_options,
+        project_options,
+    ) = {
         let mut data = state.lock().await;
         let info = data.sessions.get(&chat_id).and_then(|session| {
             session.current_path.as_ref().map(|_| {
@@ -318,17 +427,50 @@ async fn handle_text_message(
                 )
             })
         });
-        let tools = get_allowed_tools(&data.settings, chat_id);
-        // Drain pending uploads so they are sent to Claude exactly once
-        let uploads = data
+        let tools = match level {
+            PermissionLevel::Owner => get_allowed_tools(&data.settings, chat_id),
+            _ => get_public_tools(&data.settings, chat_id),
+        };
+        // Drain pending uploads so they are sent to Claude exactly once,
+        // and the /thinking budget so it applies to this prompt only
+        let (uploads, attachments, budget) = data
             .sessions
             .get_mut(&chat_id)
             .map(|s| {
                 s.cleared = false; // Reset cleared flag on new message
-                std::mem::take(&mut s.pending_uploads)
+                (
+                    std::mem::take(&mut s.pending_uploads),
+                    std::mem::take(&mut s.pending_attachments),
+                    s.thinking_budget.take(),
+                )
             })
             .unwrap_or_default();
-        (info, tools, uploads)
+        // Otherwise the chat's /effort level sets the budget
+        let budget = budget.or_else(|| {
+            data.settings
+                .efforts
+                .get(&chat_id.0.to_string())
+                .and_then(|level| effort_budget(level))
+        });
+        let display = ThinkingDisplay::for_chat(&data.settings, chat_id);
+        let mut model = chat_model_options(&data.settings, chat_id);
+        if let Some(name) = model_override {
+            model.model = Some(name.to_string());
+        }
+        let project = info
+            .as_ref()
+            .map(|(_, path)| mcp::run_project_options(&data.settings, path, level))
+            .unwrap_or_default();
+        (
+            info,
+            tools,
+            uploads,
+            attachments,
+            budget,
+            display,
+            model,
+            project,
+        )
     };
 
     let (session_id, current_path) = match session_info {
@@ -370,7 +512,20 @@ async fn handle_text_message(
         .iter()
         .filter(|t| !allowed_set.contains(**t))
         .collect();
-    let disabled_notice = if disabled.is_empty() {
+    let disabled_notice = if level != PermissionLevel::Owner {
+        let names = if allowed_tools.is_empty() {
+            "none".to_string()
+        } else {
+            allowed_tools.join(", ")
+        };
+        format!(
+            "\n\nRESTRICTED USER: This message comes from a non-owner member of a public group chat. \
+             Only these tools are available: {}. \
+             If the request needs anything else (editing files, running commands, etc.), do NOT proceed. \
+             Explain that only the bot owner can do that.",
+            names
+        )
+    } else if disabled.is_empty() {
         String::new()
     } else {
         let names: Vec<&str> = disabled.iter().map(|t| **t).collect();
@@ -384,53 +539,157 @@ async fn handle_text_message(
         )
     };
 
+    // Confined runs cannot read the bot settings that --sendfile needs
+    let sendfile_notice = if confine::is_enabled() {
+        format!(
+            "Files cannot be sent from here. To share a file, tell the user its path \
+             inside {} and suggest /down <filepath>.",
+            current_path
+        )
+    } else {
+        format!(
+            "When your work produces a file the user would want (generated code, reports, images, archives, etc.),\n\
+             send it by running this bash command:\n\n\
+             {} --sendfile <filepath> --chat {} --key {}\n\n\
+             This delivers the file directly to the user's Telegram chat.\n\
+             Do NOT tell the user to use /down — use the command above instead.",
+            env!("CARGO_BIN_NAME"),
+            chat_id.0,
+            token_hash(bot.token())
+        )
+    };
+
     // Build system prompt with sendfile instructions
-    let system_prompt_owned = format!(
+    let telegram_prompt = format!(
         "You are chatting with a user through Telegram.\n\
          Current working directory: {}\n\n\
-         When your work produces a file the user would want (generated code, reports, images, archives, etc.),\n\
-         send it by running this bash command:\n\n\
-         {} --sendfile <filepath> --chat {} --key {}\n\n\
-         This delivers the file directly to the user's Telegram chat.\n\
-         Do NOT tell the user to use /down — use the command above instead.\n\n\
+         {}\n\n\
          Always keep the user informed about what you are doing. \
          Briefly explain each step as you work (e.g. \"Reading the file...\", \"Creating the script...\", \"Running tests...\"). \
          The user cannot see your tool calls, so narrate your progress so they know what is happening.\n\n\
-         IMPORTANT: The user is on Telegram and CANNOT interact with any interactive prompts, dialogs, or confirmation requests. \
-         All tools that require user interaction (such as AskUserQuestion, EnterPlanMode, ExitPlanMode) will NOT work. \
-         Never use tools that expect user interaction. If you need clarification, just ask in plain text.{}",
+         The user is on Telegram. AskUserQuestion shows your questions as buttons and waits for the answer, \
+         and ExitPlanMode shows your plan with Approve/Revise buttons; use them when you need a decision before continuing. \
+         Other interactive prompts, dialogs, or confirmation requests will NOT work.{}",
         current_path,
-        env!("CARGO_BIN_NAME"),
-        chat_id.0,
-        token_hash(bot.token()),
+        sendfile_notice,
         disabled_notice
     );
+    let chat_prompt = state
+        .lock()
+        .await
+        .settings
+        .system_prompts
+        .get(&chat_id.0.to_string())
+        .cloned();
+    let system_prompt_owned = instructions::compose(
+        instructions::load_project_instructions(&current_path).as_deref(),
+        chat_prompt.as_deref(),
+        &telegram_prompt,
+    );
+
+    // Non-owner prompts are limited by the CLI itself, not just by the prompt
+    let tool_policy = ToolPolicy {
+        enforced: (level != PermissionLevel::Owner).then(|| allowed_tools.clone()),
+        allowed: Some(allowed_tools.clone()),
+    };
+
+    // Snapshot the working tree so the files changed by this turn can be summarized,
+    // and save a checkpoint so /undo can restore it. Runs that cannot write skip both.
+    let can_write = tool_policy.can_write();
+    let scan_root = current_path.clone();
+    let checkpoint_label = format!(
+        "{}{}",
+        checkpoint::TURN_PREFIX,
+        truncate_str(&user_text.replace('\n', " "), 60)
+    );
+    let before_snapshot = if can_write {
+        tokio::task::spawn_blocking(move || {
+            let snapshot = snapshot::scan(Path::new(&scan_root));
+            if snapshot.is_truncated() || snapshot.total_size() > checkpoint::AUTO_MAX_BYTES {
+                tracing::warn!("Directory too large, checkpoint skipped");
+            } else if let Err(e) = checkpoint::create(&scan_root, &checkpoint_label) {
+                tracing::warn!("Checkpoint failed: {e}");
+            }
+            snapshot
+        })
+        .await
+        .ok()
+    } else {
+        None
+    };
 
     // Create cancel token for this request
     let cancel_token = Arc::new(CancelToken::new());
     {
         let mut data = state.lock().await;
         data.cancel_tokens.insert(chat_id, cancel_token.clone());
+        data.run_users.insert(chat_id, user_id);
     }
 
     // Create channel for streaming
     let (tx, rx) = mpsc::channel();
 
+    let run_options = RunOptions {
+        tools: tool_policy,
+        model: model_options,
+        project: project_options,
+        thinking_budget,
+        attachments,
+    };
+
+    // Shown at the top of non-owner replies so the group can see the restriction
+    let role_header = (level != PermissionLevel::Owner).then(|| {
+        format!(
+            "👥 Public user · tools: {}",
+            if allowed_tools.is_empty() {
+                "none".to_string()
+            } else {
+                allowed_tools.join(", ")
+            }
+        )
+    });
+
     let session_id_clone = session_id.clone();
     let current_path_clone = current_path.clone();
     let cancel_token_clone = cancel_token.clone();
 
+    // One id correlates this run across log lines, the audit entry and any error reply
+    let run_id = logging::new_run_id();
+    let run_span = tracing::info_span!("ai_run", run_id = %run_id);
+    let blocking_span = run_span.clone();
+    let trace_run_id = run_id.clone();
+
     // Run Claude Code in a blocking thread
     tokio::task::spawn_blocking(move || {
-        let result = claude::execute_command_streaming(
-            &context_prompt,
-            session_id_clone.as_deref(),
-            &current_path_clone,
-            tx.clone(),
-            Some(&system_prompt_owned),
-            Some(&allowed_tools),
-            Some(cancel_token_clone),
-        );
+        let _enter = blocking_span.enter();
+        let mut trace = RunTrace::start(chat_id.0, &trace_run_id);
+        if let Some(t) = &trace {
+            tracing::debug!("Capturing run trace to {}", t.path().display());
+        }
+        let result = if live::enabled() {
+            live::execute(
+                chat_id.0,
+                &context_prompt,
+                session_id_clone.as_deref(),
+                &current_path_clone,
+                tx.clone(),
+                Some(&system_prompt_owned),
+                &run_options,
+                Some(cancel_token_clone),
+                trace.as_mut(),
+            )
+        } else {
+            claude::execute_command_streaming(
+                &context_prompt,
+                session_id_clone.as_deref(),
+                &current_path_clone,
+                tx.clone(),
+                Some(&system_prompt_owned),
+                &run_options,
+                Some(cancel_token_clone),
+                trace.as_mut(),
+            )
+        };
 
         if let Err(e) = result {
             let _ = tx.send(StreamMessage::Error { message: e });
@@ -442,224 +701,318 @@ async fn handle_text_message(
     let bot_owned = bot.clone();
     let state_owned = state.clone();
     let user_text_owned = user_text.to_string();
-    tokio::spawn(async move {
-        const SPINNER: &[&str] = &[
-            "🕐 P",
-            "🕑 Pr",
-            "🕒 Pro",
-            "🕓 Proc",
-            "🕔 Proce",
-            "🕕 Proces",
-            "🕖 Process",
-            "🕗 Processi",
-            "🕘 Processin",
-            "🕙 Processing",
-            "🕚 Processing.",
-            "🕛 Processing..",
-        ];
-        let mut full_response = String::new();
-        let mut last_edit_text = String::new();
-        let mut done = false;
-        let mut cancelled = false;
-        let mut new_session_id: Option<String> = None;
-        let mut spin_idx: usize = 0;
-
-        while !done {
-            // Check cancel token
-            if cancel_token.cancelled.load(Ordering::Relaxed) {
-                cancelled = true;
-                break;
-            }
+    let mut run_entry = AuditEntry::new(
+        AuditEvent::AiRun,
+        user_id,
+        chat_id.0,
+        &redact_outbound(state, user_text).await,
+        true,
+    );
+    run_entry.working_dir = Some(current_path.clone());
+    run_entry.run_id = Some(run_id.clone());
+    let started = std::time::Instant::now();
+    tokio::spawn(
+        async move {
+            const SPINNER: &[&str] = &[
+                "🕐 P",
+                "🕑 Pr",
+                "🕒 Pro",
+                "🕓 Proc",
+                "🕔 Proce",
+                "🕕 Proces",
+                "🕖 Process",
+                "🕗 Processi",
+                "🕘 Processin",
+                "🕙 Processing",
+                "🕚 Processing.",
+                "🕛 Processing..",
+            ];
+            let mut response = ResponseBuilder::new(&current_path, Some(run_id.clone()))
+                .with_thinking(thinking_display);
+            let mut checklist = PinnedChecklist::default();
+            let mut last_edit_text = String::new();
+            let mut cancelled = false;
+            let mut spin_idx: usize = 0;
+
+            while !response.done {
+                // Check cancel token
+                if cancel_token.cancelled.load(Ordering::Relaxed) {
+                    cancelled = true;
+                    break;
+                }
 
-            // Sleep 3s as polling interval (without reserving a rate limit slot)
-            tokio::time::sleep(tokio::time::Duration::from_millis(3000)).await;
+                // Sleep 3s as polling interval (without reserving a rate limit slot)
+                tokio::time::sleep(tokio::time::Duration::from_millis(3000)).await;
 
-            // Check cancel token again after sleep
-            if cancel_token.cancelled.load(Ordering::Relaxed) {
-                cancelled = true;
-                break;
-            }
+                // Check cancel token again after sleep
+                if cancel_token.cancelled.load(Ordering::Relaxed) {
+                    cancelled = true;
+                    break;
+                }
 
-            // Drain all available messages
-            loop {
-                match rx.try_recv() {
-                    Ok(msg) => match msg {
-                        StreamMessage::Init { session_id: sid } => {
-                            new_session_id = Some(sid);
+                // Drain all available messages
+                loop {
+                    match rx.try_recv() {
+                        Ok(StreamMessage::AwaitingUser {
+                            tool_name,
+                            input,
+                            reply,
+                        }) => {
+                            interaction::present(
+                                &bot_owned,
+                                chat_id,
+                                &state_owned,
+                                user_id,
+                                &tool_name,
+                                input,
+                                reply,
+                            )
+                            .await;
                         }
-                        StreamMessage::Text { content } => {
-                            full_response.push_str(&content);
+                        Ok(msg) => response.push(msg),
+                        Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                        Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                            response.done = true;
+                            break;
                         }
-                        StreamMessage::ToolUse { name, input } => {
-                            let summary = format_tool_input(&name, &input);
-                            let ts = chrono::Local::now().format("%H:%M:%S");
-                            println!("  [{ts}]   ⚙ {name}: {}", truncate_str(&summary, 80));
-                            full_response.push_str(&format!("\n\n⚙️ {}\n", summary));
+                    }
+                }
+
+                if let Some(html) = response.take_checklist_update() {
+                    checklist
+                        .update(&bot_owned, chat_id, &state_owned, &html)
+                        .await;
+                }
+
+                // Build display text with spinning clock+text indicator appended
+                let indicator = SPINNER[spin_idx % SPINNER.len()];
+                spin_idx += 1;
+                let display_text = response.progress_text(indicator, role_header.as_deref());
+
+                if display_text != last_edit_text && !response.done {
+                    // Rate limit: reserve slot right before the actual API call
+                    shared_rate_limit_wait(&state_owned, chat_id).await;
+                    let redacted = redact_outbound(&state_owned, &display_text).await;
+                    let html_text = markdown_to_telegram_html(&redacted);
+                    if let Err(e) = bot_owned
+                        .edit_message_text(chat_id, placeholder_msg_id, &html_text)
+                        .parse_mode(ParseMode::Html)
+                        .await
+                    {
+                        tracing::warn!("edit_message failed (streaming): {e}");
+                    }
+                    last_edit_text = display_text;
+                } else if !response.done {
+                    // No new content to display, send typing indicator
+                    shared_rate_limit_wait(&state_owned, chat_id).await;
+                    let _ = bot_owned
+                        .send_chat_action(chat_id, teloxide::types::ChatAction::Typing)
+                        .await;
+                }
+            }
+
+            interaction::discard(&bot_owned, chat_id, &state_owned).await;
+
+            // Remove cancel token and take stop message ID (processing is done)
+            let stop_msg_id = {
+                let mut data = state_owned.lock().await;
+                data.cancel_tokens.remove(&chat_id);
+                data.run_users.remove(&chat_id);
+                data.stop_message_ids.remove(&chat_id)
+            };
+
+            if cancelled {
+                // Ensure child process is killed.
+                if let Ok(guard) = cancel_token.child_pid.lock() {
+                    if let Some(pid) = *guard {
+                        #[cfg(unix)]
+                        // SAFETY: pid was obtained from child.id() and the child process is still
+                        // tracked. SIGTERM is a safe signal that asks the process to terminate.
+                        #[allow(unsafe_code)]
+                        unsafe {
+                            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+                        }
+                    }
+                }
+
+                // Build stopped response: show partial content + [Stopped] indicator
+                let stopped_response = response.stopped_text(role_header.as_deref());
+                let stopped_response = redact_outbound(&state_owned, &stopped_response).await;
+
+                // Rate limit before final API call
+                shared_rate_limit_wait(&state_owned, chat_id).await;
+
+                // Update placeholder message with partial response instead of deleting
+                let html_stopped = markdown_to_telegram_html(&stopped_response);
+                if html_stopped.len() <= TELEGRAM_MSG_LIMIT {
+                    if let Err(e) = bot_owned
+                        .edit_message_text(chat_id, placeholder_msg_id, &html_stopped)
+                        .parse_mode(ParseMode::Html)
+                        .await
+                    {
+                        tracing::warn!("edit_message failed (stopped/HTML): {e}");
+                        shared_rate_limit_wait(&state_owned, chat_id).await;
+                        let _ = bot_owned
+                            .edit_message_text(chat_id, placeholder_msg_id, &stopped_response)
+                            .await;
+                    }
+                } else {
+                    let send_result = send_long_message(
+                        &bot_owned,
+                        chat_id,
+                        &html_stopped,
+                        Some(ParseMode::Html),
+                        &state_owned,
+                    )
+                    .await;
+                    match send_result {
+                        Ok(_) => {
+                            shared_rate_limit_wait(&state_owned, chat_id).await;
+                            let _ = bot_owned.delete_message(chat_id, placeholder_msg_id).await;
                         }
-                        StreamMessage::ToolResult { content, is_error } => {
-                            if is_error {
-                                let ts = chrono::Local::now().format("%H:%M:%S");
-                                println!("  [{ts}]   ✗ Error: {}", truncate_str(&content, 80));
-                                let truncated = truncate_str(&content, 500);
-                                if truncated.contains('\n') {
-                                    full_response
-                                        .push_str(&format!("\n❌\n```\n{}\n```\n", truncated));
-                                } else {
-                                    full_response.push_str(&format!("\n❌ `{}`\n\n", truncated));
+                        Err(e) => {
+                            tracing::warn!("send_long_message failed (stopped/HTML): {e}");
+                            let fallback = send_long_message(
+                                &bot_owned,
+                                chat_id,
+                                &stopped_response,
+                                None,
+                                &state_owned,
+                            )
+                            .await;
+                            match fallback {
+                                Ok(_) => {
+                                    shared_rate_limit_wait(&state_owned, chat_id).await;
+                                    let _ =
+                                        bot_owned.delete_message(chat_id, placeholder_msg_id).await;
                                 }
-                            } else if !content.is_empty() {
-                                let truncated = truncate_str(&content, 300);
-                                if truncated.contains('\n') {
-                                    full_response.push_str(&format!("\n```\n{}\n```\n", truncated));
-                                } else {
-                                    full_response.push_str(&format!("\n✅ `{}`\n\n", truncated));
+                                Err(_) => {
+                                    shared_rate_limit_wait(&state_owned, chat_id).await;
+                                    let truncated =
+                                        truncate_str(&stopped_response, TELEGRAM_MSG_LIMIT);
+                                    let _ = bot_owned
+                                        .edit_message_text(chat_id, placeholder_msg_id, &truncated)
+                                        .await;
                                 }
                             }
                         }
-                        StreamMessage::TaskNotification { summary, .. } => {
-                            if !summary.is_empty() {
-                                full_response.push_str(&format!("\n[Task: {}]\n", summary));
-                            }
-                        }
-                        StreamMessage::Done {
-                            result,
-                            session_id: sid,
-                        } => {
-                            if !result.is_empty() && full_response.is_empty() {
-                                full_response = result;
-                            }
-                            if let Some(s) = sid {
-                                new_session_id = Some(s);
-                            }
-                            done = true;
-                        }
-                        StreamMessage::Error { message } => {
-                            full_response = format!("Error: {}", message);
-                            done = true;
-                        }
-                    },
-                    Err(std::sync::mpsc::TryRecvError::Empty) => break,
-                    Err(std::sync::mpsc::TryRecvError::Disconnected) => {
-                        done = true;
-                        break;
                     }
                 }
-            }
-
-            // Build display text with spinning clock+text indicator appended
-            let indicator = SPINNER[spin_idx % SPINNER.len()];
-            spin_idx += 1;
 
-            let display_text = if full_response.is_empty() {
-                indicator.to_string()
-            } else {
-                let normalized = normalize_empty_lines(&full_response);
-                let truncated = truncate_str(&normalized, TELEGRAM_MSG_LIMIT - 20);
-                format!("{}\n\n{}", truncated, indicator)
-            };
+                checklist
+                    .finish(&bot_owned, chat_id, &state_owned, response.checklist_html())
+                    .await;
 
-            if display_text != last_edit_text && !done {
-                // Rate limit: reserve slot right before the actual API call
-                shared_rate_limit_wait(&state_owned, chat_id).await;
-                let html_text = markdown_to_telegram_html(&display_text);
-                if let Err(e) = bot_owned
-                    .edit_message_text(chat_id, placeholder_msg_id, &html_text)
-                    .parse_mode(ParseMode::Html)
-                    .await
-                {
-                    let ts = chrono::Local::now().format("%H:%M:%S");
-                    println!("  [{ts}]   ⚠ edit_message failed (streaming): {e}");
+                // Delete the "Stopping..." message (no longer needed)
+                if let Some(msg_id) = stop_msg_id {
+                    shared_rate_limit_wait(&state_owned, chat_id).await;
+                    let _ = bot_owned.delete_message(chat_id, msg_id).await;
                 }
-                last_edit_text = display_text;
-            } else if !done {
-                // No new content to display, send typing indicator
-                shared_rate_limit_wait(&state_owned, chat_id).await;
-                let _ = bot_owned
-                    .send_chat_action(chat_id, teloxide::types::ChatAction::Typing)
+
+                tracing::info!("Stopped");
+                record_ai_run(run_entry, "stopped", started, &response.touched);
+
+                if let Some(before) = before_snapshot {
+                    let _ = send_change_summary(
+                        &bot_owned,
+                        chat_id,
+                        &state_owned,
+                        &current_path,
+                        before,
+                        response.touched,
+                    )
                     .await;
-            }
-        }
+                }
 
-        // Remove cancel token and take stop message ID (processing is done)
-        let stop_msg_id = {
-            let mut data = state_owned.lock().await;
-            data.cancel_tokens.remove(&chat_id);
-            data.stop_message_ids.remove(&chat_id)
-        };
+                // Record user message + stopped response in history
+                let mut data = state_owned.lock().await;
+                if let Some(session) = data.sessions.get_mut(&chat_id) {
+                    if session.cleared {
+                        // Session was cleared by /clear; do not re-populate
+                    } else {
+                        if let Some(ref new_sid) = response.session_id {
+                            if let Some(ref old_sid) = session.session_id {
+                                if old_sid != new_sid {
+                                    delete_session_file(old_sid);
+                                }
+                            }
+                            session.session_id = Some(new_sid.clone());
+                        }
+                        session.history.push(HistoryItem {
+                            item_type: HistoryType::User,
+                            content: user_text_owned,
+                        });
+                        session.history.push(HistoryItem {
+                            item_type: HistoryType::Assistant,
+                            content: stopped_response,
+                        });
 
-        if cancelled {
-            // Ensure child process is killed.
-            if let Ok(guard) = cancel_token.child_pid.lock() {
-                if let Some(pid) = *guard {
-                    #[cfg(unix)]
-                    // SAFETY: pid was obtained from child.id() and the child process is still
-                    // tracked. SIGTERM is a safe signal that asks the process to terminate.
-                    #[allow(unsafe_code)]
-                    unsafe {
-                        libc::kill(pid as libc::pid_t, libc::SIGTERM);
+                        save_session_to_file(session, &current_path);
                     }
                 }
-            }
 
-            // Build stopped response: show partial content + [Stopped] indicator
-            let stopped_response = if full_response.trim().is_empty() {
-                "[Stopped]".to_string()
-            } else {
-                let normalized = normalize_empty_lines(&full_response);
-                format!("{}\n\n[Stopped]", normalized)
-            };
+                return;
+            }
 
             // Rate limit before final API call
             shared_rate_limit_wait(&state_owned, chat_id).await;
 
-            // Update placeholder message with partial response instead of deleting
-            let html_stopped = markdown_to_telegram_html(&stopped_response);
-            if html_stopped.len() <= TELEGRAM_MSG_LIMIT {
+            // Final response
+            let full_response =
+                redact_outbound(&state_owned, &response.final_text(role_header.as_deref())).await;
+            let html_response = markdown_to_telegram_html(&full_response);
+
+            if html_response.len() <= TELEGRAM_MSG_LIMIT {
+                // Try HTML first, fall back to plain text if it fails
                 if let Err(e) = bot_owned
-                    .edit_message_text(chat_id, placeholder_msg_id, &html_stopped)
+                    .edit_message_text(chat_id, placeholder_msg_id, &html_response)
                     .parse_mode(ParseMode::Html)
                     .await
                 {
-                    let ts_err = chrono::Local::now().format("%H:%M:%S");
-                    println!("  [{ts_err}]   ⚠ edit_message failed (stopped/HTML): {e}");
+                    tracing::warn!("edit_message failed (HTML): {e}");
+                    // Fallback: try plain text without HTML parse mode
                     shared_rate_limit_wait(&state_owned, chat_id).await;
                     let _ = bot_owned
-                        .edit_message_text(chat_id, placeholder_msg_id, &stopped_response)
+                        .edit_message_text(chat_id, placeholder_msg_id, &full_response)
                         .await;
                 }
             } else {
+                // For long responses: send new messages FIRST, then delete placeholder.
                 let send_result = send_long_message(
                     &bot_owned,
                     chat_id,
-                    &html_stopped,
+                    &html_response,
                     Some(ParseMode::Html),
                     &state_owned,
                 )
                 .await;
                 match send_result {
                     Ok(_) => {
+                        // New messages sent successfully, now safe to delete placeholder
                         shared_rate_limit_wait(&state_owned, chat_id).await;
                         let _ = bot_owned.delete_message(chat_id, placeholder_msg_id).await;
                     }
                     Err(e) => {
-                        let ts_err = chrono::Local::now().format("%H:%M:%S");
-                        println!("  [{ts_err}]   ⚠ send_long_message failed (stopped/HTML): {e}");
-                        let fallback = send_long_message(
+                        tracing::warn!("send_long_message failed (HTML): {e}");
+                        // Fallback: try plain text
+                        let fallback_result = send_long_message(
                             &bot_owned,
                             chat_id,
-                            &stopped_response,
+                            &full_response,
                             None,
                             &state_owned,
                         )
                         .await;
-                        match fallback {
+                        match fallback_result {
                             Ok(_) => {
                                 shared_rate_limit_wait(&state_owned, chat_id).await;
                                 let _ = bot_owned.delete_message(chat_id, placeholder_msg_id).await;
                             }
-                            Err(_) => {
+                            Err(e2) => {
+                                tracing::warn!("send_long_message failed (plain): {e2}");
+                                // Last resort: edit placeholder with truncated plain text
                                 shared_rate_limit_wait(&state_owned, chat_id).await;
-                                let truncated = truncate_str(&stopped_response, TELEGRAM_MSG_LIMIT);
+                                let truncated = truncate_str(&full_response, TELEGRAM_MSG_LIMIT);
                                 let _ = bot_owned
                                     .edit_message_text(chat_id, placeholder_msg_id, &truncated)
                                     .await;
@@ -669,151 +1022,82 @@ async fn handle_text_message(
                 }
             }
 
-            // Delete the "Stopping..." message (no longer needed)
+            checklist
+                .finish(&bot_owned, chat_id, &state_owned, response.checklist_html())
+                .await;
+
+            let outcome = if response.errored { "error" } else { "done" };
+            record_ai_run(run_entry, outcome, started, &response.touched);
+
+            // Clean up leftover "Stopping..." message if /stop raced with normal completion
             if let Some(msg_id) = stop_msg_id {
                 shared_rate_limit_wait(&state_owned, chat_id).await;
                 let _ = bot_owned.delete_message(chat_id, msg_id).await;
             }
 
-            let ts = chrono::Local::now().format("%H:%M:%S");
-            println!("  [{ts}] ■ Stopped");
-
-            // Record user message + stopped response in history
-            let mut data = state_owned.lock().await;
-            if let Some(session) = data.sessions.get_mut(&chat_id) {
-                if session.cleared {
-                    // Session was cleared by /clear; do not re-populate
-                } else {
-                    if let Some(ref new_sid) = new_session_id {
-                        if let Some(ref old_sid) = session.session_id {
-                            if old_sid != new_sid {
-                                delete_session_file(old_sid);
-                            }
-                        }
-                        session.session_id = Some(new_sid.clone());
-                    }
-                    session.history.push(HistoryItem {
-                        item_type: HistoryType::User,
-                        content: user_text_owned,
-                    });
-                    session.history.push(HistoryItem {
-                        item_type: HistoryType::Assistant,
-                        content: stopped_response,
-                    });
-
-                    save_session_to_file(session, &current_path);
-                }
+            if let Some(before) = before_snapshot {
+                let _ = send_change_summary(
+                    &bot_owned,
+                    chat_id,
+                    &state_owned,
+                    &current_path,
+                    before,
+                    response.touched,
+                )
+                .await;
             }
 
-            return;
-        }
-
-        // Rate limit before final API call
-        shared_rate_limit_wait(&state_owned, chat_id).await;
-
-        // Final response
-        if full_response.is_empty() {
-            full_response = "(No response)".to_string();
-        }
-
-        let full_response = normalize_empty_lines(&full_response);
-        let html_response = markdown_to_telegram_html(&full_response);
-
-        if html_response.len() <= TELEGRAM_MSG_LIMIT {
-            // Try HTML first, fall back to plain text if it fails
-            if let Err(e) = bot_owned
-                .edit_message_text(chat_id, placeholder_msg_id, &html_response)
-                .parse_mode(ParseMode::Html)
-                .await
+            // Update session state: push user message + assistant response together
             {
-                let ts = chrono::Local::now().format("%H:%M:%S");
-                println!("  [{ts}]   ⚠ edit_message failed (HTML): {e}");
-                // Fallback: try plain text without HTML parse mode
-                shared_rate_limit_wait(&state_owned, chat_id).await;
-                let _ = bot_owned
-                    .edit_message_text(chat_id, placeholder_msg_id, &full_response)
-                    .await;
-            }
-        } else {
-            // For long responses: send new messages FIRST, then delete placeholder.
-            let send_result = send_long_message(
-                &bot_owned,
-                chat_id,
-                &html_response,
-                Some(ParseMode::Html),
-                &state_owned,
-            )
-            .await;
-            match send_result {
-                Ok(_) => {
-                    // New messages sent successfully, now safe to delete placeholder
-                    shared_rate_limit_wait(&state_owned, chat_id).await;
-                    let _ = bot_owned.delete_message(chat_id, placeholder_msg_id).await;
-                }
-                Err(e) => {
-                    let ts = chrono::Local::now().format("%H:%M:%S");
-                    println!("  [{ts}]   ⚠ send_long_message failed (HTML): {e}");
-                    // Fallback: try plain text
-                    let fallback_result =
-                        send_long_message(&bot_owned, chat_id, &full_response, None, &state_owned)
-                            .await;
-                    match fallback_result {
-                        Ok(_) => {
-                            shared_rate_limit_wait(&state_owned, chat_id).await;
-                            let _ = bot_owned.delete_message(chat_id, placeholder_msg_id).await;
-                        }
-                        Err(e2) => {
-                            println!("  [{ts}]   ⚠ send_long_message failed (plain): {e2}");
-                            // Last resort: edit placeholder with truncated plain text
-                            shared_rate_limit_wait(&state_owned, chat_id).await;
-                            let truncated = truncate_str(&full_response, TELEGRAM_MSG_LIMIT);
-                            let _ = bot_owned
-                                .edit_message_text(chat_id, placeholder_msg_id, &truncated)
-                                .await;
-                        }
-                    }
+                let mut data = state_owned.lock().await;
+                if !response.mcp_servers.is_empty() {
+                    data.mcp_status.insert(chat_id, response.mcp_servers);
                 }
-            }
-        }
-
-        // Clean up leftover "Stopping..." message if /stop raced with normal completion
-        if let Some(msg_id) = stop_msg_id {
-            shared_rate_limit_wait(&state_owned, chat_id).await;
-            let _ = bot_owned.delete_message(chat_id, msg_id).await;
-        }
-
-        // Update session state: push user message + assistant response together
-        {
-            let mut data = state_owned.lock().await;
-            if let Some(session) = data.sessions.get_mut(&chat_id) {
-                if session.cleared {
-                    // Session was cleared by /clear; do not re-populate
-                } else {
-                    if let Some(ref new_sid) = new_session_id {
-                        if let Some(ref old_sid) = session.session_id {
-                            if old_sid != new_sid {
-                                delete_session_file(old_sid);
+                if let Some(session) = data.sessions.get_mut(&chat_id) {
+                    if session.cleared {
+                        // Session was cleared by /clear; do not re-populate
+                    } else {
+                        if let Some(ref new_sid) = response.session_id {
+                            if let Some(ref old_sid) = session.session_id {
+                                if old_sid != new_sid {
+                                    delete_session_file(old_sid);
+                                }
                             }
+                            session.session_id = Some(new_sid.clone());
                         }
-                        session.session_id = Some(new_sid.clone());
+                        session.history.push(HistoryItem {
+                            item_type: HistoryType::User,
+                            content: user_text_owned,
+                        });
+                        session.history.push(HistoryItem {
+                            item_type: HistoryType::Assistant,
+                            content: full_response,
+                        });
+
+                        save_session_to_file(session, &current_path);
                     }
-                    session.history.push(HistoryItem {
-                        item_type: HistoryType::User,
-                        content: user_text_owned,
-                    });
-                    session.history.push(HistoryItem {
-                        item_type: HistoryType::Assistant,
-                        content: full_response,
-                    });
-
-                    save_session_to_file(session, &current_path);
                 }
             }
-        }
 
-        let ts = chrono::Local::now().format("%H:%M:%S");
-        println!("  [{ts}] ▶ Response sent");
-    });
+            tracing::info!("Response sent");
+        }
+        .instrument(run_span),
+    );
 
     Ok(())
 }
+
+/// Complete `entry` (created when the run started) with how it ended and record it
+fn record_ai_run(
+    mut entry: AuditEntry,
+    outcome: &str,
+    started: std::time::Instant,
+    touched: &[PathBuf],
+) {
+    entry.outcome = Some(outcome.to_string());
+    entry.duration_ms = Some(started.elapsed().as_millis() as u64);
+    entry.files_touched = touched.iter().map(|p| p.display().to_string()).collect();
+    entry.files_touched.sort();
+    entry.files_touched.dedup();
+    audit::record(&entry);
+}
@@ -1,7 +1,19 @@
+mod audit_log;
 mod bot;
+mod browser;
+mod callback;
+mod changes;
+mod checklist;
+mod checkpoints;
 mod commands;
 mod file_ops;
+mod git_ops;
+mod instructions;
+mod interaction;
+mod mcp;
 mod message;
+mod preview;
+mod render;
 mod storage;
 mod streaming;
 mod tools;
@@ -9,12 +21,15 @@ mod tools;
 use std::collections::HashMap;
 
 use teloxide::prelude::*;
+use tracing::Instrument;
 
 use self::bot::SharedData;
+use self::callback::handle_callback_query;
 use self::message::handle_message;
 use self::storage::load_bot_settings;
 
 // Re-export public API used by main.rs
+pub use self::render::replay_transcript;
 pub use self::storage::resolve_token_by_hash;
 
 /// Entry point: start the Telegram bot with long polling.
@@ -31,39 +46,108 @@ pub async fn run_bot(token: &str, default_project_dir: &str) {
         teloxide::types::BotCommand::new("cd", "Change working directory"),
         teloxide::types::BotCommand::new("clear", "Clear AI conversation history"),
         teloxide::types::BotCommand::new("stop", "Stop current AI request"),
+        teloxide::types::BotCommand::new("ls", "Browse files in current directory"),
+        teloxide::types::BotCommand::new("cat", "Show file contents with line numbers"),
         teloxide::types::BotCommand::new("down", "Download file from server"),
+        teloxide::types::BotCommand::new("diff", "Show changed files (git)"),
+        teloxide::types::BotCommand::new("log", "Show recent commits (git)"),
+        teloxide::types::BotCommand::new("commit", "Commit all changes (git)"),
+        teloxide::types::BotCommand::new("undo", "Undo file changes from the last AI turn"),
+        teloxide::types::BotCommand::new("checkpoints", "List and restore checkpoints"),
+        teloxide::types::BotCommand::new(
+            "thinking",
+            "Show or hide extended thinking, set its budget",
+        ),
+        teloxide::types::BotCommand::new("model", "Show or set the AI model"),
+        teloxide::types::BotCommand::new("maxturns", "Limit agentic turns per prompt"),
+        teloxide::types::BotCommand::new("effort", "Set how much the AI thinks"),
+        teloxide::types::BotCommand::new("systemprompt", "Extra instructions for the AI"),
+        teloxide::types::BotCommand::new("mcp", "MCP servers of this project"),
+        teloxide::types::BotCommand::new("adddir", "Extra directories the AI may use"),
         teloxide::types::BotCommand::new("public", "Toggle public access (group only)"),
+        teloxide::types::BotCommand::new("publictools", "Tools for non-owner prompts (group only)"),
+        teloxide::types::BotCommand::new("audit", "Show recent audit log entries"),
+        teloxide::types::BotCommand::new("trace", "Download the last AI run's raw stream"),
+        teloxide::types::BotCommand::new("redact", "Manage secret redaction patterns"),
         teloxide::types::BotCommand::new("availabletools", "List all available tools"),
         teloxide::types::BotCommand::new("allowedtools", "Show currently allowed tools"),
         teloxide::types::BotCommand::new("allowed", "Add/remove tool (+name / -name)"),
     ];
     if let Err(e) = bot.set_my_commands(commands).await {
-        println!("  ⚠ Failed to set bot commands: {e}");
+        tracing::warn!("Failed to set bot commands: {e}");
     }
 
     match bot_settings.owner_user_id {
-        Some(owner_id) => println!("  ✓ Owner: {owner_id}"),
-        None => println!("  ⚠ No owner registered — first user will be registered as owner"),
+        Some(owner_id) => tracing::info!(owner_id, "Owner registered"),
+        None => tracing::warn!("No owner registered — first user will be registered as owner"),
     }
 
+    let redact_regexes = crate::redact::compile_patterns(&bot_settings.redact_patterns);
     let state: bot::SharedState = std::sync::Arc::new(tokio::sync::Mutex::new(SharedData {
         sessions: HashMap::new(),
         settings: bot_settings,
         cancel_tokens: HashMap::new(),
+        run_users: HashMap::new(),
+        mcp_status: HashMap::new(),
+        redact_regexes,
         stop_message_ids: HashMap::new(),
         api_timestamps: HashMap::new(),
+        browse_views: HashMap::new(),
+        cat_views: HashMap::new(),
+        diff_views: HashMap::new(),
+        pending_commits: HashMap::new(),
+        checkpoint_views: HashMap::new(),
+        pending_shells: HashMap::new(),
+        interactions: HashMap::new(),
     }));
 
-    println!("  ✓ Bot connected — Listening for messages");
+    tracing::info!("Bot connected — listening for messages");
 
-    let shared_state = state.clone();
-    let token_owned = token.to_string();
+    let message_state = state.clone();
+    let callback_state = state.clone();
+    let message_token = token.to_string();
+    let callback_token = token.to_string();
     let default_project_dir_owned = default_project_dir.to_string();
-    teloxide::repl(bot, move |bot: Bot, msg: Message| {
-        let state = shared_state.clone();
-        let token = token_owned.clone();
-        let default_project_dir = default_project_dir_owned.clone();
-        async move { handle_message(bot, msg, state, &token, &default_project_dir).await }
-    })
-    .await;
+
+    // Messages and inline-keyboard callbacks share the same state; other update kinds are ignored.
+    let handler = dptree::entry()
+        .branch(
+            Update::filter_message().endpoint(move |bot: Bot, msg: Message| {
+                let state = message_state.clone();
+                let token = message_token.clone();
+                let default_project_dir = default_project_dir_owned.clone();
+                let span = tracing::info_span!(
+                    "chat",
+                    chat_id = msg.chat.id.0,
+                    user_id = msg.from.as_ref().map(|u| u.id.0).unwrap_or(0),
+                    user = msg
+                        .from
+                        .as_ref()
+                        .map(|u| u.first_name.as_str())
+                        .unwrap_or(""),
+                );
+                async move { handle_message(bot, msg, state, &token, &default_project_dir).await }
+                    .instrument(span)
+            }),
+        )
+        .branch(
+            Update::filter_callback_query().endpoint(move |bot: Bot, q: CallbackQuery| {
+                let state = callback_state.clone();
+                let token = callback_token.clone();
+                let span = tracing::info_span!(
+                    "chat",
+                    chat_id = q.message.as_ref().map(|m| m.chat().id.0).unwrap_or(0),
+                    user_id = q.from.id.0,
+                    user = q.from.first_name.as_str(),
+                );
+                async move { handle_callback_query(bot, q, state, &token).await }.instrument(span)
+            }),
+        );
+
+    Dispatcher::builder(bot, handler)
+        .default_handler(|_upd| Box::pin(async {}))
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch()
+        .await;
 }
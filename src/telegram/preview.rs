@@ -0,0 +1,418 @@
+use std::fs;
+use std::path::Path;
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode};
+
+use crate::auth::is_path_within_sandbox;
+
+use super::bot::{shared_rate_limit_wait, SharedState};
+use super::streaming::{escape_within, html_escape, redact_outbound};
+
+/// Lines per page when no explicit range is given
+const CAT_PAGE_LINES: usize = 60;
+
+/// Byte budget for the rendered code block, leaving room for the header and tags
+const CAT_PAGE_BYTES: usize = 3500;
+
+/// Files larger than this are refused (use /down instead)
+const CAT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Per-chat state for the /cat paging keyboard
+pub(crate) struct CatView {
+    pub(crate) message_id: MessageId,
+    pub(crate) path: String,
+}
+
+/// Parse a `start:end` line range (1-based, inclusive).
+/// Accepts `N`, `N:`, `:M` and `N:M`; returns None for malformed input.
+pub(crate) fn parse_line_range(spec: &str) -> Option<(usize, Option<usize>)> {
+    let spec = spec.trim();
+    let (start, end) = match spec.split_once(':') {
+        Some((s, e)) => (s.trim(), Some(e.trim())),
+        None => (spec, None),
+    };
+    let start = if start.is_empty() {
+        1
+    } else {
+        start.parse::<usize>().ok()?.max(1)
+    };
+    let end = match end {
+        // Single line: "N"
+        None => Some(start),
+        Some("") => None,
+        Some(e) => Some(e.parse::<usize>().ok()?),
+    };
+    if let Some(e) = end {
+        if e < start {
+            return None;
+        }
+    }
+    Some((start, end))
+}
+
+/// Infer a highlight.js-style language name from a file name
+pub(crate) fn language_for_path(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_string_lossy().to_lowercase();
+    match file_name.as_str() {
+        "dockerfile" => return Some("dockerfile"),
+        "makefile" => return Some("makefile"),
+        "cmakelists.txt" => return Some("cmake"),
+        _ => {}
+    }
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    let lang = match ext.as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "mjs" | "cjs" | "jsx" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "swift" => "swift",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "lua" => "lua",
+        "sh" | "bash" | "zsh" => "bash",
+        "ps1" => "powershell",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "xml" | "svg" => "xml",
+        "css" => "css",
+        "scss" => "scss",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "ini" | "cfg" => "ini",
+        "md" | "markdown" => "markdown",
+        "diff" | "patch" => "diff",
+        _ => return None,
+    };
+    Some(lang)
+}
+
+/// Detect binary content: NUL bytes or invalid UTF-8
+pub(crate) fn looks_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8192).any(|b| *b == 0) || std::str::from_utf8(bytes).is_err()
+}
+
+/// Render lines `start..` (1-based) with right-aligned line numbers, stopping at
+/// `end`, `CAT_PAGE_LINES` when no end is given, or the byte budget.
+/// Returns the rendered block and the last line number included.
+fn render_numbered(lines: &[&str], start: usize, end: Option<usize>) -> (String, usize) {
+    let last_wanted = end.unwrap_or(start + CAT_PAGE_LINES - 1).min(lines.len());
+    let width = last_wanted.to_string().len();
+    let mut out = String::new();
+    let mut last = start.saturating_sub(1);
+    for n in start..=last_wanted {
+        let prefix = format!("{:>width$} │ ", n, width = width);
+        let text = escape_within(lines[n - 1], CAT_PAGE_BYTES - prefix.len() - 1);
+        let line = format!("{}{}\n", prefix, text);
+        if !out.is_empty() && out.len() + line.len() > CAT_PAGE_BYTES {
+            break;
+        }
+        out.push_str(&line);
+        last = n;
+    }
+    (out.trim_end_matches('\n').to_string(), last)
+}
+
+/// Build the message text and paging keyboard for one page of a file
+fn render_page(
+    path: &Path,
+    content: &str,
+    start: usize,
+    end: Option<usize>,
+) -> (String, Option<InlineKeyboardMarkup>) {
+    let lines: Vec<&str> = content.lines().collect();
+    let total = lines.len();
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if total == 0 {
+        return (format!("<b>{}</b>\n(empty file)", html_escape(&name)), None);
+    }
+    if start > total {
+        return (
+            format!(
+                "<b>{}</b>\nLine {} is past the end of the file ({} lines).",
+                html_escape(&name),
+                start,
+                total
+            ),
+            None,
+        );
+    }
+
+    let (body, last) = render_numbered(&lines, start, end);
+    let code = match language_for_path(path) {
+        Some(lang) => format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>",
+            lang, body
+        ),
+        None => format!("<pre>{}</pre>", body),
+    };
+    let text = format!(
+        "<b>{}</b> · lines {}-{} of {}\n{}",
+        html_escape(&name),
+        start,
+        last,
+        total,
+        code
+    );
+
+    if start == 1 && last >= total {
+        return (text, None);
+    }
+    let mut nav = Vec::new();
+    if start > 1 {
+        let prev = start.saturating_sub(CAT_PAGE_LINES).max(1);
+        nav.push(InlineKeyboardButton::callback(
+            "◀ Prev",
+            format!("cat:{}", prev),
+        ));
+    }
+    if last < total {
+        nav.push(InlineKeyboardButton::callback(
+            "Next ▶",
+            format!("cat:{}", last + 1),
+        ));
+    }
+    (text, Some(InlineKeyboardMarkup::new(vec![nav])))
+}
+
+/// Read a file for display, refusing oversized and binary files
+fn read_text_file(path: &Path) -> Result<String, String> {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size > CAT_MAX_FILE_SIZE {
+        return Err(format!(
+            "File too large to display ({} bytes). Use /down instead.",
+            size
+        ));
+    }
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    if looks_binary(&bytes) {
+        return Err("Binary file — use /down to download it instead.".to_string());
+    }
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Send a page of `path` starting at `start` and remember it for the paging buttons
+pub(crate) async fn send_file_page(
+    bot: &Bot,
+    chat_id: ChatId,
+    path: &Path,
+    start: usize,
+    end: Option<usize>,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let content = match read_text_file(path) {
+        Ok(content) => content,
+        Err(e) => {
+            shared_rate_limit_wait(state, chat_id).await;
+            bot.send_message(chat_id, e).await?;
+            return Ok(());
+        }
+    };
+    let (text, keyboard) = render_page(path, &content, start, end);
+    let text = redact_outbound(state, &text).await;
+
+    shared_rate_limit_wait(state, chat_id).await;
+    let mut req = bot.send_message(chat_id, text).parse_mode(ParseMode::Html);
+    if let Some(kb) = keyboard {
+        req = req.reply_markup(kb);
+    }
+    let sent = req.await?;
+
+    let mut data = state.lock().await;
+    data.cat_views.insert(
+        chat_id,
+        CatView {
+            message_id: sent.id,
+            path: path.display().to_string(),
+        },
+    );
+    Ok(())
+}
+
+/// Handle /cat <file> [start:end] - show file contents with line numbers
+pub(crate) async fn handle_cat_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let args = text.strip_prefix("/cat").unwrap_or("").trim();
+    let (file_path, range_spec) = match args.rsplit_once(char::is_whitespace) {
+        Some((file, range)) if parse_line_range(range).is_some() => (file.trim(), Some(range)),
+        _ => (args, None),
+    };
+
+    if file_path.is_empty() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(
+            chat_id,
+            "Usage: /cat <file> [start:end]\nExample: /cat src/main.rs 10:40",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Resolve relative path using current session path (same rules as /down)
+    let resolved_path = if Path::new(file_path).is_absolute() {
+        file_path.to_string()
+    } else {
+        let current_path = {
+            let data = state.lock().await;
+            data.sessions
+                .get(&chat_id)
+                .and_then(|s| s.current_path.clone())
+        };
+        match current_path {
+            Some(base) => format!("{}/{}", base.trim_end_matches('/'), file_path),
+            None => {
+                shared_rate_limit_wait(state, chat_id).await;
+                bot.send_message(
+                    chat_id,
+                    "No active session. Use absolute path or /start <path> first.",
+                )
+                .await?;
+                return Ok(());
+            }
+        }
+    };
+
+    let path = Path::new(&resolved_path);
+    if !path.is_file() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, format!("File not found: {}", resolved_path))
+            .await?;
+        return Ok(());
+    }
+
+    let sandbox_root = dirs::home_dir().unwrap_or_else(|| Path::new("/").to_path_buf());
+    if !is_path_within_sandbox(path, &sandbox_root) {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(
+            chat_id,
+            format!(
+                "Access denied: '{}' is outside the allowed path sandbox.",
+                resolved_path
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let (start, end) = range_spec.and_then(parse_line_range).unwrap_or((1, None));
+    send_file_page(bot, chat_id, path, start, end, state).await
+}
+
+/// Handle `cat:<start>` paging callbacks by editing the page in place.
+/// Returns an optional toast to show in the callback answer.
+pub(crate) async fn handle_cat_callback(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    action: &str,
+    state: &SharedState,
+) -> ResponseResult<Option<String>> {
+    let path = {
+        let data = state.lock().await;
+        match data.cat_views.get(&chat_id) {
+            Some(view) if view.message_id == message_id => view.path.clone(),
+            _ => return Ok(Some("This view has expired. Use /cat again.".to_string())),
+        }
+    };
+    let start = action.parse::<usize>().unwrap_or(1).max(1);
+
+    let path = Path::new(&path);
+    let content = match read_text_file(path) {
+        Ok(content) => content,
+        Err(e) => return Ok(Some(e)),
+    };
+    let (text, keyboard) = render_page(path, &content, start, None);
+    let text = redact_outbound(state, &text).await;
+
+    shared_rate_limit_wait(state, chat_id).await;
+    let mut req = bot
+        .edit_message_text(chat_id, message_id, text)
+        .parse_mode(ParseMode::Html);
+    if let Some(kb) = keyboard {
+        req = req.reply_markup(kb);
+    }
+    if let Err(e) = req.await {
+        tracing::warn!("edit_message failed (cat): {e}");
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line_range() {
+        assert_eq!(parse_line_range("10:20"), Some((10, Some(20))));
+        assert_eq!(parse_line_range("10:"), Some((10, None)));
+        assert_eq!(parse_line_range(":20"), Some((1, Some(20))));
+        assert_eq!(parse_line_range("7"), Some((7, Some(7))));
+        assert_eq!(parse_line_range("20:10"), None);
+        assert_eq!(parse_line_range("abc"), None);
+        assert_eq!(parse_line_range("src/main.rs"), None);
+    }
+
+    #[test]
+    fn test_language_for_path() {
+        assert_eq!(language_for_path(Path::new("src/main.rs")), Some("rust"));
+        assert_eq!(language_for_path(Path::new("app.TSX")), Some("typescript"));
+        assert_eq!(
+            language_for_path(Path::new("Dockerfile")),
+            Some("dockerfile")
+        );
+        assert_eq!(language_for_path(Path::new("notes.unknown")), None);
+        assert_eq!(language_for_path(Path::new("LICENSE")), None);
+    }
+
+    #[test]
+    fn test_looks_binary() {
+        assert!(!looks_binary(b"fn main() {}\n"));
+        assert!(looks_binary(b"PNG\x00\x01\x02"));
+        assert!(looks_binary(&[0xff, 0xfe, 0xfd]));
+    }
+
+    #[test]
+    fn test_render_page_numbers_and_paging() {
+        let content: String = (1..=100).map(|n| format!("line {}\n", n)).collect();
+        let (text, keyboard) = render_page(Path::new("a.rs"), &content, 1, None);
+        assert!(text.contains("class=\"language-rust\""));
+        assert!(text.contains(" 1 │ line 1"));
+        assert!(text.contains("lines 1-60 of 100"));
+        assert!(keyboard.is_some());
+
+        let (text, _) = render_page(Path::new("a.rs"), &content, 5, Some(6));
+        assert!(text.contains("5 │ line 5"));
+        assert!(text.contains("6 │ line 6"));
+        assert!(!text.contains("line 7"));
+    }
+
+    #[test]
+    fn test_render_page_cuts_long_lines() {
+        let content = format!("{}\nshort\n", "<".repeat(10_000));
+        let (text, keyboard) = render_page(Path::new("a.min.js"), &content, 1, None);
+        assert!(text.len() < 4096);
+        assert!(text.contains("&lt;…"));
+        assert!(keyboard.is_some());
+    }
+
+    #[test]
+    fn test_render_page_escapes_html() {
+        let (text, _) = render_page(Path::new("a.html"), "<b>&</b>\n", 1, None);
+        assert!(text.contains("&lt;b&gt;&amp;&lt;/b&gt;"));
+    }
+}
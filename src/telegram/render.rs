@@ -0,0 +1,621 @@
+use std::path::PathBuf;
+
+use teloxide::types::ChatId;
+
+use crate::claude::{self, McpServerStatus, StreamMessage};
+use crate::redact;
+use crate::trace;
+
+use super::bot::TELEGRAM_MSG_LIMIT;
+use super::checklist::Checklist;
+use super::storage::BotSettings;
+use super::streaming::{
+    format_tool_input, markdown_to_telegram_html, normalize_empty_lines, split_message,
+    truncate_str,
+};
+
+/// Most recent sub-agent tool calls listed under a Task; older ones are counted
+const SUBAGENT_VISIBLE_TOOLS: usize = 5;
+
+/// Longest thinking block quoted in full; the rest is cut off
+const THINKING_QUOTE_LIMIT: usize = 1500;
+
+/// How a chat shows the model's extended thinking (/thinking)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ThinkingDisplay {
+    /// Not shown
+    #[default]
+    Off,
+    /// Quoted in full, as an expandable blockquote
+    On,
+    /// One line per thinking block
+    Summary,
+}
+
+impl ThinkingDisplay {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(ThinkingDisplay::Off),
+            "on" => Some(ThinkingDisplay::On),
+            "summary" => Some(ThinkingDisplay::Summary),
+            _ => None,
+        }
+    }
+
+    /// The chat's /thinking setting
+    pub fn for_chat(settings: &BotSettings, chat_id: ChatId) -> Self {
+        settings
+            .thinking_display
+            .get(&chat_id.0.to_string())
+            .and_then(|s| Self::parse(s))
+            .unwrap_or_default()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ThinkingDisplay::Off => "off",
+            ThinkingDisplay::On => "on",
+            ThinkingDisplay::Summary => "summary",
+        }
+    }
+}
+
+/// Markdown for one thinking block, or nothing when thinking is hidden
+fn render_thinking(content: &str, display: ThinkingDisplay) -> String {
+    match display {
+        ThinkingDisplay::Off => String::new(),
+        ThinkingDisplay::On => {
+            let quoted: Vec<String> = truncate_str(content, THINKING_QUOTE_LIMIT)
+                .lines()
+                .map(|line| format!("> {}", line).trim_end().to_string())
+                .collect();
+            format!("\n\n> 💭 Thinking\n{}\n\n", quoted.join("\n"))
+        }
+        ThinkingDisplay::Summary => {
+            let first = content.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+            format!(
+                "\n\n💭 {} ({} words)\n\n",
+                truncate_str(first.trim(), 80),
+                content.split_whitespace().count()
+            )
+        }
+    }
+}
+
+/// A tool call made by a sub-agent, shown as one line in its Task's section
+struct ChildTool {
+    id: Option<String>,
+    summary: String,
+    failed: bool,
+}
+
+/// A piece of the response, in stream order
+enum Segment {
+    Text(String),
+    /// Extended thinking, rendered per the chat's /thinking setting
+    Thinking(String),
+    /// A tool invocation, with its outcome once the result arrives.
+    /// For Task tools, `children` holds the calls made by the launched sub-agent.
+    Tool {
+        id: Option<String>,
+        summary: String,
+        result: Option<String>,
+        failed: bool,
+        is_agent: bool,
+        children: Vec<ChildTool>,
+    },
+}
+
+/// Indented section under a Task tool use: a status line for the sub-agent,
+/// then its latest tool calls
+fn render_subagent(children: &[ChildTool], finished: bool, failed: bool) -> String {
+    let status = match (finished, failed) {
+        (false, _) => "◐ running",
+        (true, false) => "☑ done",
+        (true, true) => "✖ failed",
+    };
+    let mut section = format!(
+        "│ 🤖 sub-agent · {} tool{} · {}\n",
+        children.len(),
+        if children.len() == 1 { "" } else { "s" },
+        status
+    );
+    let hidden = children.len().saturating_sub(SUBAGENT_VISIBLE_TOOLS);
+    if hidden > 0 {
+        section.push_str(&format!("│ … {} earlier\n", hidden));
+    }
+    for child in &children[hidden..] {
+        let mark = if child.failed { " ❌" } else { "" };
+        section.push_str(&format!("│ ⚙️ {}{}\n", child.summary, mark));
+    }
+    section
+}
+
+/// Chat rendering of a tool's output or error
+fn format_tool_result(content: &str, is_error: bool) -> String {
+    if is_error {
+        let truncated = truncate_str(content, 500);
+        if truncated.contains('\n') {
+            format!("\n❌\n```\n{}\n```\n", truncated)
+        } else {
+            format!("\n❌ `{}`\n\n", truncated)
+        }
+    } else {
+        let truncated = truncate_str(content, 300);
+        if truncated.contains('\n') {
+            format!("\n```\n{}\n```\n", truncated)
+        } else {
+            format!("\n✅ `{}`\n\n", truncated)
+        }
+    }
+}
+
+/// Accumulates the stream messages of one AI run into the markdown response shown in the chat
+pub(crate) struct ResponseBuilder {
+    working_dir: PathBuf,
+    /// Shown in error replies so the run can be found in the logs
+    run_id: Option<String>,
+    thinking: ThinkingDisplay,
+    segments: Vec<Segment>,
+    /// Todo list and background tasks, shown in their own pinned message
+    checklist: Checklist,
+    checklist_changed: bool,
+    /// TodoWrite tool uses, whose results are not shown
+    todo_ids: Vec<String>,
+    pub session_id: Option<String>,
+    /// Model the CLI reported at startup, shown under the final response
+    pub model: Option<String>,
+    /// MCP servers the CLI started, for /mcp list
+    pub mcp_servers: Vec<McpServerStatus>,
+    /// Files the AI reported writing via Write/Edit tools
+    pub touched: Vec<PathBuf>,
+    pub errored: bool,
+    /// A Done or Error message arrived
+    pub done: bool,
+}
+
+impl ResponseBuilder {
+    pub fn new(working_dir: &str, run_id: Option<String>) -> Self {
+        ResponseBuilder {
+            working_dir: PathBuf::from(working_dir),
+            run_id,
+            thinking: ThinkingDisplay::Off,
+            segments: Vec::new(),
+            checklist: Checklist::default(),
+            checklist_changed: false,
+            todo_ids: Vec::new(),
+            session_id: None,
+            model: None,
+            mcp_servers: Vec::new(),
+            touched: Vec::new(),
+            errored: false,
+            done: false,
+        }
+    }
+
+    /// Show thinking blocks as `display` instead of hiding them
+    pub fn with_thinking(mut self, display: ThinkingDisplay) -> Self {
+        self.thinking = display;
+        self
+    }
+
+    /// The response so far, with each tool's result shown right after its invocation
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(content) => text.push_str(content),
+                Segment::Thinking(content) => {
+                    text.push_str(&render_thinking(content, self.thinking))
+                }
+                Segment::Tool {
+                    summary,
+                    result,
+                    failed,
+                    is_agent,
+                    children,
+                    ..
+                } => {
+                    text.push_str(&format!("\n\n⚙️ {}\n", summary));
+                    if *is_agent || !children.is_empty() {
+                        text.push_str(&render_subagent(children, result.is_some(), *failed));
+                    }
+                    if let Some(result) = result {
+                        text.push_str(result);
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    /// Attach a result to the tool use with `tool_use_id`, or, without an id,
+    /// to the latest tool still waiting for one. Unmatched results stand alone.
+    fn attach_result(&mut self, tool_use_id: Option<String>, is_error: bool, formatted: String) {
+        let target = self
+            .segments
+            .iter_mut()
+            .rev()
+            .find_map(|segment| match segment {
+                Segment::Tool {
+                    id, result, failed, ..
+                } => {
+                    let matches = match &tool_use_id {
+                        Some(wanted) => id.as_deref() == Some(wanted.as_str()),
+                        None => result.is_none(),
+                    };
+                    matches.then_some((result, failed))
+                }
+                Segment::Text(_) | Segment::Thinking(_) => None,
+            });
+        match target {
+            Some((result, failed)) => {
+                result.get_or_insert_with(String::new).push_str(&formatted);
+                *failed |= is_error;
+            }
+            None => self.segments.push(Segment::Text(formatted)),
+        }
+    }
+
+    /// Sub-agent calls of the Task tool use `parent`, if it is in this response
+    fn children_of(&mut self, parent: &Option<String>) -> Option<&mut Vec<ChildTool>> {
+        let parent = parent.as_deref()?;
+        self.segments.iter_mut().find_map(|segment| match segment {
+            Segment::Tool { id, children, .. } if id.as_deref() == Some(parent) => Some(children),
+            _ => None,
+        })
+    }
+
+    pub fn push(&mut self, msg: StreamMessage) {
+        match msg {
+            StreamMessage::Init {
+                session_id,
+                model,
+                mcp_servers,
+            } => {
+                self.session_id = Some(session_id);
+                if model.is_some() {
+                    self.model = model;
+                }
+                if !mcp_servers.is_empty() {
+                    self.mcp_servers = mcp_servers;
+                }
+            }
+            StreamMessage::Text { content, parent } => {
+                // A sub-agent's narration stays out of the response; its Task result sums it up
+                if self.children_of(&parent).is_none() {
+                    self.segments.push(Segment::Text(content));
+                }
+            }
+            StreamMessage::Thinking { content, parent } => {
+                if self.children_of(&parent).is_none() {
+                    self.segments.push(Segment::Thinking(content));
+                }
+            }
+            StreamMessage::ToolUse {
+                id,
+                name,
+                input,
+                parent,
+            } => {
+                let summary = format_tool_input(&name, &input);
+                tracing::debug!(tool = %name, parent = ?parent, "{}", truncate_str(&summary, 80));
+                if let Some(file_path) = claude::tool_file_path(&name, &input) {
+                    self.touched.push(self.working_dir.join(file_path));
+                }
+                if let Some(children) = self.children_of(&parent) {
+                    children.push(ChildTool {
+                        id,
+                        summary,
+                        failed: false,
+                    });
+                    return;
+                }
+                self.segments.push(Segment::Tool {
+                    id,
+                    summary,
+                    result: None,
+                    failed: false,
+                    is_agent: matches!(name.as_str(), "Task" | "Agent"),
+                    children: Vec::new(),
+                });
+            }
+            StreamMessage::ToolResult {
+                tool_use_id,
+                content,
+                is_error,
+                parent,
+            } => {
+                if let Some(children) = self.children_of(&parent) {
+                    if let Some(child) = children
+                        .iter_mut()
+                        .find(|c| c.id.is_some() && c.id == tool_use_id)
+                    {
+                        child.failed |= is_error;
+                        return;
+                    }
+                }
+                if tool_use_id
+                    .as_ref()
+                    .is_some_and(|id| self.todo_ids.contains(id))
+                {
+                    return;
+                }
+                if is_error {
+                    tracing::warn!("Tool error: {}", truncate_str(&content, 80));
+                }
+                // Attached even when empty, so a Task's section shows it finished
+                let formatted = if is_error || !content.is_empty() {
+                    format_tool_result(&content, is_error)
+                } else {
+                    String::new()
+                };
+                self.attach_result(tool_use_id, is_error, formatted);
+            }
+            StreamMessage::Todos { id, items } => {
+                self.todo_ids.extend(id);
+                self.checklist.todos = items;
+                self.checklist_changed = true;
+            }
+            // Answered by the chat handler before it reaches the builder; dropping
+            // it here declines the request
+            StreamMessage::AwaitingUser { tool_name, .. } => {
+                tracing::debug!(tool = %tool_name, "User input request not handled");
+            }
+            StreamMessage::TaskNotification {
+                task_id,
+                status,
+                summary,
+            } => {
+                tracing::debug!(task_id, status, "Background task finished");
+                let summary = if summary.is_empty() { task_id } else { summary };
+                self.checklist.tasks.push((status, summary));
+                self.checklist_changed = true;
+            }
+            StreamMessage::Done { result, session_id } => {
+                let answered = self
+                    .segments
+                    .iter()
+                    .any(|s| !matches!(s, Segment::Thinking(_)));
+                if !result.is_empty() && !answered {
+                    self.segments.push(Segment::Text(result));
+                }
+                if let Some(s) = session_id {
+                    self.session_id = Some(s);
+                }
+                self.done = true;
+            }
+            StreamMessage::Error { message } => {
+                tracing::warn!("AI run failed: {message}");
+                let text = match &self.run_id {
+                    Some(run_id) => format!("Error: {}\n\n(run id: {})", message, run_id),
+                    None => format!("Error: {}", message),
+                };
+                self.segments = vec![Segment::Text(text)];
+                self.errored = true;
+                self.done = true;
+            }
+        }
+    }
+
+    /// Rendered checklist if it changed since the last call
+    pub fn take_checklist_update(&mut self) -> Option<String> {
+        if !std::mem::take(&mut self.checklist_changed) || self.checklist.is_empty() {
+            return None;
+        }
+        Some(self.checklist.render())
+    }
+
+    /// Rendered checklist, if the run produced one
+    pub fn checklist_html(&self) -> Option<String> {
+        (!self.checklist.is_empty()).then(|| self.checklist.render())
+    }
+
+    /// Placeholder text while the run is in progress, with the spinner `indicator` appended
+    pub fn progress_text(&self, indicator: &str, header: Option<&str>) -> String {
+        let text = self.text();
+        let body = if text.is_empty() {
+            indicator.to_string()
+        } else {
+            let normalized = normalize_empty_lines(&text);
+            let truncated = truncate_str(&normalized, TELEGRAM_MSG_LIMIT - 20);
+            format!("{}\n\n{}", truncated, indicator)
+        };
+        match header {
+            Some(header) => format!("{}\n\n{}", header, body),
+            None => body,
+        }
+    }
+
+    /// Final markdown of a finished run, with the model as a footer
+    pub fn final_text(&self, header: Option<&str>) -> String {
+        let mut text = self.text();
+        if text.is_empty() {
+            text.push_str("(No response)");
+        }
+        if let Some(model) = self.model.as_deref().filter(|_| !self.errored) {
+            text.push_str(&format!("\n\n🤖 {}", model));
+        }
+        match header {
+            Some(header) => normalize_empty_lines(&format!("{}\n\n{}", header, text)),
+            None => normalize_empty_lines(&text),
+        }
+    }
+
+    /// Final markdown of a run stopped with /stop: the partial response plus a marker
+    pub fn stopped_text(&self, header: Option<&str>) -> String {
+        let text = self.text();
+        let body = if text.trim().is_empty() {
+            "[Stopped]".to_string()
+        } else {
+            format!("{}\n\n[Stopped]", normalize_empty_lines(&text))
+        };
+        match header {
+            Some(header) => format!("{}\n\n{}", header, body),
+            None => body,
+        }
+    }
+}
+
+/// Render a captured run (see `trace::RunTrace`) offline into the final messages
+/// the bot would send: the final todo checklist if there was one, then either one
+/// edit of the "Processing" placeholder, or the split messages followed by
+/// deleting the placeholder.
+/// Only the built-in redaction patterns are applied, not the owner's /redact list.
+pub fn replay_transcript(content: &str) -> String {
+    let replay = trace::replay(content);
+    let mut builder = ResponseBuilder::new("", None);
+    for msg in replay.messages {
+        builder.push(msg);
+    }
+    let markdown = if replay.cancelled {
+        builder.stopped_text(None)
+    } else {
+        builder.final_text(None)
+    };
+    let html = markdown_to_telegram_html(&redact::redact(&markdown, &[]));
+
+    let mut out = String::new();
+    if let Some(checklist) = builder.checklist_html() {
+        out.push_str(&format!(
+            "--- checklist, pinned then unpinned (HTML) ---\n{}\n",
+            redact::redact(&checklist, &[])
+        ));
+    }
+    if html.len() <= TELEGRAM_MSG_LIMIT {
+        out.push_str(&format!("--- edit placeholder (HTML) ---\n{}\n", html));
+        return out;
+    }
+    let chunks = split_message(&html, true);
+    for (i, chunk) in chunks.iter().enumerate() {
+        out.push_str(&format!(
+            "--- send {}/{} (HTML) ---\n{}\n",
+            i + 1,
+            chunks.len(),
+            chunk
+        ));
+    }
+    out.push_str("--- delete placeholder ---\n");
+    out
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Every captured run in tests/fixtures/streams must render exactly as its
+    /// `.expected` file. Set OPENCLAUDE_UPDATE_FIXTURES=1 to rewrite them after
+    /// an intended rendering change, then review the diff.
+    #[test]
+    fn test_replay_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/streams");
+        let update = std::env::var_os("OPENCLAUDE_UPDATE_FIXTURES").is_some();
+        let mut checked = 0;
+        for entry in std::fs::read_dir(&dir).expect("read fixture dir") {
+            let path = entry.expect("fixture entry").path();
+            if path.extension().is_none_or(|ext| ext != "jsonl") {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path).expect("read fixture");
+            let rendered = replay_transcript(&content);
+            let expected_path = path.with_extension("expected");
+            if update {
+                std::fs::write(&expected_path, &rendered).expect("write expected");
+            } else {
+                let expected = std::fs::read_to_string(&expected_path).expect("read expected");
+                assert_eq!(rendered, expected, "replay of {}", path.display());
+            }
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn test_subagent_events_grouped_under_their_task() {
+        let tool_use =
+            |id: &str, name: &str, input: &str, parent: Option<&str>| StreamMessage::ToolUse {
+                id: Some(id.to_string()),
+                name: name.to_string(),
+                input: input.to_string(),
+                parent: parent.map(String::from),
+            };
+        let mut builder = ResponseBuilder::new("/work", None);
+        builder.push(tool_use("a", "Task", r#"{"description":"one"}"#, None));
+        builder.push(tool_use("b", "Task", r#"{"description":"two"}"#, None));
+        builder.push(tool_use("a1", "Read", r#"{"file_path":"x.rs"}"#, Some("a")));
+        builder.push(tool_use(
+            "b1",
+            "Write",
+            r#"{"file_path":"y.rs"}"#,
+            Some("b"),
+        ));
+        builder.push(StreamMessage::Text {
+            content: "thinking aloud".to_string(),
+            parent: Some("a".to_string()),
+        });
+        builder.push(StreamMessage::ToolResult {
+            tool_use_id: Some("a".to_string()),
+            content: "found it".to_string(),
+            is_error: false,
+            parent: None,
+        });
+
+        let text = builder.text();
+        let one = text.find("Task: one").expect("first task");
+        let two = text.find("Task: two").expect("second task");
+        let read = text.find("│ ⚙️ Read x.rs").expect("child of first task");
+        let write = text.find("│ ⚙️ Write y.rs").expect("child of second task");
+        assert!(one < read && read < two && two < write);
+        assert!(text.contains("│ 🤖 sub-agent · 1 tool · ☑ done"));
+        assert!(text.contains("│ 🤖 sub-agent · 1 tool · ◐ running"));
+        assert!(!text.contains("thinking aloud"));
+        assert_eq!(builder.touched, vec![PathBuf::from("/work/y.rs")]);
+    }
+
+    #[test]
+    fn test_thinking_display_modes() {
+        let render = |display: ThinkingDisplay| {
+            let mut builder = ResponseBuilder::new("/work", None).with_thinking(display);
+            builder.push(StreamMessage::Thinking {
+                content: "Check the parser first.\n\nThen the tests.".to_string(),
+                parent: None,
+            });
+            builder.push(StreamMessage::Text {
+                content: "Done.".to_string(),
+                parent: None,
+            });
+            markdown_to_telegram_html(&builder.final_text(None))
+                .trim_start()
+                .to_string()
+        };
+
+        assert_eq!(render(ThinkingDisplay::Off), "Done.");
+        assert_eq!(
+            render(ThinkingDisplay::On),
+            "<blockquote expandable>💭 Thinking\nCheck the parser first.\n\nThen the tests.</blockquote>\n\nDone."
+        );
+        assert_eq!(
+            render(ThinkingDisplay::Summary),
+            "💭 Check the parser first. (7 words)\n\nDone."
+        );
+    }
+
+    #[test]
+    fn test_replay_splits_long_response() {
+        let line = "word ".repeat(30);
+        let text = vec![line.as_str(); 200].join("\n");
+        let trace = format!(
+            "{}\n{}\n",
+            serde_json::json!({"type": "assistant", "message": {"content": [{"type": "text", "text": text}]}}),
+            serde_json::json!({"type": "result", "is_error": false, "result": ""}),
+        );
+        let rendered = replay_transcript(&trace);
+        assert!(rendered.starts_with("--- send 1/"));
+        assert!(rendered.ends_with("--- delete placeholder ---\n"));
+        assert!(rendered
+            .split("--- ")
+            .all(|part| part.len() <= TELEGRAM_MSG_LIMIT + 40));
+    }
+}
@@ -9,12 +9,34 @@ use crate::session::{ai_sessions_dir, HistoryItem, HistoryType, SessionData};
 #[derive(Clone, Default)]
 pub(crate) struct BotSettings {
     pub(crate) allowed_tools: HashMap<String, Vec<String>>,
+    /// chat_id (string) -> tools for non-owner prompts in a public group chat
+    pub(crate) public_tools: HashMap<String, Vec<String>>,
     /// chat_id (string) -> last working directory path
     pub(crate) last_sessions: HashMap<String, String>,
     /// Telegram user ID of the registered owner (imprinting auth)
     pub(crate) owner_user_id: Option<u64>,
     /// chat_id (string) -> true if group chat is public (non-owner users allowed)
     pub(crate) as_public_for_group_chat: HashMap<String, bool>,
+    /// Owner-added regexes masked in outbound messages, on top of the built-in formats
+    pub(crate) redact_patterns: Vec<String>,
+    /// project path -> destructive shell pattern ids that run without confirmation
+    pub(crate) shell_always_allow: HashMap<String, Vec<String>>,
+    /// chat_id (string) -> "on" or "summary" (/thinking); absent means off
+    pub(crate) thinking_display: HashMap<String, String>,
+    /// chat_id (string) -> model alias or id (/model); absent means the CLI default
+    pub(crate) models: HashMap<String, String>,
+    /// chat_id (string) -> model used when the main one is overloaded (/model fallback)
+    pub(crate) fallback_models: HashMap<String, String>,
+    /// chat_id (string) -> agentic turn limit per prompt (/maxturns)
+    pub(crate) max_turns: HashMap<String, u32>,
+    /// chat_id (string) -> EFFORT_LEVELS name (/effort); absent means the CLI default
+    pub(crate) efforts: HashMap<String, String>,
+    /// chat_id (string) -> extra system prompt instructions (/systemprompt)
+    pub(crate) system_prompts: HashMap<String, String>,
+    /// project path -> MCP server config files (/mcp)
+    pub(crate) mcp_configs: HashMap<String, Vec<String>>,
+    /// project path -> extra directories the AI may use (/adddir)
+    pub(crate) add_dirs: HashMap<String, Vec<String>>,
 }
 
 /// Per-chat session state
@@ -25,8 +47,12 @@ pub(crate) struct ChatSession {
     /// File upload records not yet sent to Claude Code AI.
     /// Drained and prepended to the next user prompt so Claude Code knows about uploaded files.
     pub(crate) pending_uploads: Vec<String>,
+    /// Uploaded images and PDFs as content blocks, sent with the next prompt
+    pub(crate) pending_attachments: Vec<serde_json::Value>,
     /// Set to true by /clear to prevent a racing polling loop from re-populating history.
     pub(crate) cleared: bool,
+    /// Extended thinking budget set by /thinking budget, used by the next prompt only
+    pub(crate) thinking_budget: Option<u32>,
 }
 
 /// Compute a short hash key from the bot token (first 16 chars of SHA-256 hex)
@@ -86,6 +112,24 @@ fn parse_bot_settings_entry(entry: &serde_json::Value) -> BotSettings {
         _ => HashMap::new(),
     };
 
+    let public_tools: HashMap<String, Vec<String>> = entry
+        .get("public_tools")
+        .and_then(|v| v.as_object())
+        .map(|obj| {
+            obj.iter()
+                .filter_map(|(k, v)| {
+                    v.as_array().map(|arr| {
+                        let tools: Vec<String> = arr
+                            .iter()
+                            .filter_map(|t| t.as_str().map(String::from))
+                            .collect();
+                        (k.clone(), tools)
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
     let as_public_for_group_chat: HashMap<String, bool> = entry
         .get("as_public_for_group_chat")
         .and_then(|v| v.as_object())
@@ -96,11 +140,110 @@ fn parse_bot_settings_entry(entry: &serde_json::Value) -> BotSettings {
         })
         .unwrap_or_default();
 
+    let redact_patterns: Vec<String> = entry
+        .get("redact_patterns")
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let shell_always_allow: HashMap<String, Vec<String>> = entry
+        .get("shell_always_allow")
+        .and_then(|v| v.as_object())
+        .map(|obj| {
+            obj.iter()
+                .filter_map(|(k, v)| {
+                    v.as_array().map(|arr| {
+                        let ids: Vec<String> = arr
+                            .iter()
+                            .filter_map(|t| t.as_str().map(String::from))
+                            .collect();
+                        (k.clone(), ids)
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let thinking_display: HashMap<String, String> = entry
+        .get("thinking_display")
+        .and_then(|v| v.as_object())
+        .map(|obj| {
+            obj.iter()
+                .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let string_map = |key: &str| -> HashMap<String, String> {
+        entry
+            .get(key)
+            .and_then(|v| v.as_object())
+            .map(|obj| {
+                obj.iter()
+                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let models = string_map("models");
+    let fallback_models = string_map("fallback_models");
+    let efforts = string_map("efforts");
+    let system_prompts = string_map("system_prompts");
+    let list_map = |key: &str| -> HashMap<String, Vec<String>> {
+        entry
+            .get(key)
+            .and_then(|v| v.as_object())
+            .map(|obj| {
+                obj.iter()
+                    .filter_map(|(k, v)| {
+                        v.as_array().map(|arr| {
+                            let items: Vec<String> = arr
+                                .iter()
+                                .filter_map(|t| t.as_str().map(String::from))
+                                .collect();
+                            (k.clone(), items)
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mcp_configs = list_map("mcp_configs");
+    let add_dirs = list_map("add_dirs");
+    let max_turns: HashMap<String, u32> = entry
+        .get("max_turns")
+        .and_then(|v| v.as_object())
+        .map(|obj| {
+            obj.iter()
+                .filter_map(|(k, v)| {
+                    v.as_u64()
+                        .and_then(|n| u32::try_from(n).ok())
+                        .map(|n| (k.clone(), n))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
     BotSettings {
         allowed_tools,
+        public_tools,
         last_sessions,
         owner_user_id,
         as_public_for_group_chat,
+        redact_patterns,
+        shell_always_allow,
+        thinking_display,
+        models,
+        fallback_models,
+        max_turns,
+        efforts,
+        system_prompts,
+        mcp_configs,
+        add_dirs,
     }
 }
 
@@ -137,8 +280,19 @@ fn write_bot_settings_file(path: &std::path::Path, token: &str, settings: &BotSe
     let mut entry = serde_json::json!({
         "token": token,
         "allowed_tools": settings.allowed_tools,
+        "public_tools": settings.public_tools,
         "last_sessions": settings.last_sessions,
         "as_public_for_group_chat": settings.as_public_for_group_chat,
+        "redact_patterns": settings.redact_patterns,
+        "shell_always_allow": settings.shell_always_allow,
+        "thinking_display": settings.thinking_display,
+        "models": settings.models,
+        "fallback_models": settings.fallback_models,
+        "max_turns": settings.max_turns,
+        "efforts": settings.efforts,
+        "system_prompts": settings.system_prompts,
+        "mcp_configs": settings.mcp_configs,
+        "add_dirs": settings.add_dirs,
     });
 
     if let Some(owner_id) = settings.owner_user_id {
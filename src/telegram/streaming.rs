@@ -1,6 +1,8 @@
 use teloxide::prelude::*;
 use teloxide::types::ParseMode;
 
+use crate::redact;
+
 use super::bot::{shared_rate_limit_wait, SharedState, TELEGRAM_MSG_LIMIT};
 
 /// Find the largest byte index <= `index` that is a valid UTF-8 char boundary
@@ -62,48 +64,90 @@ pub(crate) fn html_escape(s: &str) -> String {
         .replace('>', "&gt;")
 }
 
-/// Send a message that may exceed Telegram's 4096 character limit
-/// by splitting it into multiple messages, handling UTF-8 boundaries
-/// and unclosed HTML tags (e.g. <pre>) across split points
-pub(crate) async fn send_long_message(
-    bot: &Bot,
-    chat_id: ChatId,
-    text: &str,
-    parse_mode: Option<ParseMode>,
-    state: &SharedState,
-) -> ResponseResult<()> {
-    if text.len() <= TELEGRAM_MSG_LIMIT {
-        shared_rate_limit_wait(state, chat_id).await;
-        let mut req = bot.send_message(chat_id, text);
-        if let Some(mode) = parse_mode {
-            req = req.parse_mode(mode);
+/// HTML-escape `s`, cut to at most `budget` bytes of escaped text (ending in …)
+pub(crate) fn escape_within(s: &str, budget: usize) -> String {
+    let escaped = html_escape(s);
+    if escaped.len() <= budget {
+        return escaped;
+    }
+    let budget = budget.saturating_sub('…'.len_utf8());
+    let mut out = String::new();
+    for c in s.chars() {
+        let piece = html_escape(c.encode_utf8(&mut [0; 4]));
+        if out.len() + piece.len() > budget {
+            break;
         }
-        req.await?;
-        return Ok(());
+        out.push_str(&piece);
+    }
+    out.push('…');
+    out
+}
+
+/// Mask secrets in text about to be sent to Telegram: built-in token formats
+/// plus the owner's /redact patterns
+pub(crate) async fn redact_outbound(state: &SharedState, text: &str) -> String {
+    let extra = state.lock().await.redact_regexes.clone();
+    redact::redact(text, &extra)
+}
+
+/// Block-level HTML elements that `split_message` closes and reopens when a
+/// split point falls inside them
+const SPLIT_WRAPPERS: &[(&str, &str)] = &[
+    ("<pre>", "</pre>"),
+    ("<blockquote expandable>", "</blockquote>"),
+];
+
+/// The wrapper left open at the end of `chunk`, given the one open at its start
+fn open_wrapper_after(
+    chunk: &str,
+    open: Option<(&'static str, &'static str)>,
+) -> Option<(&'static str, &'static str)> {
+    let mut last: Option<(usize, Option<(&'static str, &'static str)>)> = None;
+    for &(open_tag, close_tag) in SPLIT_WRAPPERS {
+        if let Some(o) = chunk.rfind(open_tag) {
+            if last.is_none_or(|(pos, _)| o > pos) {
+                last = Some((o, Some((open_tag, close_tag))));
+            }
+        }
+        if let Some(c) = chunk.rfind(close_tag) {
+            if last.is_none_or(|(pos, _)| c > pos) {
+                last = Some((c, None));
+            }
+        }
+    }
+    match last {
+        Some((_, wrapper)) => wrapper,
+        None => open,
+    }
+}
+
+/// Split text into chunks within Telegram's 4096 character limit,
+/// handling UTF-8 boundaries and, for HTML, unclosed <pre> and blockquote tags
+/// across split points
+pub(crate) fn split_message(text: &str, is_html: bool) -> Vec<String> {
+    if text.len() <= TELEGRAM_MSG_LIMIT {
+        return vec![text.to_string()];
     }
 
-    let is_html = parse_mode.is_some();
+    let mut chunks = Vec::new();
     let mut remaining = text;
-    let mut in_pre = false;
+    let mut open: Option<(&'static str, &'static str)> = None;
 
     while !remaining.is_empty() {
-        // Reserve space for tags we may need to add (<pre> + </pre> = 11 bytes)
-        let tag_overhead = if is_html && in_pre { 11 } else { 0 };
+        // Reserve space for the tags we may need to add
+        let tag_overhead = match open {
+            Some((open_tag, close_tag)) if is_html => open_tag.len() + close_tag.len(),
+            _ => 0,
+        };
         let effective_limit = TELEGRAM_MSG_LIMIT.saturating_sub(tag_overhead);
 
         if remaining.len() <= effective_limit {
             let mut chunk = String::new();
-            if is_html && in_pre {
-                chunk.push_str("<pre>");
+            if let Some((open_tag, _)) = open.filter(|_| is_html) {
+                chunk.push_str(open_tag);
             }
             chunk.push_str(remaining);
-
-            shared_rate_limit_wait(state, chat_id).await;
-            let mut req = bot.send_message(chat_id, &chunk);
-            if let Some(mode) = parse_mode {
-                req = req.parse_mode(mode);
-            }
-            req.await?;
+            chunks.push(chunk);
             break;
         }
 
@@ -114,35 +158,45 @@ pub(crate) async fn send_long_message(
         let (raw_chunk, rest) = remaining.split_at(split_at);
 
         let mut chunk = String::new();
-        if is_html && in_pre {
-            chunk.push_str("<pre>");
+        if let Some((open_tag, _)) = open.filter(|_| is_html) {
+            chunk.push_str(open_tag);
         }
         chunk.push_str(raw_chunk);
 
-        // Track unclosed <pre> tags to close/reopen across chunks
+        // Track unclosed wrappers to close/reopen across chunks
         if is_html {
-            let last_open = raw_chunk.rfind("<pre>");
-            let last_close = raw_chunk.rfind("</pre>");
-            in_pre = match (last_open, last_close) {
-                (Some(o), Some(c)) => o > c,
-                (Some(_), None) => true,
-                (None, Some(_)) => false,
-                (None, None) => in_pre,
-            };
-            if in_pre {
-                chunk.push_str("</pre>");
+            open = open_wrapper_after(raw_chunk, open);
+            if let Some((_, close_tag)) = open {
+                chunk.push_str(close_tag);
             }
         }
+        chunks.push(chunk);
+
+        // Skip the newline character at the split point
+        remaining = rest.strip_prefix('\n').unwrap_or(rest);
+    }
 
+    chunks
+}
+
+/// Send a message that may exceed Telegram's 4096 character limit
+/// by splitting it into multiple messages (see `split_message`)
+pub(crate) async fn send_long_message(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    parse_mode: Option<ParseMode>,
+    state: &SharedState,
+) -> ResponseResult<()> {
+    let redacted = redact_outbound(state, text).await;
+
+    for chunk in split_message(&redacted, parse_mode.is_some()) {
         shared_rate_limit_wait(state, chat_id).await;
         let mut req = bot.send_message(chat_id, &chunk);
         if let Some(mode) = parse_mode {
             req = req.parse_mode(mode);
         }
         req.await?;
-
-        // Skip the newline character at the split point
-        remaining = rest.strip_prefix('\n').unwrap_or(rest);
     }
 
     Ok(())
@@ -177,6 +231,21 @@ pub(crate) fn markdown_to_telegram_html(md: &str) -> String {
             continue;
         }
 
+        // Block quote: consecutive "> " lines, collapsed by Telegram when long
+        if strip_quote(trimmed).is_some() {
+            let mut quote_lines = Vec::new();
+            while let Some(rest) = lines.get(i).and_then(|l| strip_quote(l.trim_start())) {
+                quote_lines.push(convert_inline(&html_escape(rest)));
+                i += 1;
+            }
+            result.push_str(&format!(
+                "<blockquote expandable>{}</blockquote>",
+                quote_lines.join("\n")
+            ));
+            result.push('\n');
+            continue;
+        }
+
         // Heading (# ~ ######)
         if let Some(rest) = strip_heading(trimmed) {
             result.push_str(&format!("<b>{}</b>", convert_inline(&html_escape(rest))));
@@ -210,6 +279,12 @@ pub(crate) fn markdown_to_telegram_html(md: &str) -> String {
     result.trim_end().to_string()
 }
 
+/// Strip markdown block quote prefix (">" or "> "), return remaining text
+fn strip_quote(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix('>')?;
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
 /// Strip markdown heading prefix (# ~ ######), return remaining text
 fn strip_heading(line: &str) -> Option<&str> {
     let trimmed = line.trim_start_matches('#');
@@ -1,7 +1,7 @@
 use teloxide::prelude::*;
 use teloxide::types::ParseMode;
 
-use crate::claude::DEFAULT_ALLOWED_TOOLS;
+use crate::claude::{DEFAULT_ALLOWED_TOOLS, DEFAULT_PUBLIC_TOOLS};
 
 use super::bot::{shared_rate_limit_wait, SharedState};
 use super::storage::{save_bot_settings, BotSettings};
@@ -40,11 +40,15 @@ pub(crate) const ALL_TOOLS: &[(&str, &str, bool)] = &[
     ("TaskList", "List all tasks and their status", false),
     (
         "AskUserQuestion",
-        "Ask the user a question (interactive)",
+        "Ask the user a question with answer buttons",
+        false,
+    ),
+    ("EnterPlanMode", "Enter planning mode", false),
+    (
+        "ExitPlanMode",
+        "Present a plan for approval (Approve/Revise buttons)",
         false,
     ),
-    ("EnterPlanMode", "Enter planning mode (interactive)", false),
-    ("ExitPlanMode", "Exit planning mode (interactive)", false),
 ];
 
 /// Get allowed tools for a specific chat_id.
@@ -63,6 +67,17 @@ pub(crate) fn get_allowed_tools(settings: &BotSettings, chat_id: ChatId) -> Vec<
         })
 }
 
+/// Get the tools for non-owner prompts in a public group chat.
+/// Returns the chat-specific list if configured, otherwise DEFAULT_PUBLIC_TOOLS (read-only).
+pub(crate) fn get_public_tools(settings: &BotSettings, chat_id: ChatId) -> Vec<String> {
+    let key = chat_id.0.to_string();
+    settings
+        .public_tools
+        .get(&key)
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_PUBLIC_TOOLS.iter().map(|s| s.to_string()).collect())
+}
+
 /// Normalize tool name: first letter uppercase, rest lowercase
 fn normalize_tool_name(name: &str) -> String {
     let lower = name.to_lowercase();
@@ -270,3 +285,127 @@ pub(crate) async fn handle_allowed_command(
 
     Ok(())
 }
+
+/// Handle /publictools command - show or edit the tools for non-owner prompts
+/// Usage: /publictools            (show)
+///        /publictools +toolname  (add)
+///        /publictools -toolname  (remove)
+pub(crate) async fn handle_publictools_command(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    state: &SharedState,
+    token: &str,
+) -> ResponseResult<()> {
+    let arg = text.strip_prefix("/publictools").unwrap_or("").trim();
+
+    if arg.is_empty() {
+        let tools = {
+            let data = state.lock().await;
+            get_public_tools(&data.settings, chat_id)
+        };
+        let mut msg = String::from(
+            "<b>Public Tools</b>\nUsed for prompts from non-owner members when /public is on.\n\n",
+        );
+        for tool in &tools {
+            let (desc, destructive) = tool_info(tool);
+            let badge = if destructive { " !!!" } else { "" };
+            msg.push_str(&format!(
+                "<code>{}</code>{} — {}\n",
+                html_escape(tool),
+                badge,
+                html_escape(desc)
+            ));
+        }
+        if tools.is_empty() {
+            msg.push_str("(none)\n");
+        }
+        msg.push_str(&format!(
+            "\n{} = destructive\nTotal: {}\n\n/publictools +toolname — Add a tool\n/publictools -toolname — Remove a tool",
+            risk_badge(true),
+            tools.len()
+        ));
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(chat_id, &msg)
+            .parse_mode(ParseMode::Html)
+            .await?;
+        return Ok(());
+    }
+
+    let (op, raw_name) = if let Some(name) = arg.strip_prefix('+') {
+        ('+', name.trim())
+    } else if let Some(name) = arg.strip_prefix('-') {
+        ('-', name.trim())
+    } else {
+        ('?', "")
+    };
+    if raw_name.is_empty() {
+        shared_rate_limit_wait(state, chat_id).await;
+        bot.send_message(
+            chat_id,
+            "Use +toolname to add or -toolname to remove.\nExample: /publictools +WebSearch",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let tool_name = normalize_tool_name(raw_name);
+
+    let response_msg = {
+        let mut data = state.lock().await;
+        let tools = data
+            .settings
+            .public_tools
+            .entry(chat_id.0.to_string())
+            .or_insert_with(|| DEFAULT_PUBLIC_TOOLS.iter().map(|s| s.to_string()).collect());
+        let changed = if op == '+' {
+            if tools.iter().any(|t| t == &tool_name) {
+                false
+            } else {
+                tools.push(tool_name.clone());
+                true
+            }
+        } else {
+            let before_len = tools.len();
+            tools.retain(|t| t != &tool_name);
+            tools.len() < before_len
+        };
+        if changed {
+            save_bot_settings(token, &data.settings);
+        }
+        match (op, changed) {
+            ('+', true) => {
+                let (_, destructive) = tool_info(&tool_name);
+                let warning = if destructive {
+                    "\n⚠️ Non-owner members can now use a destructive tool."
+                } else {
+                    ""
+                };
+                format!(
+                    "✅ Added <code>{}</code> for public users{}",
+                    html_escape(&tool_name),
+                    warning
+                )
+            }
+            ('+', false) => format!(
+                "<code>{}</code> is already in the list.",
+                html_escape(&tool_name)
+            ),
+            (_, true) => format!(
+                "❌ Removed <code>{}</code> for public users",
+                html_escape(&tool_name)
+            ),
+            (_, false) => format!(
+                "<code>{}</code> is not in the list.",
+                html_escape(&tool_name)
+            ),
+        }
+    };
+
+    shared_rate_limit_wait(state, chat_id).await;
+    bot.send_message(chat_id, &response_msg)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
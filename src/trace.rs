@@ -0,0 +1,269 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use serde_json::json;
+
+use crate::claude::{self, StreamDecoder, StreamMessage};
+
+/// Captured runs kept per chat; the oldest are deleted when a new run starts
+const KEEP_TRACES: usize = 20;
+
+/// `type` prefix of the records this module adds around the raw stream lines.
+/// The stream parser ignores unknown types, so a trace can be fed back as-is.
+pub const META_TYPE_PREFIX: &str = "openclaude_";
+
+/// Raw capture of one AI run: the CLI argv, every stdout line exactly as read,
+/// then stderr and the exit status, as one JSONL file.
+pub struct RunTrace {
+    path: PathBuf,
+    file: File,
+}
+
+/// Trace directory of a chat: ~/<app_dir>/traces/<chat_id>
+fn chat_trace_dir(chat_id: i64) -> Option<PathBuf> {
+    dirs::home_dir().map(|h| {
+        h.join(crate::app::dir_name())
+            .join("traces")
+            .join(chat_id.to_string())
+    })
+}
+
+/// Trace files in `dir`, oldest first (names start with a sortable timestamp)
+fn trace_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|ext| ext == "jsonl"))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+impl RunTrace {
+    /// Start capturing a run for `chat_id`. Returns None (after logging why)
+    /// if the trace file cannot be created; the run goes ahead untraced.
+    pub fn start(chat_id: i64, run_id: &str) -> Option<Self> {
+        let result = match chat_trace_dir(chat_id) {
+            Some(dir) => Self::start_in(&dir, run_id, KEEP_TRACES),
+            None => Err("no home directory".to_string()),
+        };
+        result
+            .map_err(|e| tracing::warn!("run trace disabled: {e}"))
+            .ok()
+    }
+
+    /// Create a new trace file in `dir`, first pruning so at most `keep` remain
+    fn start_in(dir: &Path, run_id: &str, keep: usize) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let existing = trace_files(dir);
+        let excess = (existing.len() + 1).saturating_sub(keep);
+        for old in existing.iter().take(excess) {
+            let _ = fs::remove_file(old);
+        }
+
+        let name = format!(
+            "{}-{}.jsonl",
+            chrono::Local::now().format("%Y%m%d-%H%M%S%.3f"),
+            run_id
+        );
+        let path = dir.join(name);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(RunTrace { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.file, "{}", line) {
+            tracing::debug!("run trace write failed ({}): {e}", self.path.display());
+        }
+    }
+
+    fn write_meta(&mut self, kind: &str, mut record: serde_json::Value) {
+        record["type"] = json!(format!("{}{}", META_TYPE_PREFIX, kind));
+        self.write_line(&record.to_string());
+    }
+
+    /// Record one CLI invocation (written again if a stale session is retried)
+    pub fn argv(&mut self, program: &str, args: &[String], working_dir: &str) {
+        self.write_meta(
+            "argv",
+            json!({
+                "time": chrono::Local::now().to_rfc3339(),
+                "program": program,
+                "args": args,
+                "cwd": working_dir,
+            }),
+        );
+    }
+
+    /// Record one stdout line verbatim, including lines that are not valid JSON
+    pub fn stdout_line(&mut self, line: &str) {
+        self.write_line(line);
+    }
+
+    /// Record how the process ended and everything it wrote to stderr
+    pub fn exit(&mut self, status: &ExitStatus, stderr: &str) {
+        if !stderr.is_empty() {
+            self.write_meta("stderr", json!({ "text": stderr }));
+        }
+        self.write_meta(
+            "exit",
+            json!({ "code": status.code(), "signal": status.signal() }),
+        );
+    }
+
+    /// Record an answer written to the CLI's stdin for one of its permission requests
+    pub fn control_response(&mut self, response: &serde_json::Value) {
+        self.write_meta("stdin", json!({ "line": response }));
+    }
+
+    /// Record that the run was killed by /stop
+    pub fn cancelled(&mut self) {
+        self.write_meta("exit", json!({ "cancelled": true }));
+    }
+}
+
+/// Path of the newest trace captured for `chat_id`
+pub fn latest(chat_id: i64) -> Option<PathBuf> {
+    chat_trace_dir(chat_id).and_then(|dir| trace_files(&dir).pop())
+}
+
+/// A captured run decoded back into the messages the chat handler received
+pub struct Replay {
+    pub messages: Vec<StreamMessage>,
+    /// The run was stopped with /stop
+    pub cancelled: bool,
+}
+
+/// Feed a captured trace back through the stream decoder, yielding the
+/// StreamMessages a live run would have delivered to the chat.
+/// When a stale session was retried (a second argv record), the failed first
+/// invocation's exit is dropped, as the live run does not report it.
+pub fn replay(content: &str) -> Replay {
+    let mut messages = Vec::new();
+    let mut decoder = StreamDecoder::default();
+    let mut stderr = String::new();
+    let mut exit_error: Option<String> = None;
+    let mut cancelled = false;
+
+    for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        let meta = json
+            .get("type")
+            .and_then(|v| v.as_str())
+            .and_then(|t| t.strip_prefix(META_TYPE_PREFIX));
+        match meta {
+            Some("argv") => {
+                decoder = StreamDecoder::default();
+                stderr.clear();
+                exit_error = None;
+            }
+            Some("stderr") => {
+                stderr = json
+                    .get("text")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
+            }
+            Some("exit") => {
+                if json.get("cancelled").and_then(|v| v.as_bool()) == Some(true) {
+                    cancelled = true;
+                    continue;
+                }
+                let code = json.get("code").and_then(|v| v.as_i64()).map(|c| c as i32);
+                if code != Some(0) {
+                    exit_error = Some(claude::exit_error_message(code, &stderr));
+                }
+            }
+            Some(_) => {}
+            None => messages.extend(decoder.decode(&json)),
+        }
+    }
+
+    if !cancelled {
+        if let Some(message) = exit_error {
+            messages.push(StreamMessage::Error { message });
+        }
+        messages.extend(decoder.finish());
+    }
+    Replay {
+        messages,
+        cancelled,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("openclaude-trace-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_trace_records_raw_lines_and_meta() {
+        let dir = temp_dir("record");
+        let mut trace = RunTrace::start_in(&dir, "abcd1234", KEEP_TRACES).expect("start");
+        trace.argv("claude", &["-p".to_string()], "/work");
+        trace.stdout_line(r#"{"type":"system","subtype":"init","session_id":"s1"}"#);
+        trace.stdout_line("not json");
+        let status = std::process::Command::new("false")
+            .status()
+            .expect("run false");
+        trace.exit(&status, "boom\n");
+        let content = fs::read_to_string(trace.path()).expect("read trace");
+        let _ = fs::remove_dir_all(&dir);
+
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].contains(r#""type":"openclaude_argv""#));
+        assert_eq!(
+            lines[1],
+            r#"{"type":"system","subtype":"init","session_id":"s1"}"#
+        );
+        assert_eq!(lines[2], "not json");
+        assert!(lines[3].contains(r#""text":"boom\n""#));
+        assert!(lines[4].contains(r#""code":1"#));
+    }
+
+    #[test]
+    fn test_retention_keeps_newest() {
+        let dir = temp_dir("retention");
+        for i in 0..5 {
+            let mut trace = RunTrace::start_in(&dir, &format!("run{i}"), 3).expect("start");
+            trace.stdout_line("{}");
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let files = trace_files(&dir);
+        let _ = fs::remove_dir_all(&dir);
+
+        let names: Vec<String> = files
+            .iter()
+            .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .collect();
+        assert_eq!(names.len(), 3);
+        assert!(names[0].ends_with("-run2.jsonl"));
+        assert!(names[2].ends_with("-run4.jsonl"));
+    }
+}